@@ -21,6 +21,10 @@ cargo test --release --all
 if [ "$DO_FEATURE_MATRIX" = true ]; then
     cargo build --all
     cargo test --all
+    # sapio-base without the schemars JsonSchema derives
+    cargo test -p sapio-base --no-default-features
+    # Objects serialized with their compile introspection
+    cargo test -p sapio --features serde-introspection
 fi
 
 # Docs
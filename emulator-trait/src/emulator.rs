@@ -18,6 +18,8 @@ pub enum EmulatorError {
     NetworkIssue(std::io::Error),
     /// Error was caused by BIP32
     BIP32Error(bitcoin::util::bip32::Error),
+    /// Emulation was needed but no emulator is configured
+    NoEmulator,
 }
 impl fmt::Display for EmulatorError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -68,3 +70,19 @@ impl CTVEmulator for CTVAvailable {
         Ok(b)
     }
 }
+
+/// a type tag that can be tossed inside an Arc when neither CTV nor an
+/// emulator is available, so that any contract relying on CTV fails to
+/// compile with [`EmulatorError::NoEmulator`]
+pub struct CTVUnavailable;
+impl CTVEmulator for CTVUnavailable {
+    fn get_signer_for(&self, _h: sha256::Hash) -> Result<Clause, EmulatorError> {
+        Err(EmulatorError::NoEmulator)
+    }
+    fn sign(
+        &self,
+        b: PartiallySignedTransaction,
+    ) -> Result<PartiallySignedTransaction, EmulatorError> {
+        Ok(b)
+    }
+}
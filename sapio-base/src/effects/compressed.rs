@@ -0,0 +1,163 @@
+// Copyright Judica, Inc 2022
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+//  License, v. 2.0. If a copy of the MPL was not distributed with this
+//  file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! A compact serialization of an effect database with interned paths
+use super::{EditableMapEffectDB, EffectDBError, EffectPath, MapEffectDB, PathFragment};
+use crate::reverse_path::ReversePath;
+use crate::serialization_helpers::SArc;
+#[cfg(feature = "schemars")]
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+/// # Compressed Effects
+/// A [`MapEffectDB`] with every path replaced by an integer id, see
+/// [`MapEffectDB::compress`]. Paths are stored once, as a table sharing
+/// common prefixes, rather than as a full string per key.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "schemars", derive(JsonSchema))]
+pub struct CompressedEffectDB {
+    /// # Paths
+    /// Entry `i` defines the path with id `i + 1` as the path with the given
+    /// parent id extended by one fragment. Id 0 is the empty path, and a
+    /// parent always comes before its children.
+    paths: Vec<(usize, PathFragment)>,
+    /// # Effects
+    /// The effects at each path id
+    #[serde(skip_serializing_if = "BTreeMap::is_empty", default)]
+    effects: BTreeMap<usize, BTreeMap<SArc<String>, serde_json::Value>>,
+    /// # Provenance
+    /// The provenance tags at each path id
+    #[serde(skip_serializing_if = "BTreeMap::is_empty", default)]
+    provenance: BTreeMap<usize, BTreeMap<SArc<String>, String>>,
+}
+
+/// assigns ids to paths, reusing the id of any prefix seen before
+#[derive(Default)]
+struct Interner {
+    paths: Vec<(usize, PathFragment)>,
+    ids: BTreeMap<(usize, PathFragment), usize>,
+}
+
+impl Interner {
+    fn intern(&mut self, path: &EffectPath) -> usize {
+        let Interner { paths, ids } = self;
+        Vec::from(path.clone()).into_iter().fold(0, |parent, f| {
+            let next = paths.len() + 1;
+            *ids.entry((parent, f.clone())).or_insert_with(|| {
+                paths.push((parent, f));
+                next
+            })
+        })
+    }
+}
+
+impl MapEffectDB {
+    /// Convert to the smaller [`CompressedEffectDB`] form, e.g. before
+    /// serializing a large contract's effects. Undo with
+    /// [`CompressedEffectDB::decompress`].
+    pub fn compress(&self) -> CompressedEffectDB {
+        let mut interner = Interner::default();
+        let effects = self
+            .effects
+            .iter()
+            .map(|(p, m)| (interner.intern(&p.0), m.clone()))
+            .collect();
+        let provenance = self
+            .provenance
+            .iter()
+            .map(|(p, m)| (interner.intern(&p.0), m.clone()))
+            .collect();
+        CompressedEffectDB {
+            paths: interner.paths,
+            effects,
+            provenance,
+        }
+    }
+}
+
+impl CompressedEffectDB {
+    /// Rebuild the [`MapEffectDB`] this was compressed from. Errors if the
+    /// path table is malformed, i.e. refers to an id it has not defined yet.
+    pub fn decompress(self) -> Result<MapEffectDB, EffectDBError> {
+        // index 0 is the empty path
+        let mut paths: Vec<Option<Arc<EffectPath>>> = vec![None];
+        for (parent, f) in self.paths {
+            let past = paths
+                .get(parent)
+                .ok_or(EffectDBError::InvalidPathId(parent))?
+                .clone();
+            paths.push(Some(ReversePath::push(past, f)));
+        }
+        let path = |id: usize| -> Result<SArc<EffectPath>, EffectDBError> {
+            let p = paths.get(id).ok_or(EffectDBError::InvalidPathId(id))?;
+            Ok(SArc(
+                p.clone().unwrap_or_else(|| Arc::new(EffectPath::empty())),
+            ))
+        };
+        let effects = self
+            .effects
+            .into_iter()
+            .map(|(id, m)| Ok((path(id)?, m)))
+            .collect::<Result<_, EffectDBError>>()?;
+        let provenance = self
+            .provenance
+            .into_iter()
+            .map(|(id, m)| Ok((path(id)?, m)))
+            .collect::<Result<_, EffectDBError>>()?;
+        Ok(EditableMapEffectDB {
+            effects,
+            empty: Default::default(),
+            provenance,
+        }
+        .into())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn test_compress_round_trip() {
+        let mut effects = BTreeMap::new();
+        for i in 0..50u64 {
+            let path = EffectPath::try_from(format!("@root/@action/pay/#{}/@effects", i)).unwrap();
+            let m = [(SArc(Arc::new("amount".to_string())), serde_json::json!(i))].into();
+            effects.insert(SArc(Arc::new(path)), m);
+        }
+        let db: MapEffectDB = EditableMapEffectDB {
+            effects,
+            empty: Default::default(),
+            provenance: Default::default(),
+        }
+        .into();
+        let db = db.with_provenance("user");
+        let compressed = db.compress();
+        // @root, @action and pay are stored once for all 50 paths
+        assert_eq!(compressed.paths.len(), 3 + 2 * 50);
+        let json = serde_json::to_string(&compressed).unwrap();
+        assert!(json.len() < serde_json::to_string(&db).unwrap().len());
+        let back = serde_json::from_str::<CompressedEffectDB>(&json)
+            .unwrap()
+            .decompress()
+            .unwrap();
+        assert_eq!(
+            serde_json::to_value(&back).unwrap(),
+            serde_json::to_value(&db).unwrap()
+        );
+        let bad = CompressedEffectDB {
+            paths: vec![(7, PathFragment::Root)],
+            ..Default::default()
+        };
+        assert!(matches!(
+            bad.decompress(),
+            Err(EffectDBError::InvalidPathId(7))
+        ));
+    }
+}
@@ -8,13 +8,22 @@
 
 use crate::reverse_path::ReversePath;
 use crate::serialization_helpers::SArc;
+#[cfg(feature = "schemars")]
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
 
 use std::sync::Arc;
+pub mod compressed;
+pub use compressed::*;
+pub mod path_alias;
+pub use path_alias::*;
 pub mod path_fragment;
 pub use path_fragment::*;
+pub mod path_parser;
+pub use path_parser::*;
+pub mod path_tree;
+pub use path_tree::*;
 pub mod reverse_path;
 pub use reverse_path::*;
 
@@ -26,6 +35,17 @@ pub type EffectPath = ReversePath<PathFragment>;
 pub enum EffectDBError {
     /// Error was from Deserialization
     SerializationError(serde_json::Error),
+    /// There is nothing at the effect's path that accepts effects
+    UnknownPath,
+    /// An effect's value is not of the type accepted at its path
+    IncompatibleType {
+        /// the name of the effect
+        name: String,
+        /// every way the value violates the schema accepted at the path
+        errors: Vec<String>,
+    },
+    /// A [`CompressedEffectDB`] refers to a path id it does not define
+    InvalidPathId(usize),
 }
 
 impl From<serde_json::Error> for EffectDBError {
@@ -44,7 +64,8 @@ pub trait EffectDB {
 /// #  Effects
 /// Map of all effects to process during compilation.  Each Key represents a
 /// path, each sub-key represents the sub-path name and value.
-#[derive(Clone, Default, Serialize, Deserialize, JsonSchema, Debug)]
+#[derive(Clone, Default, Serialize, Deserialize, Debug)]
+#[cfg_attr(feature = "schemars", derive(JsonSchema))]
 pub struct MapEffectDB {
     /// # The set of all effects
     /// List of effects to include while compiling.
@@ -52,6 +73,11 @@ pub struct MapEffectDB {
     effects: BTreeMap<SArc<EffectPath>, BTreeMap<SArc<String>, serde_json::Value>>,
     #[serde(skip, default)]
     empty: BTreeMap<SArc<String>, serde_json::Value>,
+    /// # Provenance
+    /// Optional tag recording where each effect came from (e.g. defaults,
+    /// user overrides, an imported subcontract).
+    #[serde(skip_serializing_if = "BTreeMap::is_empty", default)]
+    provenance: BTreeMap<SArc<EffectPath>, BTreeMap<SArc<String>, String>>,
 }
 
 /// # Editable Effects
@@ -63,16 +89,38 @@ pub struct EditableMapEffectDB {
     pub effects: BTreeMap<SArc<EffectPath>, BTreeMap<SArc<String>, serde_json::Value>>,
     /// Catch-all for extra data for future extension
     pub empty: BTreeMap<SArc<String>, serde_json::Value>,
+    /// Provenance tags for effects
+    pub provenance: BTreeMap<SArc<EffectPath>, BTreeMap<SArc<String>, String>>,
 }
 
 impl From<MapEffectDB> for EditableMapEffectDB {
-    fn from(MapEffectDB { effects, empty }: MapEffectDB) -> Self {
-        Self { effects, empty }
+    fn from(
+        MapEffectDB {
+            effects,
+            empty,
+            provenance,
+        }: MapEffectDB,
+    ) -> Self {
+        Self {
+            effects,
+            empty,
+            provenance,
+        }
     }
 }
 impl From<EditableMapEffectDB> for MapEffectDB {
-    fn from(EditableMapEffectDB { effects, empty }: EditableMapEffectDB) -> Self {
-        Self { effects, empty }
+    fn from(
+        EditableMapEffectDB {
+            effects,
+            empty,
+            provenance,
+        }: EditableMapEffectDB,
+    ) -> Self {
+        Self {
+            effects,
+            empty,
+            provenance,
+        }
     }
 }
 
@@ -81,6 +129,98 @@ impl MapEffectDB {
     pub fn skip_serializing(&self) -> bool {
         self.effects.is_empty()
     }
+
+    /// Write the DB as JSON to `w`, streaming each entry rather than
+    /// building the whole document (as a `String` or `Value`) in memory.
+    /// The output is byte-identical to `serde_json::to_string`.
+    pub fn serialize_to<W: std::io::Write>(&self, w: W) -> Result<(), EffectDBError> {
+        Ok(serde_json::to_writer(w, self)?)
+    }
+
+    /// Tag every effect currently in the DB with `tag`, replacing any
+    /// existing provenance.
+    pub fn with_provenance(mut self, tag: &str) -> Self {
+        self.provenance = self
+            .effects
+            .iter()
+            .map(|(path, m)| {
+                let tags = m.keys().map(|k| (k.clone(), tag.to_owned())).collect();
+                (path.clone(), tags)
+            })
+            .collect();
+        self
+    }
+
+    /// Merge `other` into `self`. Effects in `other` take precedence over
+    /// those already present, and carry their provenance with them.
+    pub fn merge(&mut self, other: MapEffectDB) {
+        for (path, m) in other.effects {
+            let mut tags = other.provenance.get(&path).cloned().unwrap_or_default();
+            let our_tags = self.provenance.entry(path.clone()).or_default();
+            for k in m.keys() {
+                match tags.remove(k) {
+                    Some(tag) => our_tags.insert(k.clone(), tag),
+                    None => our_tags.remove(k),
+                };
+            }
+            if our_tags.is_empty() {
+                self.provenance.remove(&path);
+            }
+            self.effects.entry(path).or_default().extend(m);
+        }
+    }
+
+    /// Rewrite every `PathFragment::Named(old)` in the DB's paths to
+    /// `Named(new)`, e.g. after renaming a contract field. Effects whose
+    /// rewritten path already exists are merged into it. Returns how many
+    /// paths were changed, or an error (leaving the DB untouched) if `new`
+    /// is not a valid name.
+    pub fn rename(&mut self, old: &str, new: &str) -> Result<usize, ValidFragmentError> {
+        let new = match PathFragment::parse_with_max_branch(new, DEFAULT_MAX_BRANCH)? {
+            n @ PathFragment::Named(_) => n,
+            _ => return Err(ValidFragmentError::BadName(SArc(Arc::new(new.into())))),
+        };
+        let is_old =
+            |f: &PathFragment| matches!(f, PathFragment::Named(SArc(n)) if n.as_str() == old);
+        let rewrite = |p: SArc<EffectPath>| -> (bool, SArc<EffectPath>) {
+            if !p.0.iter().any(is_old) {
+                return (false, p);
+            }
+            let frags: Vec<PathFragment> = Vec::from(p.0.as_ref().clone());
+            let path = frags
+                .into_iter()
+                .map(|f| if is_old(&f) { new.clone() } else { f })
+                .fold(None, |past, f| Some(ReversePath::push(past, f)))
+                .expect("path is non-empty");
+            (true, SArc(path))
+        };
+        let mut changed = 0;
+        for (path, m) in std::mem::take(&mut self.effects) {
+            let (renamed, path) = rewrite(path);
+            changed += renamed as usize;
+            self.effects.entry(path).or_default().extend(m);
+        }
+        for (path, m) in std::mem::take(&mut self.provenance) {
+            self.provenance
+                .entry(rewrite(path).1)
+                .or_default()
+                .extend(m);
+        }
+        Ok(changed)
+    }
+
+    /// The paths at which the DB has effects
+    pub fn paths(&self) -> impl Iterator<Item = &Arc<EffectPath>> {
+        self.effects.keys().map(|p| &p.0)
+    }
+
+    /// Where the effect `name` at `at` came from, if it was tagged.
+    pub fn provenance(&self, at: &Arc<EffectPath>, name: &str) -> Option<&str> {
+        self.provenance
+            .get(&SArc(at.clone()))?
+            .get(&SArc(Arc::new(name.to_owned())))
+            .map(String::as_str)
+    }
 }
 
 impl EffectDB for MapEffectDB {
@@ -96,8 +236,26 @@ impl EffectDB for MapEffectDB {
 #[cfg(test)]
 mod test {
     use super::*;
+    use rand::distributions::Alphanumeric;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
     use std::convert::{TryFrom, TryInto};
     #[test]
+    fn test_named_eq_by_value() {
+        use crate::serialization_helpers::SArc;
+        use std::collections::{BTreeMap, HashMap};
+        use std::sync::Arc;
+        let a = PathFragment::Named(SArc(Arc::new("alice".to_string())));
+        let b = PathFragment::Named(SArc(Arc::new("alice".to_string())));
+        assert_eq!(a, b);
+        let mut h = HashMap::new();
+        h.insert(a.clone(), 1);
+        assert_eq!(h.get(&b), Some(&1));
+        let mut m = BTreeMap::new();
+        m.insert(EffectPath::push(None, a), 2);
+        assert_eq!(m.get(&EffectPath::push(None, b)), Some(&2));
+    }
+    #[test]
     fn test_string() {
         let v: Vec<PathFragment> = vec![
             "hello".try_into().unwrap(),
@@ -125,4 +283,470 @@ mod test {
             serde_json::from_str("\"hello/#100/@finish_fn\"").map_err(|_| ())
         );
     }
+    #[test]
+    fn test_join() {
+        let base = EffectPath::try_from("@root/@action/#0").unwrap();
+        let cases = [
+            ("a", "@root/@action/#0/a"),
+            ("a/#5/@next", "@root/@action/#0/a/#5/@next"),
+        ];
+        for (rel, expected) in cases {
+            let rel = EffectPath::try_from(rel).unwrap();
+            assert_eq!(String::from(base.join(&rel).unwrap()), expected);
+        }
+        let single = EffectPath::try_from("@root").unwrap();
+        let rel = EffectPath::try_from("x").unwrap();
+        assert_eq!(String::from(single.join(&rel).unwrap()), "@root/x");
+        assert!(EffectPath::try_from("x/@root").is_err());
+        let bad = EffectPath::try_from(vec![
+            PathFragment::Named(SArc(Arc::new("x".into()))),
+            PathFragment::Root,
+        ])
+        .unwrap();
+        assert!(base.join(&bad).is_err());
+    }
+
+    #[test]
+    fn test_merge_provenance() {
+        let path: Arc<EffectPath> = Arc::new("@root/@action/pay".try_into().unwrap());
+        let effects = |kvs: &[(&str, i64)]| -> MapEffectDB {
+            let m = kvs
+                .iter()
+                .map(|(k, v)| (SArc(Arc::new(k.to_string())), (*v).into()))
+                .collect();
+            EditableMapEffectDB {
+                effects: [(SArc(path.clone()), m)].into(),
+                empty: Default::default(),
+                provenance: Default::default(),
+            }
+            .into()
+        };
+        let mut db = effects(&[("a", 1), ("b", 2)]).with_provenance("defaults");
+        db.merge(effects(&[("b", 3), ("c", 4)]).with_provenance("user"));
+        assert_eq!(db.provenance(&path, "a"), Some("defaults"));
+        assert_eq!(db.provenance(&path, "b"), Some("user"));
+        assert_eq!(db.provenance(&path, "c"), Some("user"));
+        let vals: Vec<_> = db
+            .get_value(&path)
+            .map(|(k, v)| (k.as_str(), v.clone()))
+            .collect();
+        assert_eq!(
+            vals,
+            vec![("a", 1.into()), ("b", 3.into()), ("c", 4.into())]
+        );
+        // untagged effects clear stale provenance
+        db.merge(effects(&[("a", 5)]));
+        assert_eq!(db.provenance(&path, "a"), None);
+    }
+
+    #[test]
+    fn test_fragment_error_display() {
+        let e = PathFragment::try_from("foo bar").unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "invalid path name \"foo bar\": only [A-Za-z0-9_] allowed \
+             (expected @builtin | #branch | name)"
+        );
+        let e = PathFragment::try_from("#x").unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "could not parse branch index (expected @builtin | #branch | name)"
+        );
+        let e = PathFragment::try_from("@bogus").unwrap_err();
+        assert!(e.to_string().contains(PATH_GRAMMAR));
+        let e = PathFragment::parse_with_max_branch("#7", 3).unwrap_err();
+        assert!(e.to_string().contains('7'));
+        assert!(!e.to_string().contains(PATH_GRAMMAR));
+        // structural errors are not about fragment syntax
+        let e = EffectPath::try_from("@root")
+            .unwrap()
+            .join(&"@root".try_into().unwrap())
+            .unwrap_err()
+            .to_string();
+        assert!(e.contains("@root"));
+        assert!(!e.contains(PATH_GRAMMAR));
+    }
+
+    #[test]
+    fn test_display_with() {
+        struct NoSigils;
+        impl PathFragmentFormatter for NoSigils {
+            fn format(&self, frag: &PathFragment) -> String {
+                String::from(frag).trim_start_matches(['@', '#']).to_owned()
+            }
+        }
+        let p = EffectPath::try_from("@root/@action/pay/#3").unwrap();
+        assert_eq!(p.display_with(&NoSigils), "root/action/pay/3");
+        assert_eq!(p.display_with(&CanonicalFormatter), String::from(p));
+    }
+
+    #[test]
+    fn test_rooted() {
+        use PathFragment::*;
+        let p = EffectPath::rooted(vec![Root, Action, Branch(1)]).unwrap();
+        assert!(p.is_rooted());
+        assert_eq!(String::from(p), "@root/@action/#1");
+        assert!(EffectPath::rooted(vec![Root]).is_ok());
+        for bad in [vec![], vec![Action, Root], vec![Root, Next, Root]] {
+            let e = EffectPath::rooted(bad).unwrap_err();
+            assert!(e.to_string().contains("@root"));
+        }
+        assert!(!EffectPath::try_from("a/b").unwrap().is_rooted());
+        assert!(!EffectPath::empty().is_rooted());
+    }
+
+    #[test]
+    fn test_resolve_relative() {
+        let here = EffectPath::try_from("@root/@action/pay/@next").unwrap();
+        let resolve = |s: &str| here.resolve(s).map(String::from);
+        assert_eq!(resolve("./#0").unwrap(), "@root/@action/pay/@next/#0");
+        assert_eq!(resolve(".").unwrap(), "@root/@action/pay/@next");
+        assert_eq!(
+            resolve("../@suggested").unwrap(),
+            "@root/@action/pay/@suggested"
+        );
+        assert_eq!(
+            resolve("../../refund/./@next").unwrap(),
+            "@root/@action/refund/@next"
+        );
+        assert_eq!(resolve("../../..").unwrap(), "@root");
+        // absolute paths are unaffected by the base
+        assert_eq!(resolve("@root/x").unwrap(), "@root/x");
+        // stepping past the root
+        assert!(resolve("../../../..").is_err());
+        assert!(EffectPath::empty().resolve("..").is_err());
+        assert!(resolve("./@root").is_err());
+        assert!(resolve("./bad name").is_err());
+        // relative to a relative base
+        let rel = EffectPath::try_from("a/b").unwrap();
+        assert_eq!(String::from(rel.resolve("../c").unwrap()), "a/c");
+        assert_eq!(String::from(rel.resolve("../../c").unwrap()), "c");
+    }
+
+    #[test]
+    fn test_absolute_relative() {
+        let at = |s: &str| EffectPath::try_from(s).unwrap();
+        let base = at("@root/@action/pay");
+        let abs = at("@root/@action/pay/@next/#0");
+        let rel = abs.to_relative(&base).unwrap();
+        assert_eq!(String::from(rel.clone()), "@next/#0");
+        assert_eq!(rel.to_absolute(&base).unwrap(), abs);
+        // absolute paths ignore the base
+        assert_eq!(abs.to_absolute(&at("@root/x")).unwrap(), abs);
+        assert!(base.to_relative(&base).unwrap().is_empty());
+        assert_eq!(EffectPath::empty().to_absolute(&base).unwrap(), base);
+        // `..` is resolved from the string form first
+        let up = base.resolve("../refund").unwrap();
+        assert_eq!(String::from(up.clone()), "@root/@action/refund");
+        // paths past (or beside) the base have no relative form
+        assert!(up.to_relative(&base).is_none());
+        assert!(at("@root").to_relative(&base).is_none());
+        // a relative base can not make a path absolute
+        assert!(at("x").to_absolute(&at("a/b")).is_err());
+        assert!(at("x").to_absolute(&EffectPath::empty()).is_err());
+    }
+
+    #[test]
+    fn test_root_round_trip() {
+        for s in ["@root", "@root/@action/#1", "@root/pay/@leaf:2"] {
+            let p = EffectPath::try_from(s).unwrap();
+            assert!(p.is_rooted());
+            assert_eq!(String::from(p.clone()), s);
+            let j = serde_json::to_string(&p).unwrap();
+            assert_eq!(serde_json::from_str::<EffectPath>(&j).unwrap(), p);
+        }
+        assert_eq!(PathFragment::try_from("@root").unwrap(), PathFragment::Root);
+        assert_eq!(String::from(PathFragment::Root), "@root");
+    }
+
+    /// a name drawn from the whole charset `PathFragment::named` accepts
+    fn name(rng: &mut StdRng) -> String {
+        (0..rng.gen_range(1..=12))
+            .map(|_| match rng.gen_range(0..64) {
+                0 => '_',
+                _ => rng.sample(Alphanumeric) as char,
+            })
+            .collect()
+    }
+    fn fragment(rng: &mut StdRng) -> PathFragment {
+        match rng.gen_range(0..14) {
+            0 => PathFragment::Cloned,
+            1 => PathFragment::Action,
+            2 => PathFragment::FinishFn,
+            3 => PathFragment::CondCompIf,
+            4 => PathFragment::Guard,
+            5 => PathFragment::Next,
+            6 => PathFragment::Suggested,
+            7 => PathFragment::DefaultEffect,
+            8 => PathFragment::Effects,
+            9 => PathFragment::Metadata,
+            10 => PathFragment::Branch(rng.gen_range(0..=DEFAULT_MAX_BRANCH)),
+            11 => PathFragment::Leaf(rng.gen()),
+            _ => PathFragment::named(name(rng)).unwrap(),
+        }
+    }
+    /// a valid path: optionally rooted, with up to 8 more fragments
+    fn path(rng: &mut StdRng) -> EffectPath {
+        let mut v = vec![];
+        if rng.gen() {
+            v.push(PathFragment::Root);
+        }
+        for _ in 0..rng.gen_range(0..9) {
+            v.push(fragment(rng));
+        }
+        EffectPath::try_from(v).unwrap_or_default()
+    }
+
+    #[test]
+    fn test_prop_string_round_trip() {
+        let mut rng = StdRng::seed_from_u64(166);
+        let mut parser = PathParser::new();
+        let mut out = EffectPath::empty();
+        for _ in 0..10_000 {
+            let p = path(&mut rng);
+            let s = String::from(p.clone());
+            assert_eq!(EffectPath::try_from(s.as_str()).as_ref(), Ok(&p), "{}", s);
+            parser.parse_into(&s, &mut out).unwrap();
+            assert_eq!(out, p, "{}", s);
+            let j = serde_json::to_string(&p).unwrap();
+            assert_eq!(serde_json::from_str::<EffectPath>(&j).unwrap(), p);
+        }
+    }
+
+    #[test]
+    fn test_prop_named_round_trip() {
+        // `Named` holds any string, but only those `named` accepts come back
+        // from their rendering as the same single fragment
+        let mut rng = StdRng::seed_from_u64(16);
+        let alphabet: Vec<char> = "aZ9_#@/:. é".chars().collect();
+        for _ in 0..10_000 {
+            let s: String = (0..rng.gen_range(0..8))
+                .map(|_| match rng.gen_range(0..8) {
+                    0 => rng.gen::<char>(),
+                    _ => alphabet[rng.gen_range(0..alphabet.len())],
+                })
+                .collect();
+            let p = EffectPath::from(PathFragment::Named(SArc(Arc::new(s.clone()))));
+            let round_trips = EffectPath::try_from(String::from(p.clone()).as_str()) == Ok(p);
+            assert_eq!(
+                PathFragment::named(s.as_str()).is_ok(),
+                round_trips,
+                "{:?}",
+                s
+            );
+        }
+    }
+
+    #[test]
+    fn test_prop_invalid_strings() {
+        let mut rng = StdRng::seed_from_u64(1660);
+        let mut parser = PathParser::new();
+        let mut out = EffectPath::empty();
+        let alphabet: Vec<char> = "ab_1#0@/: -.é".chars().collect();
+        let corruptions = ["", " ", "-", "#01", "#", "@", "@nope", "@leaf:", "#-1", "é"];
+        for _ in 0..10_000 {
+            // arbitrary strings never panic, and anything accepted is canonical
+            let s: String = (0..rng.gen_range(0..16))
+                .map(|_| alphabet[rng.gen_range(0..alphabet.len())])
+                .collect();
+            match EffectPath::try_from(s.as_str()) {
+                Ok(p) => assert_eq!(String::from(p), s),
+                Err(_) => assert!(parser.parse_into(&s, &mut out).is_err(), "{}", s),
+            }
+            // a valid path with one fragment replaced by garbage always errors
+            let valid = String::from(path(&mut rng));
+            let mut segs: Vec<&str> = valid.split('/').collect();
+            let at = rng.gen_range(0..segs.len());
+            segs[at] = corruptions[rng.gen_range(0..corruptions.len())];
+            let bad = segs.join("/");
+            if !bad.is_empty() {
+                assert!(EffectPath::try_from(bad.as_str()).is_err(), "{}", bad);
+                assert!(parser.parse_into(&bad, &mut out).is_err(), "{}", bad);
+            }
+        }
+    }
+
+    #[test]
+    fn test_absolute_vs_relative() {
+        let abs = EffectPath::try_from("@root/@action/pay").unwrap();
+        let rel = EffectPath::try_from("@action/pay").unwrap();
+        assert!(abs.is_rooted());
+        assert!(!rel.is_rooted());
+        assert_ne!(abs, rel);
+        assert!(abs.join(&rel).is_ok());
+        assert!(rel.join(&abs).is_err());
+        for bad in ["@action/@root", "@root/@root", "@root/a/@root/b"] {
+            assert!(EffectPath::try_from(bad).is_err());
+            let mut out = EffectPath::empty();
+            assert!(PathParser::new().parse_into(bad, &mut out).is_err());
+        }
+    }
+
+    #[test]
+    fn test_eq_semantic() {
+        let p = |s: &str| EffectPath::try_from(s).unwrap();
+        let (a, b) = (
+            p("@root/@action/Pay_Alice/#1"),
+            p("@root/@action/pay_alice/#1"),
+        );
+        assert_ne!(a, b);
+        assert!(a.eq_semantic(&b));
+        assert!(a.eq_semantic(&a));
+        assert!(!a.eq_semantic(&p("@root/@action/pay_alice/#2")));
+        assert!(!a.eq_semantic(&p("@root/@action/pay_alice")));
+        assert!(!p("@root/@next").eq_semantic(&p("@root/next")));
+    }
+
+    #[test]
+    fn test_serialize_to() {
+        let mut effects = BTreeMap::new();
+        for i in 0..100u64 {
+            let path: EffectPath = format!("@root/@action/f{}", i).as_str().try_into().unwrap();
+            let m = [(
+                SArc(Arc::new(format!("e{}", i))),
+                serde_json::json!({ "n": i }),
+            )]
+            .into();
+            effects.insert(SArc(Arc::new(path)), m);
+        }
+        let db: MapEffectDB = EditableMapEffectDB {
+            effects,
+            empty: Default::default(),
+            provenance: Default::default(),
+        }
+        .into();
+        let mut streamed = vec![];
+        db.serialize_to(&mut streamed).unwrap();
+        assert_eq!(streamed, serde_json::to_string(&db).unwrap().into_bytes());
+    }
+
+    #[test]
+    fn test_sigil_grammar() {
+        use ValidFragmentError::*;
+        let bad = |s: &str| PathFragment::try_from(s).unwrap_err();
+        assert_eq!(bad("#foo"), BranchParseError);
+        assert_eq!(bad("#"), BranchParseError);
+        assert_eq!(bad("#+5"), BranchParseError);
+        assert_eq!(bad("#05"), BranchParseError);
+        assert_eq!(bad("#1a"), BranchParseError);
+        assert_eq!(bad("@123"), UnknownBuiltin(SArc(Arc::new("@123".into()))));
+        assert_eq!(
+            bad("@foo_123"),
+            UnknownBuiltin(SArc(Arc::new("@foo_123".into())))
+        );
+        assert_eq!(bad("foo-bar"), BadName(SArc(Arc::new("foo-bar".into()))));
+        assert_eq!(
+            PathFragment::try_from("@finish_fn"),
+            Ok(PathFragment::FinishFn)
+        );
+        assert_eq!(PathFragment::try_from("#0"), Ok(PathFragment::Branch(0)));
+        assert_eq!(PathFragment::try_from("#10"), Ok(PathFragment::Branch(10)));
+        assert!(bad("@123").to_string().contains("@123"));
+    }
+
+    #[test]
+    fn test_leaf() {
+        let p = EffectPath::try_from("@root/#2/@leaf:2/@leaf:0").unwrap();
+        let v: Vec<PathFragment> = p.iter().cloned().collect();
+        assert_eq!(
+            v,
+            vec![
+                PathFragment::Leaf(0),
+                PathFragment::Leaf(2),
+                PathFragment::Branch(2),
+                PathFragment::Root
+            ]
+        );
+        assert_ne!(PathFragment::Leaf(2), PathFragment::Branch(2));
+        assert_eq!(String::from(p.clone()), "@root/#2/@leaf:2/@leaf:0");
+        let json = serde_json::to_string(&p).unwrap();
+        assert_eq!(json, "\"@root/#2/@leaf:2/@leaf:0\"");
+        assert_eq!(serde_json::from_str::<EffectPath>(&json).unwrap(), p);
+        let bad = |s: &str| PathFragment::try_from(s).unwrap_err();
+        assert_eq!(bad("@leaf:"), ValidFragmentError::BranchParseError);
+        assert_eq!(bad("@leaf:01"), ValidFragmentError::BranchParseError);
+        assert_eq!(
+            bad("@leaf"),
+            ValidFragmentError::UnknownBuiltin(SArc(Arc::new("@leaf".into())))
+        );
+    }
+
+    #[test]
+    fn test_rename() {
+        let at = |s: &str| SArc(Arc::new(EffectPath::try_from(s).unwrap()));
+        let arg = |v: i64| {
+            std::iter::once((SArc(Arc::new("x".to_string())), serde_json::json!(v))).collect()
+        };
+        let mut db: MapEffectDB = EditableMapEffectDB {
+            effects: vec![
+                (at("@root/old/@next"), arg(1)),
+                (at("@root/#0/old/sub/old"), arg(2)),
+                (at("@root/older"), arg(3)),
+            ]
+            .into_iter()
+            .collect(),
+            empty: Default::default(),
+            provenance: Default::default(),
+        }
+        .into();
+        assert!(matches!(
+            db.rename("old", "not valid"),
+            Err(ValidFragmentError::BadName(_))
+        ));
+        assert!(matches!(
+            db.rename("old", "@next"),
+            Err(ValidFragmentError::BadName(_))
+        ));
+        assert_eq!(db.rename("old", "new"), Ok(2));
+        let mut paths: Vec<String> = db
+            .paths()
+            .map(|p| String::from(p.as_ref().clone()))
+            .collect();
+        paths.sort();
+        assert_eq!(
+            paths,
+            vec!["@root/#0/new/sub/new", "@root/new/@next", "@root/older"]
+        );
+        let v: Vec<_> = db.get_value(&at("@root/#0/new/sub/new").0).collect();
+        assert_eq!(v[0].1, &serde_json::json!(2));
+        assert_eq!(db.rename("old", "new"), Ok(0));
+    }
+
+    #[test]
+    fn test_branch_cap() {
+        let at = format!("#{}", DEFAULT_MAX_BRANCH);
+        assert_eq!(
+            PathFragment::try_from(at.as_str()),
+            Ok(PathFragment::Branch(DEFAULT_MAX_BRANCH))
+        );
+        let above = format!("#{}", DEFAULT_MAX_BRANCH + 1);
+        assert_eq!(
+            PathFragment::try_from(above.as_str()),
+            Err(ValidFragmentError::BranchOutOfRange {
+                value: DEFAULT_MAX_BRANCH + 1,
+                max: DEFAULT_MAX_BRANCH
+            })
+        );
+        assert!(EffectPath::try_from(format!("@root/{}", above)).is_err());
+        assert!(PathFragment::parse_with_max_branch("#5", 5).is_ok());
+        assert!(PathFragment::parse_with_max_branch("#6", 5).is_err());
+    }
+
+    #[test]
+    fn test_empty_path() {
+        let empty = EffectPath::empty();
+        assert_eq!(String::from(empty.clone()), "");
+        assert_eq!(EffectPath::try_from("").unwrap(), empty);
+        assert_eq!(serde_json::to_string(&empty).unwrap(), "\"\"");
+        assert_eq!(serde_json::from_str::<EffectPath>("\"\"").unwrap(), empty);
+        let base = EffectPath::try_from("@root/@action/#0").unwrap();
+        assert_eq!(base.join(&empty).unwrap(), base);
+        let rel = EffectPath::try_from("a/#1").unwrap();
+        assert_eq!(empty.join(&rel).unwrap(), rel);
+        assert!(base.starts_with(&empty));
+        assert_eq!(
+            EffectPath::try_from("@root").unwrap().parent(),
+            Some(EffectPath::default())
+        );
+    }
 }
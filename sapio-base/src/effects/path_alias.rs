@@ -0,0 +1,103 @@
+// Copyright Judica, Inc 2022
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+//  License, v. 2.0. If a copy of the MPL was not distributed with this
+//  file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! named abbreviations for long path prefixes
+use super::{EffectPath, ValidFragmentError};
+use crate::serialization_helpers::SArc;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::sync::Arc;
+
+/// `PathAliases` lets effect references abbreviate a long prefix, e.g. after
+/// defining `$vault = @root/@action/open/@next/@default_effect/#0`, the path
+/// `$vault/@action/withdraw` expands to the full path beneath it. Aliases
+/// are expanded before the path is validated, so the expansion is checked
+/// exactly like a path written out in full.
+#[derive(Default, Debug, Clone)]
+pub struct PathAliases {
+    aliases: BTreeMap<String, EffectPath>,
+}
+
+/// alias names follow the same rules as named fragments
+fn valid_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+impl PathAliases {
+    /// no aliases defined
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Define an alias from `$name = path`. The path may itself begin with
+    /// a previously defined alias, and redefining an alias replaces it.
+    pub fn define(&mut self, definition: &str) -> Result<&mut Self, ValidFragmentError> {
+        let bad = || ValidFragmentError::BadName(SArc(Arc::new(definition.into())));
+        let (name, path) = definition.split_once('=').ok_or_else(bad)?;
+        let name = name.trim().strip_prefix('$').ok_or_else(bad)?;
+        if !valid_name(name) {
+            return Err(bad());
+        }
+        let path = self.expand(path.trim())?;
+        self.aliases.insert(name.into(), path);
+        Ok(self)
+    }
+    /// the full path `name` (without the `$`) stands for, if defined
+    pub fn get(&self, name: &str) -> Option<&EffectPath> {
+        self.aliases.get(name)
+    }
+    /// Parse `path`, first replacing a leading `$name` segment with the
+    /// path it stands for. Paths without an alias parse as usual.
+    ///
+    /// Errors with [`ValidFragmentError::UnknownAlias`] if `name` is not
+    /// defined.
+    pub fn expand(&self, path: &str) -> Result<EffectPath, ValidFragmentError> {
+        let rest = match path.strip_prefix('$') {
+            Some(rest) => rest,
+            None => return EffectPath::try_from(path),
+        };
+        let (name, tail) = rest.split_once('/').unwrap_or((rest, ""));
+        let prefix = self
+            .aliases
+            .get(name)
+            .ok_or_else(|| ValidFragmentError::UnknownAlias(SArc(Arc::new(name.into()))))?;
+        prefix.join(&EffectPath::try_from(tail)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_expand_aliases() {
+        let mut aliases = PathAliases::new();
+        aliases
+            .define("$vault = @root/@action/open/@next/@default_effect/#0")
+            .unwrap()
+            .define("$withdraw = $vault/@action/withdraw")
+            .unwrap();
+        let expand = |s: &str| aliases.expand(s).map(String::from);
+        assert_eq!(
+            expand("$vault/@action/withdraw/@suggested").unwrap(),
+            "@root/@action/open/@next/@default_effect/#0/@action/withdraw/@suggested"
+        );
+        assert_eq!(
+            expand("$withdraw").unwrap(),
+            "@root/@action/open/@next/@default_effect/#0/@action/withdraw"
+        );
+        assert_eq!(expand("@root/x").unwrap(), "@root/x");
+        // the expansion is validated like any other path
+        assert!(expand("$vault/bad name").is_err());
+        assert!(expand("$vault/@root").is_err());
+        assert_eq!(
+            aliases.expand("$safe/@next").unwrap_err(),
+            ValidFragmentError::UnknownAlias(SArc(Arc::new("safe".into())))
+        );
+        assert!(aliases.define("vault = @root").is_err());
+        assert!(aliases.define("$ = @root").is_err());
+        assert!(aliases.define("$x = $nope/a").is_err());
+    }
+}
@@ -7,6 +7,7 @@
 //! Path  Fragments
 use crate::reverse_path::ReversePath;
 use crate::serialization_helpers::SArc;
+#[cfg(feature = "schemars")]
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
@@ -15,9 +16,8 @@ use std::str::FromStr;
 use std::sync::Arc;
 
 /// The derivation path fragments allowed, including user-generated
-#[derive(
-    Serialize, Deserialize, Debug, Hash, Eq, PartialEq, JsonSchema, Clone, PartialOrd, Ord,
-)]
+#[derive(Serialize, Deserialize, Debug, Hash, Eq, PartialEq, Clone, PartialOrd, Ord)]
+#[cfg_attr(feature = "schemars", derive(JsonSchema))]
 #[serde(into = "String")]
 #[serde(try_from = "&str")]
 pub enum PathFragment {
@@ -45,7 +45,10 @@ pub enum PathFragment {
     Metadata,
     /// A numbered branch at this level
     Branch(u64),
-    /// a named branch at this level
+    /// A numbered Taproot script leaf at this level
+    Leaf(u64),
+    /// a named branch at this level. Only names [`PathFragment::named`]
+    /// accepts survive being rendered into a path and parsed back.
     Named(SArc<String>),
 }
 
@@ -57,25 +60,20 @@ impl From<PathFragment> for String {
 impl From<&PathFragment> for String {
     fn from(a: &PathFragment) -> Self {
         match a {
-            PathFragment::Root => "@root".into(),
-            PathFragment::Cloned => "@cloned".into(),
-            PathFragment::Action => "@action".into(),
-            PathFragment::FinishFn => "@finish_fn".into(),
-            PathFragment::CondCompIf => "@cond_comp_if".into(),
-            PathFragment::Guard => "@guard".into(),
-            PathFragment::Next => "@next".into(),
-            PathFragment::Suggested => "@suggested".into(),
-            PathFragment::DefaultEffect => "@default_effect".into(),
-            PathFragment::Effects => "@effects".into(),
-            PathFragment::Metadata => "@metadata".into(),
             PathFragment::Branch(u) => format!("#{}", u),
+            PathFragment::Leaf(u) => format!("@leaf:{}", u),
             PathFragment::Named(SArc(a)) => a.as_ref().clone(),
+            builtin => builtin
+                .builtin_name()
+                .expect("builtins without an index have a name")
+                .into(),
         }
     }
 }
 
 /// Error for parsing a fragment
-#[derive(Serialize, Deserialize, Debug, Hash, Eq, PartialEq, JsonSchema, Clone)]
+#[derive(Serialize, Deserialize, Debug, Hash, Eq, PartialEq, Clone)]
+#[cfg_attr(feature = "schemars", derive(JsonSchema))]
 pub enum ValidFragmentError {
     /// branch could not be parsed (must be alphanumeric for user generated)
     BranchParseError,
@@ -83,12 +81,64 @@ pub enum ValidFragmentError {
     BadName(SArc<String>),
     /// Other error
     InvalidReversePath(&'static str),
+    /// an `@` fragment that is not a known builtin
+    UnknownBuiltin(SArc<String>),
+    /// a branch index was larger than the maximum allowed
+    BranchOutOfRange {
+        /// the index that was given
+        value: u64,
+        /// the largest index allowed
+        max: u64,
+    },
+    /// a builtin fragment that is not in the permitted set (see
+    /// [`PathFragment::check_allowed`])
+    ForbiddenFragment(PathFragment),
+    /// a `$name` prefix with no definition (see [`super::PathAliases`])
+    UnknownAlias(SArc<String>),
 }
 
+/// A reminder of the fragment grammar, shown with the errors for fragments
+/// that do not parse as one of its forms.
+pub const PATH_GRAMMAR: &str = "expected @builtin | #branch | name";
+
 impl std::error::Error for ValidFragmentError {}
 impl std::fmt::Display for ValidFragmentError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
-        std::fmt::Debug::fmt(self, f)
+        match self {
+            ValidFragmentError::BranchParseError => {
+                write!(f, "could not parse branch index ({})", PATH_GRAMMAR)
+            }
+            ValidFragmentError::BadName(SArc(name)) => write!(
+                f,
+                "invalid path name {:?}: only [A-Za-z0-9_] allowed ({})",
+                name.as_str(),
+                PATH_GRAMMAR
+            ),
+            ValidFragmentError::InvalidReversePath(why) => write!(f, "invalid path: {}", why),
+            ValidFragmentError::UnknownBuiltin(SArc(name)) => {
+                write!(
+                    f,
+                    "unknown builtin fragment {:?} ({})",
+                    name.as_str(),
+                    PATH_GRAMMAR
+                )
+            }
+            ValidFragmentError::BranchOutOfRange { value, max } => write!(
+                f,
+                "branch index {} out of range: at most {} allowed",
+                value, max
+            ),
+            ValidFragmentError::ForbiddenFragment(frag) => {
+                write!(
+                    f,
+                    "builtin fragment {} is not permitted",
+                    String::from(frag)
+                )
+            }
+            ValidFragmentError::UnknownAlias(SArc(name)) => {
+                write!(f, "undefined path alias ${}", name)
+            }
+        }
     }
 }
 use std::num::ParseIntError;
@@ -98,15 +148,73 @@ impl From<ParseIntError> for ValidFragmentError {
     }
 }
 
-impl TryFrom<Arc<String>> for PathFragment {
-    type Error = ValidFragmentError;
-    fn try_from(s: Arc<String>) -> Result<Self, Self::Error> {
-        Self::try_from(s.as_ref().as_str())
+/// The largest `PathFragment::Branch` index accepted by default. Legitimate
+/// contracts are nowhere near this, so larger values are rejected as likely
+/// bugs (or attempts to bloat encodings).
+pub const DEFAULT_MAX_BRANCH: u64 = 1 << 32;
+
+impl PathFragment {
+    /// A `Named` fragment, checking `name` is non-empty and only ASCII
+    /// alphanumerics or `_`, i.e. that it parses back from its rendering.
+    pub fn named(name: impl Into<String>) -> Result<Self, ValidFragmentError> {
+        let name = name.into();
+        if valid_name(&name) {
+            Ok(PathFragment::Named(SArc(Arc::new(name))))
+        } else {
+            Err(ValidFragmentError::BadName(SArc(Arc::new(name))))
+        }
     }
-}
-impl TryFrom<&str> for PathFragment {
-    type Error = ValidFragmentError;
-    fn try_from(s: &str) -> Result<Self, Self::Error> {
+    /// Parse a fragment, rejecting `Branch` indexes larger than `max`.
+    pub fn parse_with_max_branch(s: &str, max: u64) -> Result<Self, ValidFragmentError> {
+        let p = Self::parse_unchecked(s)?;
+        p.check_branch(max)?;
+        Ok(p)
+    }
+    /// Errors if this is a `Branch` with an index larger than `max`.
+    pub fn check_branch(&self, max: u64) -> Result<(), ValidFragmentError> {
+        match self {
+            PathFragment::Branch(value) if *value > max => {
+                Err(ValidFragmentError::BranchOutOfRange { value: *value, max })
+            }
+            _ => Ok(()),
+        }
+    }
+    /// The string form of a builtin without an index (e.g. `"@next"`), or
+    /// `None` for branches, leaves and names.
+    pub(crate) fn builtin_name(&self) -> Option<&'static str> {
+        Some(match self {
+            PathFragment::Root => "@root",
+            PathFragment::Cloned => "@cloned",
+            PathFragment::Action => "@action",
+            PathFragment::FinishFn => "@finish_fn",
+            PathFragment::CondCompIf => "@cond_comp_if",
+            PathFragment::Guard => "@guard",
+            PathFragment::Next => "@next",
+            PathFragment::Suggested => "@suggested",
+            PathFragment::DefaultEffect => "@default_effect",
+            PathFragment::Effects => "@effects",
+            PathFragment::Metadata => "@metadata",
+            PathFragment::Branch(_) | PathFragment::Leaf(_) | PathFragment::Named(_) => {
+                return None
+            }
+        })
+    }
+    /// Is this one of the `@` builtins (i.e. not a branch or a name)?
+    pub fn is_builtin(&self) -> bool {
+        !matches!(self, PathFragment::Branch(_) | PathFragment::Named(_))
+    }
+    /// Errors if this is a builtin of a kind not listed in `allowed`.
+    /// Branches and names are always allowed, and builtins carrying an
+    /// index (e.g. `Leaf`) match regardless of the index.
+    pub fn check_allowed(&self, allowed: &[PathFragment]) -> Result<(), ValidFragmentError> {
+        let kind = std::mem::discriminant(self);
+        if self.is_builtin() && !allowed.iter().any(|a| std::mem::discriminant(a) == kind) {
+            Err(ValidFragmentError::ForbiddenFragment(self.clone()))
+        } else {
+            Ok(())
+        }
+    }
+    fn parse_unchecked(s: &str) -> Result<Self, ValidFragmentError> {
         Ok(match s {
             "@root" => PathFragment::Root,
             "@cloned" => PathFragment::Cloned,
@@ -119,30 +227,352 @@ impl TryFrom<&str> for PathFragment {
             "@default_effect" => PathFragment::DefaultEffect,
             "@effects" => PathFragment::Effects,
             "@metadata" => PathFragment::Metadata,
-            n if n.starts_with('#') => PathFragment::Branch(FromStr::from_str(&n[1..])?),
-            n if n.chars().all(|x| x.is_ascii_alphanumeric() || x == '_') => {
-                PathFragment::Named(SArc(Arc::new(s.into())))
+            // a branch is `#` followed by a canonical decimal number
+            n if n.starts_with('#') => PathFragment::Branch(parse_index(&n[1..])?),
+            // a leaf is `@leaf:` followed by a canonical decimal number
+            n if n.starts_with("@leaf:") => PathFragment::Leaf(parse_index(&n[6..])?),
+            n if n.starts_with('@') => {
+                return Err(ValidFragmentError::UnknownBuiltin(SArc(Arc::new(s.into()))))
             }
+            // names are non-empty, as an empty one would not survive being
+            // rendered into a path and parsed back
+            n if valid_name(n) => PathFragment::Named(SArc(Arc::new(s.into()))),
             _ => return Err(ValidFragmentError::BadName(SArc(Arc::new(s.into())))),
         })
     }
 }
 
+fn valid_name(n: &str) -> bool {
+    !n.is_empty() && n.chars().all(|x| x.is_ascii_alphanumeric() || x == '_')
+}
+
+/// parse a decimal index with no sign or leading zeros
+pub(crate) fn parse_index(digits: &str) -> Result<u64, ValidFragmentError> {
+    let canonical = !digits.is_empty()
+        && digits.bytes().all(|b| b.is_ascii_digit())
+        && (digits == "0" || !digits.starts_with('0'));
+    if !canonical {
+        return Err(ValidFragmentError::BranchParseError);
+    }
+    Ok(FromStr::from_str(digits)?)
+}
+
+impl TryFrom<Arc<String>> for PathFragment {
+    type Error = ValidFragmentError;
+    fn try_from(s: Arc<String>) -> Result<Self, Self::Error> {
+        // reuse the caller's allocation for names
+        Ok(match Self::try_from(s.as_ref().as_str())? {
+            PathFragment::Named(_) => PathFragment::Named(SArc(s)),
+            p => p,
+        })
+    }
+}
+impl TryFrom<&str> for PathFragment {
+    type Error = ValidFragmentError;
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        Self::parse_with_max_branch(s, DEFAULT_MAX_BRANCH)
+    }
+}
+
+/// A [`PathFragment`] which deserializes from either its string form
+/// (`"#3"`) or the enum-tagged object form some clients generate
+/// (`{"Branch": 3}`, `{"Named": "foo"}`, `{"Root": null}`), validating
+/// both identically. It always serializes to the compact string form.
+#[derive(Serialize, Deserialize, Debug, Hash, Eq, PartialEq, Clone, PartialOrd, Ord)]
+#[serde(into = "String")]
+#[serde(try_from = "AnyFormPathFragment")]
+pub struct LenientPathFragment(pub PathFragment);
+
+/// the forms accepted by [`LenientPathFragment`]
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum AnyFormPathFragment {
+    Str(String),
+    Tagged(TaggedPathFragment),
+}
+
+/// [`PathFragment`] in serde's default (externally tagged) representation
+#[derive(Deserialize)]
+enum TaggedPathFragment {
+    Root,
+    Cloned,
+    Action,
+    FinishFn,
+    CondCompIf,
+    Guard,
+    Next,
+    Suggested,
+    DefaultEffect,
+    Effects,
+    Metadata,
+    Branch(u64),
+    Leaf(u64),
+    Named(String),
+}
+
+impl TryFrom<AnyFormPathFragment> for LenientPathFragment {
+    type Error = ValidFragmentError;
+    fn try_from(f: AnyFormPathFragment) -> Result<Self, Self::Error> {
+        let tagged = match f {
+            AnyFormPathFragment::Str(s) => return Ok(Self(PathFragment::try_from(s.as_str())?)),
+            AnyFormPathFragment::Tagged(t) => t,
+        };
+        // go through the string form so both forms are checked the same way
+        let s = match tagged {
+            TaggedPathFragment::Root => PathFragment::Root.into(),
+            TaggedPathFragment::Cloned => PathFragment::Cloned.into(),
+            TaggedPathFragment::Action => PathFragment::Action.into(),
+            TaggedPathFragment::FinishFn => PathFragment::FinishFn.into(),
+            TaggedPathFragment::CondCompIf => PathFragment::CondCompIf.into(),
+            TaggedPathFragment::Guard => PathFragment::Guard.into(),
+            TaggedPathFragment::Next => PathFragment::Next.into(),
+            TaggedPathFragment::Suggested => PathFragment::Suggested.into(),
+            TaggedPathFragment::DefaultEffect => PathFragment::DefaultEffect.into(),
+            TaggedPathFragment::Effects => PathFragment::Effects.into(),
+            TaggedPathFragment::Metadata => PathFragment::Metadata.into(),
+            TaggedPathFragment::Branch(u) => String::from(PathFragment::Branch(u)),
+            TaggedPathFragment::Leaf(u) => String::from(PathFragment::Leaf(u)),
+            TaggedPathFragment::Named(name) => match PathFragment::try_from(name.as_str())? {
+                n @ PathFragment::Named(_) => return Ok(Self(n)),
+                _ => return Err(ValidFragmentError::BadName(SArc(Arc::new(name)))),
+            },
+        };
+        Ok(Self(PathFragment::try_from(s.as_str())?))
+    }
+}
+
+impl From<LenientPathFragment> for String {
+    fn from(f: LenientPathFragment) -> Self {
+        f.0.into()
+    }
+}
+
+impl From<LenientPathFragment> for PathFragment {
+    fn from(f: LenientPathFragment) -> Self {
+        f.0
+    }
+}
+
+/// Controls how each fragment of a path is rendered by
+/// [`ReversePath::display_with`]. The default methods match the canonical
+/// form used for serialization.
+pub trait PathFragmentFormatter {
+    /// render a single fragment
+    fn format(&self, frag: &PathFragment) -> String {
+        String::from(frag)
+    }
+    /// placed between fragments
+    fn separator(&self) -> &str {
+        "/"
+    }
+}
+
+/// The canonical (wire) formatting of paths
+pub struct CanonicalFormatter;
+impl PathFragmentFormatter for CanonicalFormatter {}
+
 impl From<ReversePath<PathFragment>> for String {
     fn from(r: ReversePath<PathFragment>) -> String {
-        let mut v: Vec<String> = r.iter().cloned().map(String::from).collect();
+        r.display_with(&CanonicalFormatter)
+    }
+}
+
+impl PathFragment {
+    /// Lenient equality for matching user-entered fragments: builtins and
+    /// branches must match exactly, but names are compared ignoring ASCII
+    /// case.
+    pub fn eq_semantic(&self, other: &Self) -> bool {
+        match (self, other) {
+            (PathFragment::Named(SArc(a)), PathFragment::Named(SArc(b))) => {
+                a.eq_ignore_ascii_case(b)
+            }
+            (a, b) => a == b,
+        }
+    }
+    /// Can this fragment begin an absolute path?
+    pub fn is_root(&self) -> bool {
+        matches!(self, PathFragment::Root)
+    }
+}
+
+impl ReversePath<PathFragment> {
+    /// Build an absolute path from fragments listed root first, checking
+    /// that the first fragment is a root (`@root`), and that no other is.
+    pub fn rooted(frags: Vec<PathFragment>) -> Result<Self, ValidFragmentError> {
+        match frags.split_first() {
+            None => Err(ValidFragmentError::InvalidReversePath(
+                "an absolute path must begin with @root, but the path is empty",
+            )),
+            Some((first, _)) if !first.is_root() => Err(ValidFragmentError::InvalidReversePath(
+                "an absolute path must begin with @root",
+            )),
+            Some((_, rest)) if rest.iter().any(PathFragment::is_root) => Err(
+                ValidFragmentError::InvalidReversePath("@root may only begin a path"),
+            ),
+            _ => ReversePath::try_from(frags).map_err(ValidFragmentError::InvalidReversePath),
+        }
+    }
+
+    /// Lenient equality for matching user-entered paths, comparing each
+    /// fragment with [`PathFragment::eq_semantic`]. Unlike `Eq` (which is
+    /// used for hashing and lookups), `@root/Pay` and `@root/pay` are
+    /// semantically equal.
+    pub fn eq_semantic(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.iter().zip(other.iter()).all(|(a, b)| a.eq_semantic(b))
+    }
+
+    /// Is this an absolute path, i.e. does it begin with a root fragment?
+    pub fn is_rooted(&self) -> bool {
+        self.iter()
+            .last()
+            .map(PathFragment::is_root)
+            .unwrap_or(false)
+    }
+
+    /// Render the path root first, formatting each fragment with `fmt`
+    pub fn display_with<F: PathFragmentFormatter + ?Sized>(&self, fmt: &F) -> String {
+        let mut v: Vec<String> = self.iter().map(|f| fmt.format(f)).collect();
+        v.reverse();
+        v.join(fmt.separator())
+    }
+
+    /// The path as a JSON array of segments, root first, for tools
+    /// navigating serialized contracts without splitting strings. Builtins
+    /// and names are strings (only builtins begin with `@`), while branches
+    /// and leaves are objects carrying their index, e.g.
+    /// `["@root", "@action", {"branch": 3}, "field"]`.
+    pub fn to_value_segments(&self) -> serde_json::Value {
+        let mut v: Vec<serde_json::Value> = self
+            .iter()
+            .map(|f| match f {
+                PathFragment::Branch(u) => serde_json::json!({ "branch": u }),
+                PathFragment::Leaf(u) => serde_json::json!({ "leaf": u }),
+                f => String::from(f).into(),
+            })
+            .collect();
         v.reverse();
-        v.join("/")
+        v.into()
+    }
+
+    /// Append a path relative to `self` (e.g., one rooted at a subcontract)
+    /// onto `self`.
+    ///
+    /// Errors if the result would not be a valid path, i.e. if `relative`
+    /// contains a `@root`, which may only begin a path.
+    pub fn join(
+        &self,
+        relative: &ReversePath<PathFragment>,
+    ) -> Result<ReversePath<PathFragment>, ValidFragmentError> {
+        let mut frags: Vec<&PathFragment> = relative.iter().collect();
+        if frags.contains(&&PathFragment::Root) {
+            return Err(ValidFragmentError::InvalidReversePath(
+                "@root may only begin a path",
+            ));
+        }
+        frags.reverse();
+        let joined = frags.into_iter().fold(Arc::new(self.clone()), |acc, f| {
+            ReversePath::push(Some(acc), f.clone())
+        });
+        Ok(Arc::try_unwrap(joined).unwrap_or_else(|a| a.as_ref().clone()))
+    }
+
+    /// Parse `path` as seen from `self`. A path beginning with `./` (this
+    /// node) or `../` (its parent) is relative: each `.` segment is skipped,
+    /// each `..` moves up one fragment, and the rest are appended. Anything
+    /// else is parsed as usual.
+    ///
+    /// Errors if a `..` would step above `@root` (or above an empty path).
+    pub fn resolve(&self, path: &str) -> Result<ReversePath<PathFragment>, ValidFragmentError> {
+        let is_relative =
+            |p: &str| p == "." || p == ".." || p.starts_with("./") || p.starts_with("../");
+        if !is_relative(path) {
+            return Self::try_from(path);
+        }
+        let mut at = Arc::new(self.clone());
+        for seg in path.split('/') {
+            match seg {
+                "." | "" => {}
+                ".." => {
+                    if at.is_empty() || (at.len() == 1 && at.is_rooted()) {
+                        return Err(ValidFragmentError::InvalidReversePath(
+                            "relative path steps above the root",
+                        ));
+                    }
+                    at = Arc::new(at.parent().unwrap_or_default());
+                }
+                seg => {
+                    let f = PathFragment::try_from(seg)?;
+                    if f.is_root() {
+                        return Err(ValidFragmentError::InvalidReversePath(
+                            "@root may only begin a path",
+                        ));
+                    }
+                    let past = if at.is_empty() { None } else { Some(at) };
+                    at = ReversePath::push(past, f);
+                }
+            }
+        }
+        Ok(Arc::try_unwrap(at).unwrap_or_else(|a| a.as_ref().clone()))
+    }
+
+    /// This path made absolute by placing it beneath `base`, or itself if
+    /// it already begins with `@root`. The `.` and `..` segments of the
+    /// string form are resolved when parsing (see
+    /// [`ReversePath::resolve`]), so a parsed relative path only ever
+    /// descends from `base`.
+    ///
+    /// Errors if `base` is not itself absolute, or if `self` contains a
+    /// `@root` other than at its start.
+    pub fn to_absolute(
+        &self,
+        base: &ReversePath<PathFragment>,
+    ) -> Result<ReversePath<PathFragment>, ValidFragmentError> {
+        if self.is_rooted() {
+            return Ok(self.clone());
+        }
+        if !base.is_rooted() {
+            return Err(ValidFragmentError::InvalidReversePath(
+                "the base of an absolute path must begin with @root",
+            ));
+        }
+        base.join(self)
+    }
+
+    /// This path relative to `base`, i.e. the fragments after `base`, such
+    /// that `rel.to_absolute(base)` gives back `self` (for an absolute
+    /// `base`). The path relative to itself is empty.
+    ///
+    /// `None` if `self` is not beneath `base`, as stepping back up past
+    /// `base` can not be expressed with fragments.
+    pub fn to_relative(
+        &self,
+        base: &ReversePath<PathFragment>,
+    ) -> Option<ReversePath<PathFragment>> {
+        if !self.starts_with(base) {
+            return None;
+        }
+        let mut frags: Vec<PathFragment> =
+            self.iter().take(self.len() - base.len()).cloned().collect();
+        frags.reverse();
+        Some(ReversePath::try_from(frags).unwrap_or_default())
     }
 }
 
 impl TryFrom<&str> for ReversePath<PathFragment> {
     type Error = ValidFragmentError;
     fn try_from(r: &str) -> Result<ReversePath<PathFragment>, Self::Error> {
+        if r.is_empty() {
+            return Ok(ReversePath::empty());
+        }
         let frags = r
             .split('/')
             .map(PathFragment::try_from)
             .collect::<Result<Vec<_>, _>>()?;
+        if frags.iter().skip(1).any(PathFragment::is_root) {
+            return Err(ValidFragmentError::InvalidReversePath(
+                "@root may only begin a path",
+            ));
+        }
         ReversePath::try_from(frags).map_err(ValidFragmentError::InvalidReversePath)
     }
 }
@@ -153,3 +583,127 @@ impl TryFrom<String> for ReversePath<PathFragment> {
         Self::try_from(r.as_ref())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::hash_map::DefaultHasher;
+    use std::collections::HashMap;
+    use std::hash::{Hash, Hasher};
+
+    fn hash_of<T: Hash>(t: &T) -> u64 {
+        let mut h = DefaultHasher::new();
+        t.hash(&mut h);
+        h.finish()
+    }
+
+    /// `SArc` wraps an `Arc`, but hashing and equality must only ever see
+    /// the value behind it, or effect lookups would depend on how a path
+    /// happened to be built.
+    #[test]
+    fn test_fragment_independent_of_arc() {
+        let shared = Arc::new(String::from("pay"));
+        let a = PathFragment::try_from(shared.clone()).unwrap();
+        let b = PathFragment::try_from(shared).unwrap();
+        let fresh = PathFragment::try_from("pay").unwrap();
+        match (&a, &b, &fresh) {
+            (
+                PathFragment::Named(SArc(a)),
+                PathFragment::Named(SArc(b)),
+                PathFragment::Named(SArc(f)),
+            ) => {
+                assert!(Arc::ptr_eq(a, b));
+                assert!(!Arc::ptr_eq(a, f));
+            }
+            _ => panic!("expected names"),
+        }
+        assert_eq!(a, fresh);
+        assert_eq!(hash_of(&a), hash_of(&fresh));
+        assert_eq!(a.cmp(&fresh), std::cmp::Ordering::Equal);
+        let mut m = HashMap::new();
+        m.insert(a, 1);
+        assert_eq!(m.get(&fresh), Some(&1));
+    }
+
+    #[test]
+    fn test_to_value_segments() {
+        let p = ReversePath::<PathFragment>::try_from("@root/@action/#3/@leaf:1/field").unwrap();
+        assert_eq!(
+            p.to_value_segments(),
+            serde_json::json!(["@root", "@action", {"branch": 3}, {"leaf": 1}, "field"])
+        );
+        assert_eq!(
+            ReversePath::<PathFragment>::empty().to_value_segments(),
+            serde_json::json!([])
+        );
+    }
+
+    #[test]
+    fn test_path_independent_of_arc() {
+        // one path sharing its prefix with a sibling, one parsed afresh
+        let prefix = Arc::new(ReversePath::<PathFragment>::try_from("@root/@action").unwrap());
+        let shared_name = Arc::new(String::from("pay"));
+        let built = ReversePath::push(
+            Some(prefix.clone()),
+            PathFragment::try_from(shared_name.clone()).unwrap(),
+        );
+        let sibling = ReversePath::push(Some(prefix), PathFragment::try_from(shared_name).unwrap());
+        let parsed = ReversePath::<PathFragment>::try_from("@root/@action/pay").unwrap();
+        assert_eq!(*built, parsed);
+        assert_eq!(*built, *sibling);
+        assert_eq!(hash_of(&*built), hash_of(&parsed));
+        let mut m = HashMap::new();
+        m.insert(SArc(built), "effect");
+        assert_eq!(m.get(&SArc(Arc::new(parsed))), Some(&"effect"));
+        assert_eq!(m.get(&SArc(sibling)), Some(&"effect"));
+    }
+
+    /// serde support must not depend on the `schemars` feature, see
+    /// `cargo test -p sapio-base --no-default-features`
+    #[test]
+    fn test_serde_roundtrip() {
+        for s in ["@root", "#3", "@leaf:1", "pay"] {
+            let f = PathFragment::try_from(s).unwrap();
+            let j = serde_json::to_string(&f).unwrap();
+            assert_eq!(j, format!("\"{}\"", s));
+            assert_eq!(serde_json::from_str::<PathFragment>(&j).unwrap(), f);
+        }
+        assert!(serde_json::from_str::<PathFragment>("\"a/b\"").is_err());
+        let p = ReversePath::<PathFragment>::try_from("@root/@action/pay").unwrap();
+        let j = serde_json::to_string(&p).unwrap();
+        assert_eq!(j, "\"@root/@action/pay\"");
+        assert_eq!(
+            serde_json::from_str::<ReversePath<PathFragment>>(&j).unwrap(),
+            p
+        );
+    }
+
+    #[test]
+    fn test_lenient_tagged_form() {
+        use serde_json::json;
+        let parse = |v: serde_json::Value| {
+            serde_json::from_value::<LenientPathFragment>(v).map(PathFragment::from)
+        };
+        let cases = vec![
+            (json!("#3"), json!({"Branch": 3})),
+            (json!("foo"), json!({"Named": "foo"})),
+            (json!("@leaf:1"), json!({"Leaf": 1})),
+            (json!("@root"), json!({"Root": null})),
+        ];
+        for (string, tagged) in cases {
+            let a = parse(string.clone()).unwrap();
+            assert_eq!(a, parse(tagged).unwrap());
+            assert_eq!(a, PathFragment::try_from(string.as_str().unwrap()).unwrap());
+            // always written back in the compact form
+            assert_eq!(
+                serde_json::to_value(LenientPathFragment(a)).unwrap(),
+                string
+            );
+        }
+        // the tagged form is validated like the string form
+        assert!(parse(json!({"Named": "@root"})).is_err());
+        assert!(parse(json!({"Named": "a/b"})).is_err());
+        assert!(parse(json!({"Branch": DEFAULT_MAX_BRANCH + 1})).is_err());
+        assert!(parse(json!({"Bogus": 1})).is_err());
+    }
+}
@@ -0,0 +1,109 @@
+// Copyright Judica, Inc 2022
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+//  License, v. 2.0. If a copy of the MPL was not distributed with this
+//  file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! A path parser which reuses allocations across calls
+use super::path_fragment::parse_index;
+use super::{EffectPath, PathFragment, ValidFragmentError, DEFAULT_MAX_BRANCH};
+use crate::reverse_path::ReversePath;
+use crate::serialization_helpers::SArc;
+use std::sync::Arc;
+
+/// `PathParser` parses many paths in a row (e.g. in a server), reusing the
+/// nodes of the previously parsed path for any prefix the next one shares
+/// with it. Parsing the same (or a sibling) path repeatedly therefore
+/// allocates only for the fragments that differ.
+///
+/// Results are identical to `EffectPath::try_from(&str)`.
+#[derive(Default)]
+pub struct PathParser {
+    /// nodes[i] is the path of the first i+1 fragments of the last parse
+    nodes: Vec<Arc<EffectPath>>,
+}
+
+/// does `f` parse from `s`? Does not allocate.
+fn fragment_is(f: &PathFragment, s: &str) -> bool {
+    match f {
+        // only canonical numbers (no sign or leading zeros) are indexes
+        PathFragment::Branch(u) => s
+            .strip_prefix('#')
+            .is_some_and(|n| parse_index(n) == Ok(*u)),
+        PathFragment::Leaf(u) => s
+            .strip_prefix("@leaf:")
+            .is_some_and(|n| parse_index(n) == Ok(*u)),
+        PathFragment::Named(SArc(n)) => n.as_str() == s,
+        builtin => builtin.builtin_name() == Some(s),
+    }
+}
+
+impl PathParser {
+    /// create a parser with no saved state
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Parse `s` into `out`. On error `out` is left unmodified.
+    pub fn parse_into(&mut self, s: &str, out: &mut EffectPath) -> Result<(), ValidFragmentError> {
+        if s.is_empty() {
+            self.nodes.clear();
+            *out = ReversePath::empty();
+            return Ok(());
+        }
+        let mut depth = 0;
+        for seg in s.split('/') {
+            let reused = self
+                .nodes
+                .get(depth)
+                .and_then(|n| n.iter().next())
+                .is_some_and(|f| fragment_is(f, seg));
+            if !reused {
+                let frag = PathFragment::parse_with_max_branch(seg, DEFAULT_MAX_BRANCH)?;
+                if depth > 0 && frag.is_root() {
+                    return Err(ValidFragmentError::InvalidReversePath(
+                        "@root may only begin a path",
+                    ));
+                }
+                self.nodes.truncate(depth);
+                let past = self.nodes.last().cloned();
+                self.nodes.push(ReversePath::push(past, frag));
+            }
+            depth += 1;
+        }
+        self.nodes.truncate(depth);
+        *out = self.nodes[depth - 1].as_ref().clone();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::convert::TryFrom;
+    #[test]
+    fn test_parse_into_matches_try_from() {
+        let mut parser = PathParser::new();
+        let mut out = EffectPath::empty();
+        for s in [
+            "@root/@action/pay/#1",
+            "@root/@action/pay/#2",
+            "@root/@action",
+            "@root/@action/pay/#1/@next",
+            "",
+            "a/b",
+            "@root",
+        ] {
+            parser.parse_into(s, &mut out).unwrap();
+            assert_eq!(out, EffectPath::try_from(s).unwrap(), "{}", s);
+        }
+        parser.parse_into("@root/@action/pay/#1", &mut out).unwrap();
+        assert!(parser
+            .parse_into("@root/@action/pay/#01", &mut out)
+            .is_err());
+        let before = out.clone();
+        assert!(parser.parse_into("@root/bad name", &mut out).is_err());
+        assert_eq!(out, before);
+        parser.parse_into("@root/x", &mut out).unwrap();
+        assert_eq!(String::from(out), "@root/x");
+    }
+}
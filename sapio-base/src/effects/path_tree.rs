@@ -0,0 +1,108 @@
+// Copyright Judica, Inc 2022
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+//  License, v. 2.0. If a copy of the MPL was not distributed with this
+//  file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! A nested view of the paths in an effect database
+use super::{MapEffectDB, PathFragment};
+use crate::serialization_helpers::SArc;
+#[cfg(feature = "schemars")]
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// # Path Tree
+/// The paths of a [`MapEffectDB`] grouped by shared prefix, see
+/// [`MapEffectDB::to_tree`]. Each node is reached from its parent by one
+/// fragment and holds the effects at exactly its path, if any.
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "schemars", derive(JsonSchema))]
+pub struct PathTree {
+    /// # Effects
+    /// The effects at this node's path
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub effects: BTreeMap<SArc<String>, serde_json::Value>,
+    /// # Children
+    /// The nodes one fragment below this one
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub children: BTreeMap<PathFragment, PathTree>,
+}
+
+impl PathTree {
+    /// the node at `frags` (listed root first) below this one, if any
+    pub fn get(&self, frags: &[PathFragment]) -> Option<&PathTree> {
+        frags.iter().try_fold(self, |node, f| node.children.get(f))
+    }
+}
+
+impl MapEffectDB {
+    /// Group the DB's paths into a [`PathTree`] by shared prefix. The
+    /// returned node is the (empty) path every path extends.
+    pub fn to_tree(&self) -> PathTree {
+        let mut tree = PathTree::default();
+        for (path, m) in self.effects.iter() {
+            let node = Vec::from(path.0.as_ref().clone())
+                .into_iter()
+                .fold(&mut tree, |node, f| node.children.entry(f).or_default());
+            node.effects
+                .extend(m.iter().map(|(k, v)| (k.clone(), v.clone())));
+        }
+        tree
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::effects::{EditableMapEffectDB, EffectPath};
+    use serde_json::json;
+    use std::convert::TryFrom;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_to_tree() {
+        let at = |s: &str| SArc(Arc::new(EffectPath::try_from(s).unwrap()));
+        let arg =
+            |v: serde_json::Value| std::iter::once((SArc(Arc::new("x".to_string())), v)).collect();
+        let db: MapEffectDB = EditableMapEffectDB {
+            effects: vec![
+                (at("@root/@action/bump/@suggested"), arg(json!(1))),
+                (at("@root/@action/pay/@suggested"), arg(json!(2))),
+                (at("@root/@action/pay/@suggested/#0"), arg(json!(3))),
+                (at("@root/@finish_fn"), arg(json!(4))),
+            ]
+            .into_iter()
+            .collect(),
+            empty: Default::default(),
+            provenance: Default::default(),
+        }
+        .into();
+        let tree = db.to_tree();
+        let frags = |s: &str| Vec::from(EffectPath::try_from(s).unwrap());
+        let keys = |t: &PathTree| -> Vec<String> {
+            t.children.keys().cloned().map(String::from).collect()
+        };
+        assert!(tree.effects.is_empty());
+        assert_eq!(keys(&tree), vec!["@root"]);
+        assert_eq!(
+            keys(tree.get(&frags("@root")).unwrap()),
+            vec!["@action", "@finish_fn"]
+        );
+        assert_eq!(
+            keys(tree.get(&frags("@root/@action")).unwrap()),
+            vec!["bump", "pay"]
+        );
+        let pay = tree.get(&frags("@root/@action/pay/@suggested")).unwrap();
+        assert_eq!(pay.effects.values().collect::<Vec<_>>(), vec![&json!(2)]);
+        assert_eq!(keys(pay), vec!["#0"]);
+        let leaf = pay.get(&frags("#0")).unwrap();
+        assert_eq!(leaf.effects.values().collect::<Vec<_>>(), vec![&json!(3)]);
+        assert!(leaf.children.is_empty());
+        assert!(tree.get(&frags("@root/@action/nope")).is_none());
+        assert_eq!(
+            serde_json::to_value(tree.get(&frags("@root/@finish_fn")).unwrap()).unwrap(),
+            json!({"effects": {"x": 4}})
+        );
+    }
+}
@@ -5,22 +5,26 @@
 //  file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
 //! general non-parameter compilation state required by all contracts
+#[cfg(feature = "schemars")]
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::marker::PhantomData;
 use std::sync::Arc;
 /// Used to Build a Shared Path for all children of a given context.
-#[derive(
-    Serialize, Deserialize, JsonSchema, Debug, Clone, Hash, PartialEq, PartialOrd, Ord, Eq,
-)]
+///
+/// A ReversePath may be empty (see [`ReversePath::empty`]), in which case it
+/// has no elements. An empty path is never stored as the `past` of another
+/// path, so each path has exactly one representation.
+#[derive(Serialize, Deserialize, Debug, Clone, Hash, PartialEq, PartialOrd, Ord, Eq)]
+#[cfg_attr(feature = "schemars", derive(JsonSchema))]
 #[serde(try_from = "Y")]
 #[serde(into = "Y")]
 #[serde(
-    bound = "T: Clone, Y: Serialize + for<'d> Deserialize<'d> + JsonSchema + std::fmt::Debug + Clone, Y: Serialize + From<Self>, Self: TryFrom<Y>, <Self as TryFrom<Y>>::Error : std::fmt::Display"
+    bound = "T: Clone, Y: Serialize + for<'d> Deserialize<'d> + std::fmt::Debug + Clone, Y: Serialize + From<Self>, Self: TryFrom<Y>, <Self as TryFrom<Y>>::Error : std::fmt::Display"
 )]
 pub struct ReversePath<T, Y = String> {
     past: Option<Arc<ReversePath<T, Y>>>,
-    this: T,
+    this: Option<T>,
     _pd: PhantomData<Y>,
 }
 
@@ -36,7 +40,7 @@ impl<'a, T, Y> Iterator for RPI<'a, T, Y> {
 
     // next() is the only required method
     fn next(&mut self) -> Option<Self::Item> {
-        let ret = self.inner.map(|x| &x.this);
+        let ret = self.inner.and_then(|x| x.this.as_ref());
         match self.inner.map(|x| x.past.as_ref()) {
             Some(Some(x)) => {
                 self.inner = Some(x);
@@ -74,11 +78,16 @@ impl<T: Clone, Y> From<T> for ReversePath<T, Y> {
     fn from(this: T) -> Self {
         ReversePath {
             past: None,
-            this,
+            this: Some(this),
             _pd: Default::default(),
         }
     }
 }
+impl<T, Y> Default for ReversePath<T, Y> {
+    fn default() -> Self {
+        Self::empty()
+    }
+}
 /// Helper for making a ReversePath.
 pub struct MkReversePath<T, Y>(Option<Arc<ReversePath<T, Y>>>);
 impl<T, Y> MkReversePath<T, Y> {
@@ -109,15 +118,54 @@ impl<T, Y> ReversePath<T, Y> {
     /// Add an element to a ReversePath and do not wrap in Arc
     pub fn push_owned(v: Option<Arc<ReversePath<T, Y>>>, s: T) -> ReversePath<T, Y> {
         ReversePath::<T, Y> {
-            past: v,
-            this: s,
+            past: v.filter(|p| !p.is_empty()),
+            this: Some(s),
+            _pd: Default::default(),
+        }
+    }
+    /// The path with no elements
+    pub fn empty() -> Self {
+        ReversePath {
+            past: None,
+            this: None,
             _pd: Default::default(),
         }
     }
+    /// Is this the path with no elements?
+    pub fn is_empty(&self) -> bool {
+        self.this.is_none()
+    }
+    /// the number of elements in the path
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
     /// iterate over a reversepath
     pub fn iter(&self) -> RPI<'_, T, Y> {
         RPI { inner: Some(self) }
     }
+    /// Does `self` begin with every element of `prefix`?
+    /// Every path starts with the empty path.
+    pub fn starts_with(&self, prefix: &ReversePath<T, Y>) -> bool
+    where
+        T: PartialEq,
+    {
+        let (n, m) = (self.len(), prefix.len());
+        n >= m && self.iter().skip(n - m).eq(prefix.iter())
+    }
+}
+impl<T: Clone, Y: Clone> ReversePath<T, Y> {
+    /// The path without its last element, or None if the path is empty.
+    pub fn parent(&self) -> Option<ReversePath<T, Y>> {
+        if self.is_empty() {
+            return None;
+        }
+        Some(
+            self.past
+                .as_ref()
+                .map(|p| p.as_ref().clone())
+                .unwrap_or_default(),
+        )
+    }
 }
 
 #[cfg(test)]
@@ -181,6 +229,32 @@ mod test {
         assert_eq!(a, b);
     }
     #[test]
+    fn test_empty() {
+        let e = ReversePath::<i64, Vec<i64>>::empty();
+        assert!(e.is_empty());
+        assert_eq!(e, Default::default());
+        assert_eq!(e.len(), 0);
+        assert_eq!(Vec::from(e.clone()), Vec::<i64>::new());
+        assert_eq!(e.parent(), None);
+        // pushing onto an empty path is the same as starting a new one
+        let a = ReversePath::push(Some(Arc::new(e.clone())), 1i64);
+        assert_eq!(a.as_ref(), &ReversePath::from(1i64));
+        assert_eq!(a.parent(), Some(e.clone()));
+        assert!(a.starts_with(&e));
+        assert!(e.starts_with(&e));
+        assert!(!e.starts_with(&a));
+    }
+    #[test]
+    fn test_starts_with() {
+        let a = ReversePath::<i64, Vec<i64>>::push(Some(ReversePath::push(None, 1i64)), 5);
+        let b = ReversePath::push(None, 1i64);
+        assert!(a.starts_with(&b));
+        assert!(a.starts_with(&a));
+        assert!(!b.starts_with(&a));
+        assert!(!a.starts_with(&ReversePath::from(5)));
+        assert_eq!(a.parent().as_ref(), Some(b.as_ref()));
+    }
+    #[test]
     fn test_neq() {
         assert_ne!(
             ReversePath::<i64, Vec<i64>>::push(Some(ReversePath::push(None, 1i64)), 5,),
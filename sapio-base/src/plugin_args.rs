@@ -6,11 +6,13 @@
 
 //! arguments for passing into a sapio module
 use crate::effects::MapEffectDB;
+#[cfg(feature = "schemars")]
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 /// a remote derivation for the network definitions
-#[derive(Serialize, Deserialize, JsonSchema)]
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "schemars", derive(JsonSchema))]
 #[serde(remote = "bitcoin::Network")]
 pub enum NetworkDef {
     /// Classic Bitcoin
@@ -25,7 +27,8 @@ pub enum NetworkDef {
 
 /// # Arguments For Creating this Contract
 /// Provide this information to create an instance of a contract
-#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[cfg_attr(feature = "schemars", derive(JsonSchema))]
 pub struct CreateArgs<S> {
     /// # The Main Contract Arguments
     pub arguments: S,
@@ -35,13 +38,14 @@ pub struct CreateArgs<S> {
 }
 
 /// # Contextual Arguments For Creating this Contract
-#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[cfg_attr(feature = "schemars", derive(JsonSchema))]
 pub struct ContextualArguments {
     #[serde(with = "NetworkDef")]
     /// # The Network the contract should be created for.
     pub network: bitcoin::Network,
     #[serde(with = "bitcoin::util::amount::serde::as_sat")]
-    #[schemars(with = "u64")]
+    #[cfg_attr(feature = "schemars", schemars(with = "u64"))]
     /// # The Amount of Funds Available to the Contract as Bitcoin.
     pub amount: bitcoin::util::amount::Amount,
 
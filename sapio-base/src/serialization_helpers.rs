@@ -5,16 +5,16 @@
 //  file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
 //! Helpers for serializing Arcs
+#[cfg(feature = "schemars")]
 use schemars::JsonSchema;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::borrow::Borrow;
 use std::sync::Arc;
 
 /// Serializable Arc Type
-#[derive(
-    Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq, PartialOrd, Eq, Hash, Ord,
-)]
-#[serde(bound = "T: Serialize + for<'d> Deserialize<'d> + JsonSchema + std::fmt::Debug + Clone ")]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, PartialOrd, Eq, Hash, Ord)]
+#[cfg_attr(feature = "schemars", derive(JsonSchema))]
+#[serde(bound = "T: Serialize + for<'d> Deserialize<'d> + std::fmt::Debug + Clone ")]
 #[serde(transparent)]
 pub struct SArc<T>(
     #[serde(serialize_with = "serializer")]
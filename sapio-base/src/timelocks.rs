@@ -5,6 +5,7 @@
 //  file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
 use super::Clause;
+#[cfg(feature = "schemars")]
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::convert::TryFrom;
@@ -41,29 +42,35 @@ pub mod type_tags {
     }
     use super::*;
     /// Type Tag for Realtive
-    #[derive(JsonSchema, Serialize, Deserialize, Copy, Clone, PartialOrd, Ord, Eq, PartialEq)]
+    #[derive(Serialize, Deserialize, Copy, Clone, PartialOrd, Ord, Eq, PartialEq)]
+    #[cfg_attr(feature = "schemars", derive(JsonSchema))]
     pub struct Rel;
     /// Type Tag for Absolute
-    #[derive(JsonSchema, Serialize, Deserialize, Copy, Clone, PartialOrd, Ord, Eq, PartialEq)]
+    #[derive(Serialize, Deserialize, Copy, Clone, PartialOrd, Ord, Eq, PartialEq)]
+    #[cfg_attr(feature = "schemars", derive(JsonSchema))]
     pub struct Abs;
     /// Type Tag for Height
-    #[derive(JsonSchema, Serialize, Deserialize, Copy, Clone, PartialOrd, Ord, Eq, PartialEq)]
+    #[derive(Serialize, Deserialize, Copy, Clone, PartialOrd, Ord, Eq, PartialEq)]
+    #[cfg_attr(feature = "schemars", derive(JsonSchema))]
     pub struct Height;
     /// Type Tag for Median Time Passed
-    #[derive(JsonSchema, Serialize, Deserialize, Copy, Clone, PartialOrd, Ord, Eq, PartialEq)]
+    #[derive(Serialize, Deserialize, Copy, Clone, PartialOrd, Ord, Eq, PartialEq)]
+    #[cfg_attr(feature = "schemars", derive(JsonSchema))]
     pub struct MTP;
 }
 use type_tags::*;
 
 /// LockTime represents either a nLockTime or a Sequence field.
 /// They are represented generically in the same type
-#[derive(JsonSchema, Serialize, Deserialize, Copy, Clone, PartialOrd, Ord, Eq, PartialEq)]
+#[derive(Serialize, Deserialize, Copy, Clone, PartialOrd, Ord, Eq, PartialEq)]
+#[cfg_attr(feature = "schemars", derive(JsonSchema))]
 #[serde(transparent)]
 pub struct LockTime<RelOrAbs: Absolutivity, HeightOrTime: TimeType>(
     u32,
     #[serde(skip)] PhantomData<(RelOrAbs, HeightOrTime)>,
 );
-#[derive(JsonSchema, Serialize, Deserialize, Copy, Clone, PartialOrd, Ord, Eq, PartialEq)]
+#[derive(Serialize, Deserialize, Copy, Clone, PartialOrd, Ord, Eq, PartialEq)]
+#[cfg_attr(feature = "schemars", derive(JsonSchema))]
 /// # Any Relative Time Lock
 /// Represents a type which can be either type of relative lock
 pub enum AnyRelTimeLock {
@@ -75,7 +82,8 @@ pub enum AnyRelTimeLock {
     RT(RelTime),
 }
 
-#[derive(JsonSchema, Serialize, Deserialize, Copy, Clone, PartialOrd, Ord, Eq, PartialEq)]
+#[derive(Serialize, Deserialize, Copy, Clone, PartialOrd, Ord, Eq, PartialEq)]
+#[cfg_attr(feature = "schemars", derive(JsonSchema))]
 /// # Any Absolute Time Lock
 /// Represents a type which can be either type of absolute lock
 pub enum AnyAbsTimeLock {
@@ -86,7 +94,8 @@ pub enum AnyAbsTimeLock {
     /// in unix time stamp since epoch
     AT(AbsTime),
 }
-#[derive(JsonSchema, Serialize, Deserialize, Copy, Clone)]
+#[derive(Serialize, Deserialize, Copy, Clone)]
+#[cfg_attr(feature = "schemars", derive(JsonSchema))]
 /// # Any Time Lock (Relative, Absolute) x (Height, Time)
 /// Represents a type which can be any type of lock
 pub enum AnyTimeLock {
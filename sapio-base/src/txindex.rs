@@ -19,7 +19,7 @@ pub enum TxIndexError {
     /// TXID exists, but the vout index was too high
     IndexTooHigh(u32),
     /// Error in the Rpc System
-    RpcError(Box<dyn std::error::Error>),
+    RpcError(Box<dyn std::error::Error + Send + Sync>),
 }
 impl std::error::Error for TxIndexError {}
 
@@ -0,0 +1,92 @@
+// Copyright Judica, Inc 2022
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+//  License, v. 2.0. If a copy of the MPL was not distributed with this
+//  file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Compares allocations made by `PathParser` against `TryFrom<&str>` for
+//! repeated parses. Kept in its own test binary so that the counting
+//! allocator only sees this test.
+use sapio_base::effects::{EffectPath, PathParser};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::convert::TryFrom;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+struct Counting;
+static ALLOCS: AtomicUsize = AtomicUsize::new(0);
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, l: Layout) -> *mut u8 {
+        ALLOCS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(l)
+    }
+    unsafe fn dealloc(&self, p: *mut u8, l: Layout) {
+        System.dealloc(p, l)
+    }
+}
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+fn allocs_during(f: impl FnOnce()) -> usize {
+    let start = ALLOCS.load(Ordering::Relaxed);
+    f();
+    ALLOCS.load(Ordering::Relaxed) - start
+}
+
+#[test]
+fn path_parser_allocates_less() {
+    const N: usize = 1000;
+    let paths = [
+        "@root/@action/pay/@next/@default_effect/#0",
+        "@root/@action/pay/@next/@default_effect/#1",
+    ];
+    let try_from = allocs_during(|| {
+        for i in 0..N {
+            let p = EffectPath::try_from(paths[i % 2]).unwrap();
+            std::hint::black_box(p);
+        }
+    });
+    let mut parser = PathParser::new();
+    let mut out = EffectPath::empty();
+    let parser_allocs = allocs_during(|| {
+        for i in 0..N {
+            parser.parse_into(paths[i % 2], &mut out).unwrap();
+            std::hint::black_box(&out);
+        }
+    });
+    // only the differing last fragment is allocated on each parse, beyond
+    // building the shared prefix (its nodes and the parser's list of them)
+    // the first time
+    let prefix = paths[0].split('/').count();
+    assert!(
+        parser_allocs <= N + 2 * prefix,
+        "PathParser made {} allocations for {} parses",
+        parser_allocs,
+        N
+    );
+    assert!(parser_allocs * 4 < try_from);
+}
+
+#[test]
+fn path_parser_builtin_comparison_does_not_allocate() {
+    const N: usize = 1000;
+    // the last parse left `@next` where this one has the name `next`, so
+    // each parse compares a builtin against a name before replacing it
+    let paths = ["@root/@action/pay/@next/#0", "@root/@action/pay/next/#0"];
+    let mut parser = PathParser::new();
+    let mut out = EffectPath::empty();
+    let parser_allocs = allocs_during(|| {
+        for i in 0..N {
+            parser.parse_into(paths[i % 2], &mut out).unwrap();
+            std::hint::black_box(&out);
+        }
+    });
+    // a node each for the last two fragments, plus the name and its `Arc`
+    // for every other parse
+    let prefix = paths[0].split('/').count();
+    assert!(
+        parser_allocs <= 3 * N + 2 * prefix,
+        "PathParser made {} allocations for {} parses",
+        parser_allocs,
+        N
+    );
+}
@@ -45,3 +45,14 @@ impl SupportedDescriptors {
         }
     }
 }
+
+/// Renders the descriptor (with its checksum), e.g. for import into a
+/// watch-only wallet
+impl std::fmt::Display for SupportedDescriptors {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SupportedDescriptors::Pk(p) => p.fmt(f),
+            SupportedDescriptors::XOnly(x) => x.fmt(f),
+        }
+    }
+}
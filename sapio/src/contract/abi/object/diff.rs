@@ -0,0 +1,186 @@
+// Copyright Judica, Inc 2022
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+//  License, v. 2.0. If a copy of the MPL was not distributed with this
+//  file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! structural comparison of compiled Objects
+use crate::contract::object::Object;
+use crate::contract::{Compilable, CompilationError, Context};
+use serde_json::Value;
+use std::fmt;
+
+/// A single point of disagreement between two [`Object`]s
+#[derive(Debug, Clone, PartialEq)]
+pub struct Difference {
+    /// a JSON Pointer (RFC 6901) into the serialized [`Object`]
+    pub pointer: String,
+    /// the value in the expected object, `None` if it is absent there
+    pub expected: Option<Value>,
+    /// the value in the actual object, `None` if it is absent there
+    pub actual: Option<Value>,
+}
+
+/// Every difference between two [`Object`]s, see [`Object::diff`]
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ObjectDiff {
+    /// the differences, in the order they appear in the serialized object
+    pub differences: Vec<Difference>,
+}
+
+impl ObjectDiff {
+    /// true if the two objects were identical
+    pub fn is_empty(&self) -> bool {
+        self.differences.is_empty()
+    }
+}
+
+impl fmt::Display for ObjectDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let show = |v: &Option<Value>| v.as_ref().map_or("<absent>".into(), |v| v.to_string());
+        for d in &self.differences {
+            writeln!(
+                f,
+                "{}: expected {}, found {}",
+                d.pointer,
+                show(&d.expected),
+                show(&d.actual)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ObjectDiff {}
+
+fn escape(key: &str) -> String {
+    key.replace('~', "~0").replace('/', "~1")
+}
+
+fn diff_values(pointer: String, expected: &Value, actual: &Value, out: &mut Vec<Difference>) {
+    match (expected, actual) {
+        (Value::Object(e), Value::Object(a)) => {
+            for (k, ev) in e.iter() {
+                let p = format!("{}/{}", pointer, escape(k));
+                match a.get(k) {
+                    Some(av) => diff_values(p, ev, av, out),
+                    None => out.push(Difference {
+                        pointer: p,
+                        expected: Some(ev.clone()),
+                        actual: None,
+                    }),
+                }
+            }
+            for (k, av) in a.iter().filter(|(k, _)| !e.contains_key(*k)) {
+                out.push(Difference {
+                    pointer: format!("{}/{}", pointer, escape(k)),
+                    expected: None,
+                    actual: Some(av.clone()),
+                })
+            }
+        }
+        (Value::Array(e), Value::Array(a)) => {
+            for i in 0..e.len().max(a.len()) {
+                let p = format!("{}/{}", pointer, i);
+                match (e.get(i), a.get(i)) {
+                    (Some(ev), Some(av)) => diff_values(p, ev, av, out),
+                    (ev, av) => out.push(Difference {
+                        pointer: p,
+                        expected: ev.cloned(),
+                        actual: av.cloned(),
+                    }),
+                }
+            }
+        }
+        (e, a) if e != a => out.push(Difference {
+            pointer,
+            expected: Some(e.clone()),
+            actual: Some(a.clone()),
+        }),
+        _ => {}
+    }
+}
+
+impl Object {
+    /// Compare this object (the expected one) against `actual`, reporting
+    /// every field of the serialized form that differs.
+    pub fn diff(&self, actual: &Object) -> ObjectDiff {
+        let mut differences = vec![];
+        match (serde_json::to_value(self), serde_json::to_value(actual)) {
+            (Ok(e), Ok(a)) => diff_values(String::new(), &e, &a, &mut differences),
+            // Objects always serialize, but never report a failure as a match
+            (e, a) => differences.push(Difference {
+                pointer: String::new(),
+                expected: e.ok(),
+                actual: a.ok(),
+            }),
+        }
+        ObjectDiff { differences }
+    }
+}
+
+/// Recompile `source` and check that it produces exactly `expected`, e.g.
+/// to confirm that a received [`Object`] was honestly built from a contract.
+///
+/// The outer error is a failure to compile `source` at all, the inner one
+/// describes how the compiled object disagrees with `expected`.
+pub fn verify_against<C: Compilable>(
+    source: &C,
+    ctx: Context,
+    expected: &Object,
+) -> Result<Result<(), ObjectDiff>, CompilationError> {
+    let actual = source.compile(ctx)?;
+    let diff = expected.diff(&actual);
+    Ok(if diff.is_empty() { Ok(()) } else { Err(diff) })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate as sapio;
+    use crate::contract::test_helpers::{ctx, key};
+    use crate::contract::Contract;
+    use crate::*;
+    use bitcoin::util::amount::Amount;
+    use bitcoin::XOnlyPublicKey;
+
+    struct Pay {
+        to: XOnlyPublicKey,
+    }
+    impl Pay {
+        #[then]
+        fn send(self, ctx: sapio::Context) {
+            ctx.template()
+                .add_output(Amount::from_sat(1000), &self.to, None)?
+                .into()
+        }
+    }
+    impl Contract for Pay {
+        declare! {then, Self::send}
+        declare! {non updatable}
+    }
+
+    #[test]
+    fn test_verify_against() {
+        let source = Pay { to: key(1) };
+        let honest = source.compile(ctx(5000)).unwrap();
+        assert_eq!(verify_against(&source, ctx(5000), &honest).unwrap(), Ok(()));
+
+        let mut tampered = honest.clone();
+        tampered
+            .metadata
+            .extra
+            .insert("label".into(), "evil".into());
+        let diff = verify_against(&source, ctx(5000), &tampered)
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(
+            diff.differences,
+            vec![Difference {
+                pointer: "/metadata/label".into(),
+                expected: Some("evil".into()),
+                actual: None,
+            }]
+        );
+    }
+}
@@ -29,7 +29,7 @@ pub enum ObjectError {
     /// OpReturn Too Long
     OpReturnTooLong,
     /// The Error was for an unknown/unhandled reason
-    Custom(Box<dyn std::error::Error>),
+    Custom(Box<dyn std::error::Error + Send + Sync>),
 }
 impl std::error::Error for ObjectError {}
 impl From<TaprootBuilderError> for ObjectError {
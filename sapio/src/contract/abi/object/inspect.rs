@@ -0,0 +1,1702 @@
+// Copyright Judica, Inc 2022
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+//  License, v. 2.0. If a copy of the MPL was not distributed with this
+//  file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! read-only introspection of a compiled Object
+use crate::contract::compiler::nums_key;
+use crate::contract::object::GuardKind;
+use crate::contract::object::Object;
+use crate::contract::object::SupportedDescriptors;
+use crate::contract::warnings::CompilationWarning;
+use crate::contract::CompilationError;
+use crate::template::Template;
+use crate::util::json_schema;
+use ::miniscript::descriptor::WshInner;
+use ::miniscript::{
+    Descriptor, DescriptorTrait, Miniscript, MiniscriptKey, ScriptContext, Terminal,
+};
+use bitcoin::hashes::{sha256, Hash};
+use bitcoin::util::amount::Amount;
+use bitcoin::XOnlyPublicKey;
+use sapio_base::effects::EffectPath;
+use sapio_base::effects::PathFragment;
+use sapio_base::effects::{EditableMapEffectDB, EffectDB, EffectDBError, MapEffectDB};
+use sapio_base::serialization_helpers::SArc;
+use sapio_base::timelocks::{AbsHeight, AbsTime, AnyAbsTimeLock, AnyTimeLock, RelHeight, RelTime};
+use sapio_base::Clause;
+use serde_json::Value;
+use std::collections::{btree_map, BTreeMap, BTreeSet, VecDeque};
+use std::convert::TryFrom;
+use std::sync::Arc;
+
+/// Size metrics for a compiled [`Object`], see [`Object::stats`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CompileStats {
+    /// the number of templates in the object and every object it creates
+    pub templates: usize,
+    /// the most templates on any path from the root to a leaf
+    pub max_depth: usize,
+    /// the total size, in bytes, of every script (e.g. taproot leaf) in the
+    /// object and every object it creates
+    pub script_weight: usize,
+    /// how many of each kind of fragment appear in the templates' known paths,
+    /// keyed by the fragment's sigil ("#" for branches, "" for names)
+    pub fragments: BTreeMap<&'static str, usize>,
+}
+
+fn fragment_kind(f: &PathFragment) -> &'static str {
+    match f {
+        PathFragment::Root => "@root",
+        PathFragment::Cloned => "@cloned",
+        PathFragment::Action => "@action",
+        PathFragment::FinishFn => "@finish_fn",
+        PathFragment::CondCompIf => "@cond_comp_if",
+        PathFragment::Guard => "@guard",
+        PathFragment::Next => "@next",
+        PathFragment::Suggested => "@suggested",
+        PathFragment::DefaultEffect => "@default_effect",
+        PathFragment::Effects => "@effects",
+        PathFragment::Metadata => "@metadata",
+        PathFragment::Branch(_) => "#",
+        PathFragment::Leaf(_) => "@leaf",
+        PathFragment::Named(_) => "",
+    }
+}
+
+/// the weight of the largest witness that satisfies `d`
+fn satisfaction_weight(d: &SupportedDescriptors) -> usize {
+    match d {
+        SupportedDescriptors::XOnly(d) => d.max_satisfaction_weight().unwrap_or(0),
+        SupportedDescriptors::Pk(d) => d.max_satisfaction_weight().unwrap_or(0),
+    }
+}
+
+fn script_weight(d: &SupportedDescriptors) -> usize {
+    match d {
+        SupportedDescriptors::XOnly(Descriptor::Tr(tr)) => {
+            tr.iter_scripts().map(|(_, ms)| ms.script_size()).sum()
+        }
+        SupportedDescriptors::XOnly(d) => d.explicit_script().map(|s| s.len()).unwrap_or(0),
+        SupportedDescriptors::Pk(d) => d.explicit_script().map(|s| s.len()).unwrap_or(0),
+    }
+}
+
+/// `n` (an nSequence or `OP_CSV` argument) as a relative timelock, unless
+/// it is disabled (BIP 68) or zero
+fn relative_lock(n: u32) -> Option<AnyTimeLock> {
+    let v = (n & 0xffff) as u16;
+    if n & (1 << 31) != 0 || v == 0 {
+        None
+    } else if n & (1 << 22) != 0 {
+        Some(AnyTimeLock::R(RelTime::from(v).into()))
+    } else {
+        Some(AnyTimeLock::R(RelHeight::from(v).into()))
+    }
+}
+
+/// `n` (an nLockTime or `OP_CLTV` argument) as an absolute timelock, unless
+/// it is zero
+fn absolute_lock(n: u32) -> Option<AnyTimeLock> {
+    if n == 0 {
+        return None;
+    }
+    AbsHeight::try_from(n)
+        .map(AnyAbsTimeLock::from)
+        .or_else(|_| AbsTime::try_from(n).map(AnyAbsTimeLock::from))
+        .ok()
+        .map(AnyTimeLock::A)
+}
+
+/// every `OP_CSV` / `OP_CLTV` in `ms`
+fn miniscript_timelocks<Pk: MiniscriptKey, Ctx: ScriptContext>(
+    ms: &Miniscript<Pk, Ctx>,
+) -> Vec<AnyTimeLock> {
+    ms.iter()
+        .filter_map(|node| match node.node {
+            Terminal::Older(n) => relative_lock(n),
+            Terminal::After(n) => absolute_lock(n),
+            _ => None,
+        })
+        .collect()
+}
+
+/// every `OP_CSV` / `OP_CLTV` in the scripts of `d`
+fn script_timelocks(d: &SupportedDescriptors) -> Vec<AnyTimeLock> {
+    match d {
+        SupportedDescriptors::XOnly(Descriptor::Tr(tr)) => tr
+            .iter_scripts()
+            .flat_map(|(_, ms)| miniscript_timelocks(ms))
+            .collect(),
+        SupportedDescriptors::XOnly(Descriptor::Wsh(w)) => match w.as_inner() {
+            WshInner::Ms(ms) => miniscript_timelocks(ms),
+            WshInner::SortedMulti(_) => vec![],
+        },
+        SupportedDescriptors::Pk(Descriptor::Wsh(w)) => match w.as_inner() {
+            WshInner::Ms(ms) => miniscript_timelocks(ms),
+            WshInner::SortedMulti(_) => vec![],
+        },
+        _ => vec![],
+    }
+}
+
+/// every way to combine one condition set from each of `a` and `b`
+fn dnf_and(a: Vec<Vec<Clause>>, b: &[Vec<Clause>]) -> Vec<Vec<Clause>> {
+    a.into_iter()
+        .flat_map(|x| {
+            b.iter().map(move |y| {
+                let mut both = x.clone();
+                both.extend(y.iter().cloned());
+                both
+            })
+        })
+        .collect()
+}
+
+/// every way to satisfy `k` of `subs`, e.g. a `thresh` or `multi`
+fn dnf_thresh(k: usize, subs: &[Vec<Vec<Clause>>]) -> Vec<Vec<Clause>> {
+    match (k, subs.split_first()) {
+        (0, _) => vec![vec![]],
+        (_, None) => vec![],
+        (k, Some((first, rest))) => {
+            let mut with_first = dnf_and(first.clone(), &dnf_thresh(k - 1, rest));
+            with_first.extend(dnf_thresh(k, rest));
+            with_first
+        }
+    }
+}
+
+/// `ms` in disjunctive normal form: each inner vec is one set of clauses
+/// that together satisfy it. Keys known only by hash are resolved against
+/// `keys`, and paths needing an unknown one are dropped.
+fn miniscript_dnf<Ctx: ScriptContext>(
+    ms: &Miniscript<XOnlyPublicKey, Ctx>,
+    keys: &[XOnlyPublicKey],
+) -> Vec<Vec<Clause>> {
+    let one = |c: Clause| vec![vec![c]];
+    let dnf = |m: &Miniscript<XOnlyPublicKey, Ctx>| miniscript_dnf(m, keys);
+    match &ms.node {
+        Terminal::True => vec![vec![]],
+        Terminal::False => vec![],
+        Terminal::PkK(k) => one(Clause::Key(*k)),
+        Terminal::PkH(h) => keys
+            .iter()
+            .filter(|k| k.to_pubkeyhash() == *h)
+            .map(|k| vec![Clause::Key(*k)])
+            .take(1)
+            .collect(),
+        Terminal::After(n) => one(Clause::After(*n)),
+        Terminal::Older(n) => one(Clause::Older(*n)),
+        Terminal::Sha256(h) => one(Clause::Sha256(*h)),
+        Terminal::Hash256(h) => one(Clause::Hash256(*h)),
+        Terminal::Ripemd160(h) => one(Clause::Ripemd160(*h)),
+        Terminal::Hash160(h) => one(Clause::Hash160(*h)),
+        Terminal::TxTemplate(h) => one(Clause::TxTemplate(*h)),
+        Terminal::Alt(m)
+        | Terminal::Swap(m)
+        | Terminal::Check(m)
+        | Terminal::DupIf(m)
+        | Terminal::Verify(m)
+        | Terminal::NonZero(m)
+        | Terminal::ZeroNotEqual(m) => dnf(m),
+        Terminal::AndV(a, b) | Terminal::AndB(a, b) => dnf_and(dnf(a), &dnf(b)),
+        Terminal::AndOr(a, b, c) => {
+            let mut v = dnf_and(dnf(a), &dnf(b));
+            v.extend(dnf(c));
+            v
+        }
+        Terminal::OrB(a, b) | Terminal::OrD(a, b) | Terminal::OrC(a, b) | Terminal::OrI(a, b) => {
+            let mut v = dnf(a);
+            v.extend(dnf(b));
+            v
+        }
+        Terminal::Thresh(k, subs) => {
+            let subs: Vec<_> = subs.iter().map(|m| dnf(m)).collect();
+            dnf_thresh(*k, &subs)
+        }
+        Terminal::Multi(k, pks) | Terminal::MultiA(k, pks) => {
+            let subs: Vec<_> = pks.iter().map(|pk| one(Clause::Key(*pk))).collect();
+            dnf_thresh(*k, &subs)
+        }
+    }
+}
+
+type TemplateValues<'a> = std::iter::Chain<
+    btree_map::Values<'a, sha256::Hash, Template>,
+    btree_map::Values<'a, sha256::Hash, Template>,
+>;
+
+/// An object reached by [`Object::objects`]
+#[derive(Debug, Clone, Copy)]
+pub struct ObjectVisit<'a> {
+    /// the object
+    pub object: &'a Object,
+    /// how many templates lie between it and the object walked from
+    pub depth: usize,
+    /// the amount of the output creating it, or None for the object walked
+    /// from
+    pub amount: Option<Amount>,
+}
+
+/// Iterator over an [`Object`] and every object it creates, see
+/// [`Object::objects`]
+pub struct ObjectsIter<'a> {
+    pending: Vec<ObjectVisit<'a>>,
+}
+
+impl<'a> Iterator for ObjectsIter<'a> {
+    type Item = ObjectVisit<'a>;
+    fn next(&mut self) -> Option<Self::Item> {
+        let visit = self.pending.pop()?;
+        for t in visit.object.templates() {
+            self.pending.extend(t.outputs.iter().map(|o| ObjectVisit {
+                object: &o.contract,
+                depth: visit.depth + 1,
+                amount: Some(o.amount),
+            }));
+        }
+        Some(visit)
+    }
+}
+
+/// Iterator over every template of an [`Object`] and the objects it
+/// creates, see [`Object::templates_iter`]
+pub struct TemplatesIter<'a> {
+    current: Option<(&'a Object, TemplateValues<'a>)>,
+    objects: ObjectsIter<'a>,
+}
+
+impl<'a> Iterator for TemplatesIter<'a> {
+    type Item = (Option<EffectPath>, &'a Template);
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((obj, templates)) = self.current.as_mut() {
+                if let Some(t) = templates.next() {
+                    return Some((obj.path_of(t), t));
+                }
+            }
+            let obj = self.objects.next()?.object;
+            self.current = Some((obj, obj.templates()));
+        }
+    }
+}
+
+impl Object {
+    /// Lazily visit this contract and every contract it creates along any
+    /// branch, depth first, each with how deep it is and the amount it is
+    /// created with. Every other walk over the contracts an object creates
+    /// is built on this one.
+    pub fn objects(&self) -> ObjectsIter<'_> {
+        ObjectsIter {
+            pending: vec![ObjectVisit {
+                object: self,
+                depth: 0,
+                amount: None,
+            }],
+        }
+    }
+
+    /// Lazily visit every template this contract (and any contract it
+    /// creates) can produce, with the path it was built at (None if it is
+    /// not known, see [`CompilationError::UnknownTemplatePath`]). Unlike
+    /// [`Object::template_manifest`] nothing is collected up front, so
+    /// tools streaming a large contract to disk or the network can process
+    /// one template at a time.
+    pub fn templates_iter(&self) -> TemplatesIter<'_> {
+        TemplatesIter {
+            current: None,
+            objects: self.objects(),
+        }
+    }
+
+    /// this object's own templates, committed then suggested
+    fn templates(&self) -> TemplateValues<'_> {
+        self.ctv_to_tx.values().chain(self.suggested_txs.values())
+    }
+
+    /// the path a template was built at, if it recorded one (templates of
+    /// an `Object` deserialized without them do not)
+    fn path_of(&self, t: &Template) -> Option<EffectPath> {
+        t.path.as_ref().map(|p| p.0.as_ref().clone())
+    }
+
+    /// [`Object::path_of`], or [`CompilationError::UnknownTemplatePath`]
+    fn known_path_of(t: (Option<EffectPath>, &Template)) -> Result<EffectPath, CompilationError> {
+        match t {
+            (Some(path), _) => Ok(path),
+            (None, t) => Err(CompilationError::UnknownTemplatePath { hash: t.hash() }),
+        }
+    }
+
+    /// A flat list of every template this contract (and any contract it
+    /// creates) can produce, with the path it was built at, its CTV hash,
+    /// and the amount it requires. Fails if any template's path is not
+    /// known.
+    pub fn template_manifest(
+        &self,
+    ) -> Result<Vec<(EffectPath, sha256::Hash, Amount)>, CompilationError> {
+        self.templates_iter()
+            .map(|(path, t)| Ok((Self::known_path_of((path, t))?, t.hash(), t.max)))
+            .collect()
+    }
+
+    /// The paths of the templates this contract (and any contract it
+    /// creates) can still produce once the choices in `effects` are made.
+    /// Effects at a continuation point fix it to the branches they name: the
+    /// templates for its default effect, and for effects not in `effects`,
+    /// are pruned. Continuation points without effects keep every branch.
+    /// Fails if any template's path is not known.
+    pub fn reachable_templates(
+        &self,
+        effects: &MapEffectDB,
+    ) -> Result<Vec<EffectPath>, CompilationError> {
+        let excluded = |path: &EffectPath| {
+            let frags = Vec::from(path.clone());
+            let mut prefix: Option<Arc<EffectPath>> = None;
+            for (i, frag) in frags.iter().enumerate() {
+                // the branch of a continuation point this fragment enters
+                let branch = match (frag, frags.get(i + 1)) {
+                    (PathFragment::DefaultEffect, _) => Some(None),
+                    (PathFragment::Effects, Some(PathFragment::Named(SArc(name)))) => {
+                        Some(Some(name))
+                    }
+                    _ => None,
+                };
+                if let (Some(branch), Some(at)) = (branch, &prefix) {
+                    let mut chosen = effects.get_value(at).map(|(n, _)| n).peekable();
+                    if chosen.peek().is_some() && !chosen.any(|n| Some(n) == branch) {
+                        return true;
+                    }
+                }
+                prefix = Some(EffectPath::push(prefix, frag.clone()));
+            }
+            false
+        };
+        let mut reachable = vec![];
+        for t in self.templates_iter() {
+            let path = Self::known_path_of(t)?;
+            if !excluded(&path) {
+                reachable.push(path);
+            }
+        }
+        Ok(reachable)
+    }
+
+    /// An index from the CTV hash of every template this contract (and any
+    /// contract it creates) can produce to the path it was built at. Fails
+    /// if any template's path is not known.
+    pub fn template_index(&self) -> Result<BTreeMap<sha256::Hash, EffectPath>, CompilationError> {
+        Ok(self
+            .template_manifest()?
+            .into_iter()
+            .map(|(path, hash, _)| (hash, path))
+            .collect())
+    }
+
+    /// The path of the template with CTV hash `ctv_hash`, if this contract
+    /// (or any contract it creates) can produce it and its path is known.
+    /// Use [`Object::template_index`] to look up many hashes.
+    pub fn path_for_template(&self, ctv_hash: &sha256::Hash) -> Option<EffectPath> {
+        self.templates_iter()
+            .find(|(_, t)| t.hash() == *ctv_hash)
+            .and_then(|(path, _)| path)
+    }
+
+    /// The paths of every template, ordered so that a template always comes
+    /// before any template spending one of its outputs (i.e., parents before
+    /// children). Wallets assembling PSBTs should process templates in this
+    /// order. Fails if any template's path is not known.
+    pub fn template_order(&self) -> Result<Vec<EffectPath>, CompilationError> {
+        let mut order = vec![];
+        let mut queue = VecDeque::from([self]);
+        while let Some(obj) = queue.pop_front() {
+            for t in obj.templates() {
+                order.push(Self::known_path_of((obj.path_of(t), t))?);
+                queue.extend(t.outputs.iter().map(|o| &o.contract));
+            }
+        }
+        Ok(order)
+    }
+
+    /// Lint for branches (of this object and every object it creates) that
+    /// are statically unreachable because their conditional compilation
+    /// marked them `Never`. Each is reported as a
+    /// [`CompilationError::UnreachableBranch`] warning at the branch's path.
+    pub fn lint_unreachable(&self) -> Vec<CompilationWarning> {
+        self.objects()
+            .flat_map(|v| v.object.unreachable_branches.iter())
+            .map(|p| CompilationWarning {
+                path: p.0.as_ref().clone(),
+                error: CompilationError::UnreachableBranch,
+            })
+            .collect()
+    }
+
+    /// Whether the CTV-committed template built at `path` (in this object or
+    /// any object it creates) is enforced by real `OP_CTV` or by an
+    /// emulator's signatures, or None if there is no such template.
+    pub fn template_guard_kind(&self, path: &EffectPath) -> Option<GuardKind> {
+        self.objects()
+            .find_map(|v| {
+                let obj = v.object;
+                obj.ctv_to_tx
+                    .iter()
+                    .find(|(_, t)| obj.path_of(t).as_ref() == Some(path))
+                    .map(|(h, _)| obj.template_guard_kinds.get(h).copied())
+            })
+            .flatten()
+    }
+
+    /// Paths supplied in the effect DB, at or below this object, that the
+    /// compiler never looked up. These usually indicate an effect registered
+    /// at a mistyped path.
+    pub fn unused_effects(&self) -> Vec<EffectPath> {
+        self.unused_effect_paths
+            .iter()
+            .map(|p| p.0.as_ref().clone())
+            .collect()
+    }
+
+    /// The effects of `db` that compiling this object (with `db`) could not
+    /// apply, because nothing at or below this object consulted their path,
+    /// e.g. effects for a branch that is not reached yet. The residual keeps
+    /// each effect's provenance, so it can be kept and merged back in (see
+    /// [`MapEffectDB::merge`]) once more of the contract is assembled.
+    pub fn residual_effects(&self, db: &MapEffectDB) -> MapEffectDB {
+        let unused: BTreeSet<_> = self.unused_effect_paths.iter().collect();
+        let mut residual = EditableMapEffectDB::from(db.clone());
+        residual.effects.retain(|p, _| unused.contains(p));
+        residual.provenance.retain(|p, _| unused.contains(p));
+        residual.into()
+    }
+
+    /// Check that every effect in `db` could apply to this object (or an
+    /// object it creates): its path must be a continuation point, and its
+    /// value must have a JSON type the continuation's schema accepts. Use
+    /// this before applying a persisted DB to catch effects left stale by
+    /// a contract change.
+    pub fn validate_effects(
+        &self,
+        db: &MapEffectDB,
+    ) -> Result<(), Vec<(EffectPath, EffectDBError)>> {
+        let apis: BTreeMap<_, _> = self
+            .objects()
+            .flat_map(|v| v.object.continue_apis.iter())
+            .map(|(p, cp)| (p.0.clone(), cp))
+            .collect();
+        let mut errors = vec![];
+        for path in db.paths() {
+            let cp = match apis.get(path) {
+                Some(cp) => cp,
+                None => {
+                    errors.push((path.as_ref().clone(), EffectDBError::UnknownPath));
+                    continue;
+                }
+            };
+            let schema = match &cp.schema {
+                Some(schema) => schema,
+                None => continue,
+            };
+            for (name, v) in db.get_value(path) {
+                let violations = json_schema::validate(&schema.0, v);
+                if !violations.is_empty() {
+                    errors.push((
+                        path.as_ref().clone(),
+                        EffectDBError::IncompatibleType {
+                            name: name.as_ref().clone(),
+                            errors: violations,
+                        },
+                    ));
+                }
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Check every `PathFragment::Branch` selected along `path` against the
+    /// branches this object (or an object it creates) actually has there,
+    /// failing with [`CompilationError::BranchIndexOutOfRange`] at the first
+    /// one past the end, e.g. `#5` beneath a template with only 3 outputs.
+    ///
+    /// Selections beneath a parent with no branches at all are not checked,
+    /// since the path does not address this object there. Fails with
+    /// [`CompilationError::UnknownTemplatePath`] if any template's path is
+    /// not known.
+    pub fn check_branch_selection(&self, path: &EffectPath) -> Result<(), CompilationError> {
+        let mut known: Vec<Vec<PathFragment>> = vec![];
+        for ObjectVisit { object: obj, .. } in self.objects() {
+            known.push(obj.root_path.0.as_ref().clone().into());
+            known.extend(
+                obj.continue_apis
+                    .keys()
+                    .map(|p| p.0.as_ref().clone().into()),
+            );
+            for t in obj.templates() {
+                known.push(Self::known_path_of((obj.path_of(t), t))?.into());
+            }
+        }
+        let fragments: Vec<PathFragment> = path.clone().into();
+        for (i, f) in fragments.iter().enumerate() {
+            let requested = match f {
+                PathFragment::Branch(n) => *n,
+                _ => continue,
+            };
+            let parent = &fragments[..i];
+            let siblings: BTreeSet<u64> = known
+                .iter()
+                .filter(|k| k.starts_with(parent))
+                .filter_map(|k| match k.get(i) {
+                    Some(PathFragment::Branch(n)) => Some(*n),
+                    _ => None,
+                })
+                .collect();
+            if siblings.is_empty() {
+                break;
+            }
+            if !siblings.contains(&requested) {
+                return Err(CompilationError::BranchIndexOutOfRange {
+                    requested,
+                    available: siblings.len() as u64,
+                    path: EffectPath::try_from(parent.to_vec()).unwrap_or_default(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that no two nodes of this object (and the objects it creates)
+    /// share a path, which happens when one parent has two children with
+    /// the same `PathFragment::Named`, e.g. a hand-built or merged object.
+    /// Compilation never produces such objects, as each fragment may only
+    /// be derived once per context.
+    ///
+    /// Returns the parent path of each duplicated name, sorted, leaving out
+    /// those within an already duplicated subtree. Templates whose paths are
+    /// not known can not collide, so are not checked.
+    pub fn check_unique_names(&self) -> Result<(), Vec<EffectPath>> {
+        let mut seen: BTreeMap<EffectPath, usize> = BTreeMap::new();
+        for ObjectVisit { object: obj, .. } in self.objects() {
+            *seen.entry(obj.root_path.0.as_ref().clone()).or_default() += 1;
+            for p in obj.continue_apis.keys() {
+                *seen.entry(p.0.as_ref().clone()).or_default() += 1;
+            }
+            for path in obj.templates().filter_map(|t| obj.path_of(t)) {
+                *seen.entry(path).or_default() += 1;
+            }
+        }
+        let parents: BTreeSet<EffectPath> = seen
+            .into_iter()
+            .filter(|(_, n)| *n > 1)
+            .filter_map(|(path, _)| {
+                // the deepest name is where the paths first collide
+                let mut at = Some(path);
+                while let Some(p) = at {
+                    if matches!(p.iter().next(), Some(PathFragment::Named(_))) {
+                        return Some(p.parent().unwrap_or_default());
+                    }
+                    at = p.parent();
+                }
+                None
+            })
+            .collect();
+        // everything beneath a duplicated node is duplicated too, so only
+        // report the outermost collisions
+        let outermost: Vec<EffectPath> = parents
+            .iter()
+            .filter(|p| !parents.iter().any(|q| q != *p && p.starts_with(q)))
+            .cloned()
+            .collect();
+        if outermost.is_empty() {
+            Ok(())
+        } else {
+            Err(outermost)
+        }
+    }
+
+    /// The worst-case fees, at `feerate` sats per vbyte, to fully unwind this
+    /// object: spending it with its most expensive template (including the
+    /// witness satisfying this object's script), and then unwinding every
+    /// output that template creates the same way. Saturates at
+    /// [`Amount::max_value`] rather than overflowing.
+    pub fn max_unwind_cost(&self, feerate: Amount) -> Amount {
+        let witness = self
+            .descriptor
+            .as_ref()
+            .map(satisfaction_weight)
+            .unwrap_or(0);
+        self.templates()
+            .map(|t| {
+                let vbytes = (t.tx.weight() + witness).div_ceil(4) as u64;
+                t.outputs
+                    .iter()
+                    .map(|o| o.contract.max_unwind_cost(feerate))
+                    .fold(
+                        feerate
+                            .checked_mul(vbytes)
+                            .unwrap_or_else(Amount::max_value),
+                        |a, b| a.checked_add(b).unwrap_or_else(Amount::max_value),
+                    )
+            })
+            .max()
+            .unwrap_or_else(|| Amount::from_sat(0))
+    }
+
+    /// The descriptor of this contract's address, if known, for importing
+    /// it into a watch-only wallet. See [`Object::descriptors`] for the
+    /// contracts it creates.
+    pub fn root_descriptor(&self) -> Option<&SupportedDescriptors> {
+        self.descriptor.as_ref()
+    }
+
+    /// A hash of this contract's structure: its guards, the paths of its
+    /// templates (and, recursively, the structure of every contract they
+    /// create), and its continuation schemas. Values derived from the
+    /// [`crate::contract::Context`] are left out, e.g. the network (and so
+    /// the address), amounts, and the CTV hashes committing to them, so
+    /// logically identical contracts share a fingerprint wherever they are
+    /// compiled.
+    pub fn fingerprint(&self) -> sha256::Hash {
+        sha256::Hash::hash(self.structure().to_string().as_bytes())
+    }
+
+    fn structure(&self) -> Value {
+        let descriptor = self.descriptor.as_ref().map(|d| {
+            let with_checksum = d.to_string();
+            let mut d = with_checksum
+                .split('#')
+                .next()
+                .unwrap_or_default()
+                .to_string();
+            for t in self.ctv_to_tx.values() {
+                if let Some(path) = self.path_of(t) {
+                    d = d.replace(&t.hash().to_string(), &String::from(path));
+                }
+            }
+            d
+        });
+        let template = |t: &Template, committed: bool| {
+            serde_json::json!({
+                "path": self.path_of(t),
+                "committed": committed,
+                "guards": t.guards,
+                "outputs": t.outputs.iter().map(|o| o.contract.structure()).collect::<Vec<_>>(),
+            })
+        };
+        let mut templates: Vec<Value> = self
+            .ctv_to_tx
+            .values()
+            .map(|t| template(t, true))
+            .chain(self.suggested_txs.values().map(|t| template(t, false)))
+            .collect();
+        templates.sort_by_key(|t| t.to_string());
+        let schemas: BTreeMap<String, _> = self
+            .continue_apis
+            .iter()
+            .map(|(p, c)| (String::from(p.0.as_ref().clone()), &c.schema))
+            .collect();
+        serde_json::json!({
+            "root_path": self.root_path,
+            "descriptor": descriptor,
+            "templates": templates,
+            "continue_apis": schemas,
+        })
+    }
+
+    /// The descriptor of this contract and of every contract it creates
+    /// along any branch, at each contract's root path. Contracts without a
+    /// known descriptor (e.g. plain addresses) are skipped.
+    pub fn descriptors(&self) -> Vec<(EffectPath, &SupportedDescriptors)> {
+        self.objects()
+            .filter_map(|v| {
+                let d = v.object.descriptor.as_ref()?;
+                Some((v.object.root_path.0.as_ref().clone(), d))
+            })
+            .collect()
+    }
+
+    /// Check that every branch of every contract this object creates can be
+    /// funded by the output creating that contract, so that funding
+    /// problems in branches that are rarely taken surface up front. A
+    /// template needing more than its contract receives (e.g. because it
+    /// [adds funds](crate::template::Builder::add_amount) expecting another
+    /// input) is reported as [`CompilationError::OutOfFunds`] at the
+    /// template's path, or as [`CompilationError::UnknownTemplatePath`] at
+    /// its contract's root path if that is not known. The value of this object itself is set by whoever
+    /// funds it, so its own templates are not checked.
+    pub fn check_all_branches_fundable(&self) -> Result<(), Vec<(EffectPath, CompilationError)>> {
+        let mut errors = vec![];
+        for ObjectVisit {
+            object: obj,
+            amount,
+            ..
+        } in self.objects()
+        {
+            for t in obj.templates() {
+                if let Some(available) = amount.filter(|v| *v < t.max) {
+                    errors.push(match obj.path_of(t) {
+                        Some(path) => (
+                            path,
+                            CompilationError::OutOfFunds {
+                                available,
+                                required: t.max,
+                            },
+                        ),
+                        None => (
+                            obj.root_path.0.as_ref().clone(),
+                            CompilationError::UnknownTemplatePath { hash: t.hash() },
+                        ),
+                    });
+                }
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Every way to spend the contract at `path` (this object or any object
+    /// it creates) as a list of clause sets in disjunctive normal form: all
+    /// of the clauses in any one set together satisfy the contract. For
+    /// taproot contracts the key path is one of the sets, unless the internal
+    /// key is the unspendable [`nums_key`]. Contracts without
+    /// a taproot descriptor, or not found, have no known conditions.
+    pub fn spend_conditions(&self, path: &EffectPath) -> Vec<Vec<Clause>> {
+        let obj = match self
+            .objects()
+            .find(|v| v.object.root_path.0.as_ref() == path)
+        {
+            Some(v) => v.object,
+            None => return vec![],
+        };
+        let tr = match &obj.descriptor {
+            Some(SupportedDescriptors::XOnly(Descriptor::Tr(tr))) => tr,
+            _ => return vec![],
+        };
+        let scripts: Vec<_> = tr.iter_scripts().map(|(_, ms)| ms).collect();
+        let internal_key = Some(*tr.internal_key()).filter(|k| *k != nums_key());
+        let mut keys: Vec<_> = internal_key.into_iter().collect();
+        for ms in &scripts {
+            keys.extend(ms.iter_pk());
+        }
+        let mut conditions: Vec<_> = internal_key
+            .map(|k| vec![Clause::Key(k)])
+            .into_iter()
+            .collect();
+        for ms in scripts {
+            conditions.extend(miniscript_dnf(ms, &keys));
+        }
+        for c in conditions.iter_mut() {
+            c.sort();
+            c.dedup();
+        }
+        let mut seen = BTreeSet::new();
+        conditions.retain(|c| seen.insert(c.clone()));
+        conditions
+    }
+
+    /// Every relative (CSV) and absolute (CLTV) timelock imposed by this
+    /// object and every object it creates: those in a contract's scripts,
+    /// at the contract's root path, and those set by a template's
+    /// nSequences and nLockTime, at the template's path. Fails if any
+    /// template's path is not known.
+    pub fn timelocks(&self) -> Result<Vec<(EffectPath, AnyTimeLock)>, CompilationError> {
+        let mut locks = vec![];
+        for ObjectVisit { object: obj, .. } in self.objects() {
+            if let Some(d) = obj.descriptor.as_ref() {
+                let at = obj.root_path.0.as_ref();
+                locks.extend(script_timelocks(d).into_iter().map(|l| (at.clone(), l)));
+            }
+            for t in obj.templates() {
+                let at = Self::known_path_of((obj.path_of(t), t))?;
+                if t.tx.version >= 2 {
+                    locks.extend(
+                        t.tx.input
+                            .iter()
+                            .filter_map(|i| relative_lock(i.sequence))
+                            .map(|l| (at.clone(), l)),
+                    );
+                }
+                // nLockTime is only enforced if an input is not final
+                if t.tx.input.iter().any(|i| i.sequence != u32::MAX) {
+                    locks.extend(absolute_lock(t.tx.lock_time).map(|l| (at.clone(), l)));
+                }
+            }
+        }
+        Ok(locks)
+    }
+
+    /// Aggregate size metrics over this object and every object it creates
+    pub fn stats(&self) -> CompileStats {
+        let mut stats = CompileStats::default();
+        for ObjectVisit {
+            object: obj, depth, ..
+        } in self.objects()
+        {
+            stats.max_depth = stats.max_depth.max(depth);
+            stats.script_weight += obj.descriptor.as_ref().map(script_weight).unwrap_or(0);
+            for t in obj.templates() {
+                stats.templates += 1;
+                for f in obj.path_of(t).iter().flat_map(|p| p.iter()) {
+                    *stats.fragments.entry(fragment_kind(f)).or_default() += 1;
+                }
+            }
+            if !(obj.ctv_to_tx.is_empty() && obj.suggested_txs.is_empty()) {
+                stats.max_depth = stats.max_depth.max(depth + 1);
+            }
+        }
+        stats
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate as sapio;
+    use crate::contract::actions::ConditionalCompileType;
+    use crate::contract::compiler::nums_key;
+    use crate::contract::test_helpers::{ctx, ctx_with_effects, effects_db, key, Leaf};
+    use crate::contract::{Compilable, CompilationError, Contract};
+    use crate::*;
+    use bitcoin::hashes::sha256;
+    use bitcoin::util::amount::Amount;
+    use bitcoin::XOnlyPublicKey;
+    use sapio_base::timelocks::{AbsHeight, RelHeight, RelTime};
+    use std::convert::TryFrom;
+
+    struct TwoWay {
+        a: XOnlyPublicKey,
+        b: XOnlyPublicKey,
+    }
+    impl TwoWay {
+        #[then]
+        fn to_a(self, ctx: sapio::Context) {
+            ctx.template()
+                .add_output(Amount::from_sat(1000), &self.a, None)?
+                .into()
+        }
+        #[then]
+        fn to_b(self, ctx: sapio::Context) {
+            ctx.template()
+                .add_output(Amount::from_sat(2000), &self.b, None)?
+                .into()
+        }
+    }
+    impl Contract for TwoWay {
+        declare! {then, Self::to_a, Self::to_b}
+        declare! {non updatable}
+    }
+
+    struct Chain {
+        next: TwoWay,
+    }
+    impl Chain {
+        #[then]
+        fn step(self, ctx: sapio::Context) {
+            let amt = ctx.funds();
+            ctx.template()
+                .add_output(
+                    amt,
+                    &TwoWay {
+                        a: self.next.a,
+                        b: self.next.b,
+                    },
+                    None,
+                )?
+                .into()
+        }
+    }
+    impl Contract for Chain {
+        declare! {then, Self::step}
+        declare! {non updatable}
+    }
+
+    #[test]
+    fn test_template_order() {
+        let obj = Chain {
+            next: TwoWay {
+                a: key(1),
+                b: key(2),
+            },
+        }
+        .compile(ctx(10_000))
+        .unwrap();
+        let order: Vec<String> = obj
+            .template_order()
+            .unwrap()
+            .into_iter()
+            .map(String::from)
+            .collect();
+        assert_eq!(order.len(), 3);
+        assert_eq!(order[0], "@root/@action/step/@next/@default_effect");
+        let pos = |suffix: &str| order.iter().position(|p| p.ends_with(suffix)).unwrap();
+        assert!(pos("to_a/@next/@default_effect") > 0);
+        assert!(pos("to_b/@next/@default_effect") > 0);
+    }
+
+    #[test]
+    fn test_path_for_template() {
+        let obj = Chain {
+            next: TwoWay {
+                a: key(1),
+                b: key(2),
+            },
+        }
+        .compile(ctx(10_000))
+        .unwrap();
+        let child = &obj.ctv_to_tx.values().next().unwrap().outputs[0].contract;
+        for t in child.ctv_to_tx.values() {
+            let path = obj.path_for_template(&t.hash()).unwrap();
+            assert_eq!(Some(&path), t.path.as_ref().map(|p| p.0.as_ref()));
+            assert_eq!(obj.template_index().unwrap()[&t.hash()], path);
+        }
+        let unknown = bitcoin::hashes::Hash::hash(&[0u8]);
+        assert_eq!(obj.path_for_template(&unknown), None);
+    }
+
+    #[test]
+    fn test_template_guard_kind() {
+        use crate::contract::object::GuardKind;
+        use bitcoin::util::psbt::PartiallySignedTransaction;
+        use sapio_base::effects::{EffectPath, PathFragment};
+        use sapio_ctv_emulator_trait::{CTVEmulator, EmulatorError};
+        use std::convert::TryFrom;
+        use std::sync::Arc;
+        struct Oracle;
+        impl CTVEmulator for Oracle {
+            fn get_signer_for(
+                &self,
+                _h: sha256::Hash,
+            ) -> Result<sapio_base::Clause, EmulatorError> {
+                Ok(sapio_base::Clause::Key(key(9)))
+            }
+            fn sign(
+                &self,
+                b: PartiallySignedTransaction,
+            ) -> Result<PartiallySignedTransaction, EmulatorError> {
+                Ok(b)
+            }
+        }
+        let contract = || TwoWay {
+            a: key(1),
+            b: key(2),
+        };
+        let path = EffectPath::try_from("@root/@action/to_a/@next/@default_effect").unwrap();
+        let real = contract().compile(ctx(10_000)).unwrap();
+        assert_eq!(real.template_guard_kind(&path), Some(GuardKind::Ctv));
+        let emulated = contract()
+            .compile(sapio::Context::new(
+                bitcoin::Network::Regtest,
+                Amount::from_sat(10_000),
+                Arc::new(Oracle),
+                EffectPath::from(PathFragment::Root),
+                Default::default(),
+            ))
+            .unwrap();
+        assert_eq!(
+            emulated.template_guard_kind(&path),
+            Some(GuardKind::Emulated)
+        );
+        let nowhere = EffectPath::try_from("@root/@action/to_c").unwrap();
+        assert_eq!(real.template_guard_kind(&nowhere), None);
+    }
+
+    #[test]
+    fn test_max_unwind_cost() {
+        let obj = Chain {
+            next: TwoWay {
+                a: key(1),
+                b: key(2),
+            },
+        }
+        .compile(ctx(10_000))
+        .unwrap();
+        let child = &obj.ctv_to_tx.values().next().unwrap().outputs[0].contract;
+        let feerate = Amount::from_sat(2);
+        // every template is 1-in-1-out (376 WU before witness); spending
+        // the child by either of its two CTV leaves needs a deeper control
+        // block (121 vbytes) than spending the root by its only leaf (113)
+        assert_eq!(child.max_unwind_cost(feerate), Amount::from_sat(2 * 121));
+        assert_eq!(
+            obj.max_unwind_cost(feerate),
+            Amount::from_sat(2 * (113 + 121))
+        );
+        assert_eq!(
+            obj.max_unwind_cost(Amount::from_sat(0)),
+            Amount::from_sat(0)
+        );
+        assert_eq!(
+            obj.max_unwind_cost(Amount::max_value()),
+            Amount::max_value()
+        );
+    }
+
+    struct DeadCode {
+        to: XOnlyPublicKey,
+    }
+    impl DeadCode {
+        #[compile_if]
+        fn disabled(self, _ctx: sapio::Context) {
+            ConditionalCompileType::Never
+        }
+        #[then]
+        fn live(self, ctx: sapio::Context) {
+            ctx.template()
+                .add_output(Amount::from_sat(1000), &self.to, None)?
+                .into()
+        }
+        #[then(compile_if = "[Self::disabled]")]
+        fn dead(self, ctx: sapio::Context) {
+            ctx.template()
+                .add_output(Amount::from_sat(2000), &self.to, None)?
+                .into()
+        }
+    }
+    impl Contract for DeadCode {
+        declare! {then, Self::live, Self::dead}
+        declare! {non updatable}
+    }
+
+    struct Switch {
+        to: XOnlyPublicKey,
+        on: bool,
+    }
+    impl Switch {
+        #[compile_if]
+        fn if_on(self, _ctx: sapio::Context) {
+            if self.on {
+                ConditionalCompileType::Required
+            } else {
+                ConditionalCompileType::Skippable
+            }
+        }
+        #[compile_if]
+        fn if_off(self, _ctx: sapio::Context) {
+            if self.on {
+                ConditionalCompileType::Skippable
+            } else {
+                ConditionalCompileType::Required
+            }
+        }
+        #[then(compile_if = "[Self::if_on]")]
+        fn up(self, ctx: sapio::Context) {
+            ctx.template()
+                .add_output(Amount::from_sat(1000), &self.to, None)?
+                .into()
+        }
+        #[then(compile_if = "[Self::if_off]")]
+        fn down(self, ctx: sapio::Context) {
+            ctx.template()
+                .add_output(Amount::from_sat(2000), &self.to, None)?
+                .into()
+        }
+        #[then]
+        fn always(self, ctx: sapio::Context) {
+            ctx.template()
+                .add_output(Amount::from_sat(3000), &self.to, None)?
+                .into()
+        }
+    }
+    impl Contract for Switch {
+        declare! {then, Self::up, Self::down, Self::always}
+        declare! {non updatable}
+    }
+
+    #[test]
+    fn test_cond_comp_decisions() {
+        let decisions = |on| {
+            let obj = Switch { to: key(1), on }.compile(ctx(10_000)).unwrap();
+            obj.cond_comp_decisions
+                .into_iter()
+                .map(|(p, taken)| (String::from(p.0.as_ref().clone()), taken))
+                .collect::<Vec<_>>()
+        };
+        let expect = |up, down| {
+            vec![
+                ("@root/@action/up/@cond_comp_if".to_string(), up),
+                ("@root/@action/down/@cond_comp_if".to_string(), down),
+            ]
+        };
+        assert_eq!(decisions(true), expect(true, false));
+        assert_eq!(decisions(false), expect(false, true));
+    }
+
+    #[test]
+    fn test_lint_unreachable() {
+        let obj = DeadCode { to: key(1) }.compile(ctx(10_000)).unwrap();
+        let lints = obj.lint_unreachable();
+        assert_eq!(lints.len(), 1);
+        assert_eq!(String::from(lints[0].path.clone()), "@root/@action/dead");
+        assert!(matches!(
+            lints[0].error,
+            CompilationError::UnreachableBranch
+        ));
+        assert!(TwoWay {
+            a: key(1),
+            b: key(2)
+        }
+        .compile(ctx(10_000))
+        .unwrap()
+        .lint_unreachable()
+        .is_empty());
+    }
+
+    #[test]
+    fn test_stats() {
+        let obj = Chain {
+            next: TwoWay {
+                a: key(1),
+                b: key(2),
+            },
+        }
+        .compile(ctx(10_000))
+        .unwrap();
+        let stats = obj.stats();
+        assert_eq!(stats.templates, 3);
+        assert_eq!(stats.max_depth, 2);
+        // each template's path is @root/@action/<fn>/@next/@default_effect,
+        // with the TwoWay templates nested under the Chain output's path
+        assert_eq!(stats.fragments["@root"], 3);
+        assert_eq!(stats.fragments["@action"], 5);
+        assert_eq!(stats.fragments["@next"], 5);
+        assert_eq!(stats.fragments["#"], 2);
+        // one 36 byte CTV leaf per template
+        assert_eq!(stats.script_weight, 3 * 36);
+    }
+
+    #[test]
+    fn test_templates_iter() {
+        let obj = Chain {
+            next: TwoWay {
+                a: key(1),
+                b: key(2),
+            },
+        }
+        .compile(ctx(10_000))
+        .unwrap();
+        let mut visited: Vec<_> = obj
+            .templates_iter()
+            .map(|(path, t)| (path.unwrap(), t.hash()))
+            .collect();
+        visited.sort();
+        let mut expected: Vec<_> = obj
+            .template_manifest()
+            .unwrap()
+            .into_iter()
+            .map(|(path, hash, _)| (path, hash))
+            .collect();
+        expected.sort();
+        assert_eq!(visited.len(), 3);
+        assert_eq!(visited, expected);
+        visited.dedup();
+        assert_eq!(visited.len(), 3);
+    }
+
+    #[test]
+    fn test_fingerprint() {
+        use crate::contract::object::Object;
+        use bitcoin::hashes::Hash;
+        use sapio_base::effects::{EffectPath, PathFragment};
+        use sapio_ctv_emulator_trait::CTVAvailable;
+        use std::sync::Arc;
+        let on = |network| {
+            sapio::Context::new(
+                network,
+                Amount::from_sat(10_000),
+                Arc::new(CTVAvailable),
+                EffectPath::from(PathFragment::Root),
+                Default::default(),
+            )
+        };
+        let two_way = |b| TwoWay {
+            a: key(1),
+            b: key(b),
+        };
+        let regtest = two_way(2).compile(on(bitcoin::Network::Regtest)).unwrap();
+        let mainnet = two_way(2).compile(on(bitcoin::Network::Bitcoin)).unwrap();
+        let object_hash = |o: &Object| sha256::Hash::hash(&serde_json::to_vec(o).unwrap());
+        assert_ne!(object_hash(&regtest), object_hash(&mainnet));
+        assert_eq!(regtest.fingerprint(), mainnet.fingerprint());
+        let other = two_way(3).compile(on(bitcoin::Network::Regtest)).unwrap();
+        assert_ne!(regtest.fingerprint(), other.fingerprint());
+    }
+
+    #[test]
+    fn test_template_manifest() {
+        let obj = TwoWay {
+            a: key(1),
+            b: key(2),
+        }
+        .compile(ctx(10_000))
+        .unwrap();
+        let mut manifest = obj.template_manifest().unwrap();
+        manifest.sort_by_key(|(_, _, amt)| *amt);
+        let paths: Vec<String> = manifest.iter().map(|m| m.0.clone().into()).collect();
+        assert_eq!(
+            paths,
+            vec![
+                "@root/@action/to_a/@next/@default_effect",
+                "@root/@action/to_b/@next/@default_effect"
+            ]
+        );
+        let amounts: Vec<_> = manifest.iter().map(|m| m.2.as_sat()).collect();
+        assert_eq!(amounts, vec![1000, 2000]);
+        for (p, h, _) in &manifest {
+            let t = &obj.ctv_to_tx[h];
+            assert_eq!(t.path.as_ref().map(|p| p.0.as_ref()), Some(p));
+        }
+    }
+
+    struct Tip {
+        to: XOnlyPublicKey,
+    }
+    fn coerce_tip(k: Option<u64>) -> Result<Option<u64>, CompilationError> {
+        Ok(k)
+    }
+    impl Tip {
+        #[guard]
+        fn signed(self, _ctx: sapio::Context) {
+            sapio_base::Clause::Key(self.to)
+        }
+        #[continuation(guarded_by = "[Self::signed]", web_api, coerce_args = "coerce_tip")]
+        fn bump(self, ctx: sapio::Context, extra: Option<u64>) {
+            match extra {
+                Some(extra) => ctx
+                    .template()
+                    .add_output(Amount::from_sat(extra), &self.to, None)?
+                    .into(),
+                None => crate::contract::empty(),
+            }
+        }
+    }
+    impl Contract for Tip {
+        declare! {updatable<Option<u64>>, Self::bump}
+    }
+
+    #[test]
+    fn test_schema_validation_failed() {
+        let effects = effects_db(&[("@root/@action/bump/@suggested", "x", (-5).into())]);
+        let tip = Tip { to: key(1) };
+        match tip.compile(ctx_with_effects(10_000, effects)) {
+            Err(CompilationError::SchemaValidationFailed { errors }) => {
+                assert_eq!(errors, vec!["field arguments must be at least 0"]);
+            }
+            r => panic!("expected SchemaValidationFailed, got {:?}", r.err()),
+        }
+    }
+
+    #[test]
+    fn test_unused_effects() {
+        let effects = effects_db(&[
+            ("@root/@action/bump/@suggested", "x", 5.into()),
+            ("@root/@action/bupm/@suggested", "x", 5.into()),
+        ]);
+        let obj = Tip { to: key(1) }
+            .compile(ctx_with_effects(10_000, effects))
+            .unwrap();
+        assert_eq!(obj.suggested_txs.len(), 1);
+        let unused: Vec<String> = obj.unused_effects().into_iter().map(String::from).collect();
+        assert_eq!(unused, vec!["@root/@action/bupm/@suggested"]);
+    }
+
+    struct Picker;
+    impl Picker {
+        #[guard]
+        fn signed(self, _ctx: sapio::Context) {
+            sapio_base::Clause::Key(key(2))
+        }
+        #[continuation(guarded_by = "[Self::signed]", web_api, coerce_args = "coerce_tip")]
+        fn pick(self, ctx: sapio::Context, amount: Option<u64>) {
+            ctx.template()
+                .add_output(Amount::from_sat(amount.unwrap_or(1000)), &key(1), None)?
+                .into()
+        }
+    }
+    impl Contract for Picker {
+        declare! {updatable<Option<u64>>, Self::pick}
+    }
+
+    #[test]
+    fn test_reachable_templates() {
+        use sapio_base::effects::MapEffectDB;
+        let db = |names: &[(&str, u64)]| {
+            let entries: Vec<_> = names
+                .iter()
+                .map(|(n, v)| ("@root/@action/pick/@suggested", *n, (*v).into()))
+                .collect();
+            effects_db(&entries)
+        };
+        let obj = Picker
+            .compile(ctx_with_effects(10_000, db(&[("a", 2000), ("b", 3000)])))
+            .unwrap();
+        let reachable = |effects: &MapEffectDB| {
+            let mut paths: Vec<String> = obj
+                .reachable_templates(effects)
+                .unwrap()
+                .into_iter()
+                .map(String::from)
+                .collect();
+            paths.sort();
+            paths
+        };
+        assert_eq!(reachable(&MapEffectDB::default()).len(), 3);
+        // fixing `a` excludes the default and `b`
+        let fixed = reachable(&db(&[("a", 2000)]));
+        assert_eq!(fixed.len(), 1);
+        assert!(fixed[0].starts_with("@root/@action/pick/@suggested/@effects/a"));
+        assert_eq!(reachable(&db(&[("a", 2000), ("b", 3000)])).len(), 2);
+    }
+
+    #[test]
+    fn test_residual_effects() {
+        use sapio_base::effects::{EffectDB, EffectPath};
+        let reached = "@root/@action/bump/@suggested";
+        // a continuation of a contract that is not part of the object yet
+        let later = "@root/@action/later/@suggested";
+        let effects =
+            effects_db(&[(reached, "x", 5.into()), (later, "x", 7.into())]).with_provenance("user");
+        let at = |s: &str| std::sync::Arc::new(EffectPath::try_from(s).unwrap());
+        let (reached, later) = (at(reached), at(later));
+        let obj = Tip { to: key(1) }
+            .compile(ctx_with_effects(10_000, effects.clone()))
+            .unwrap();
+        assert_eq!(obj.suggested_txs.len(), 1);
+        let residual = obj.residual_effects(&effects);
+        let paths: Vec<_> = residual.paths().cloned().collect();
+        assert_eq!(paths, vec![later.clone()]);
+        assert_eq!(
+            residual
+                .get_value(&later)
+                .map(|(_, v)| v.clone())
+                .collect::<Vec<_>>(),
+            vec![serde_json::json!(7)]
+        );
+        assert_eq!(residual.provenance(&later, "x"), Some("user"));
+        assert_eq!(residual.get_value(&reached).count(), 0);
+    }
+
+    #[test]
+    fn test_check_unique_names() {
+        use bitcoin::hashes::Hash;
+        let mut obj = Fanout.compile(ctx(10_000)).unwrap();
+        assert_eq!(obj.check_unique_names(), Ok(()));
+        // a second template (and outputs) at the same path, as if `fan` had
+        // been declared twice
+        let t = obj.ctv_to_tx.values().next().unwrap().clone();
+        obj.ctv_to_tx.insert(sha256::Hash::hash(b"copy"), t);
+        let dups: Vec<String> = obj
+            .check_unique_names()
+            .unwrap_err()
+            .into_iter()
+            .map(String::from)
+            .collect();
+        assert_eq!(dups, vec!["@root/@action"]);
+    }
+
+    struct Fanout;
+    impl Fanout {
+        #[then]
+        fn fan(self, ctx: sapio::Context) {
+            let mut t = ctx.template();
+            for i in 0..3 {
+                t = t.add_output(Amount::from_sat(1000), &Tip { to: key(i + 1) }, None)?;
+            }
+            t.into()
+        }
+    }
+    impl Contract for Fanout {
+        declare! {then, Self::fan}
+        declare! {non updatable}
+    }
+
+    #[test]
+    fn test_branch_index_out_of_range() {
+        let effects_at = |branch: u64| {
+            let p = format!(
+                "@root/@action/fan/@next/@default_effect/#{}/@action/bump/@suggested",
+                branch
+            );
+            effects_db(&[(&p, "x", 5.into())])
+        };
+        assert!(Fanout
+            .compile(ctx_with_effects(10_000, effects_at(2)))
+            .is_ok());
+        match Fanout.compile(ctx_with_effects(10_000, effects_at(5))) {
+            Err(CompilationError::BranchIndexOutOfRange {
+                requested,
+                available,
+                path,
+            }) => {
+                assert_eq!((requested, available), (5, 3));
+                assert_eq!(
+                    String::from(path),
+                    "@root/@action/fan/@next/@default_effect"
+                );
+            }
+            r => panic!("expected BranchIndexOutOfRange, got {:?}", r.err()),
+        }
+    }
+
+    #[test]
+    fn test_validate_effects() {
+        use sapio_base::effects::EffectDBError;
+        let db = |entries: Vec<(&str, serde_json::Value)>| {
+            let entries: Vec<_> = entries.into_iter().map(|(p, v)| (p, "x", v)).collect();
+            effects_db(&entries)
+        };
+        let obj = Tip { to: key(1) }.compile(ctx(10_000)).unwrap();
+        let good = "@root/@action/bump/@suggested";
+        assert!(obj.validate_effects(&db(vec![(good, 5.into())])).is_ok());
+        let errors = obj
+            .validate_effects(&db(vec![
+                (good, 5.into()),
+                ("@root/@action/bupm/@suggested", 5.into()),
+            ]))
+            .unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            String::from(errors[0].0.clone()),
+            "@root/@action/bupm/@suggested"
+        );
+        assert!(matches!(errors[0].1, EffectDBError::UnknownPath));
+        let errors = obj
+            .validate_effects(&db(vec![(good, "five".into())]))
+            .unwrap_err();
+        assert!(matches!(
+            &errors[0].1,
+            EffectDBError::IncompatibleType { name, errors }
+                if name == "x" && errors[0].starts_with("field arguments must be")
+        ));
+    }
+
+    struct Vesting {
+        to: XOnlyPublicKey,
+    }
+    impl Vesting {
+        #[guard]
+        fn cooldown(self, _ctx: sapio::Context) {
+            RelHeight::from(144).into()
+        }
+        #[then(guarded_by = "[Self::cooldown]")]
+        fn vest(self, ctx: sapio::Context) {
+            ctx.template()
+                .set_lock_time(AbsHeight::try_from(800_000).unwrap().into())?
+                .set_sequence(0, RelTime::from(4).into())?
+                .add_output(Amount::from_sat(1000), &self.to, None)?
+                .into()
+        }
+    }
+    impl Contract for Vesting {
+        declare! {then, Self::vest}
+        declare! {non updatable}
+    }
+
+    #[test]
+    fn test_timelocks() {
+        use sapio_base::timelocks::{AnyAbsTimeLock, AnyRelTimeLock, AnyTimeLock};
+        let obj = Vesting { to: key(1) }.compile(ctx(10_000)).unwrap();
+        let locks: Vec<(String, &str, u32)> = obj
+            .timelocks()
+            .unwrap()
+            .into_iter()
+            .map(|(p, l)| {
+                let kind = match l {
+                    AnyTimeLock::R(AnyRelTimeLock::RH(_)) => "relative height",
+                    AnyTimeLock::R(AnyRelTimeLock::RT(_)) => "relative time",
+                    AnyTimeLock::A(AnyAbsTimeLock::AH(_)) => "absolute height",
+                    AnyTimeLock::A(AnyAbsTimeLock::AT(_)) => "absolute time",
+                };
+                (String::from(p), kind, l.get())
+            })
+            .collect();
+        let at = "@root/@action/vest/@next/@default_effect".to_string();
+        assert_eq!(
+            locks,
+            vec![
+                ("@root".to_string(), "relative height", 144),
+                (at.clone(), "relative time", (1 << 22) | 4),
+                (at, "absolute height", 800_000),
+            ]
+        );
+    }
+
+    struct Multisig {
+        keys: [XOnlyPublicKey; 2],
+    }
+    impl Multisig {
+        #[guard]
+        fn both(self, _ctx: sapio::Context) {
+            sapio_base::Clause::Threshold(
+                2,
+                self.keys
+                    .iter()
+                    .copied()
+                    .map(sapio_base::Clause::Key)
+                    .collect(),
+            )
+        }
+        #[then(guarded_by = "[Self::both]")]
+        fn spend(self, ctx: sapio::Context) {
+            ctx.template()
+                .add_output(
+                    Amount::from_sat(5000),
+                    &TwoWay {
+                        a: self.keys[0],
+                        b: self.keys[1],
+                    },
+                    None,
+                )?
+                .into()
+        }
+    }
+    impl Contract for Multisig {
+        declare! {then, Self::spend}
+        declare! {non updatable}
+    }
+
+    struct Either {
+        a: XOnlyPublicKey,
+        b: XOnlyPublicKey,
+        c: XOnlyPublicKey,
+    }
+    impl Either {
+        #[guard]
+        fn ab_or_c(self, _ctx: sapio::Context) {
+            use sapio_base::Clause;
+            Clause::Or(vec![
+                (
+                    1,
+                    Clause::And(vec![Clause::Key(self.a), Clause::Key(self.b)]),
+                ),
+                (1, Clause::Key(self.c)),
+            ])
+        }
+    }
+    impl Contract for Either {
+        declare! {finish, Self::ab_or_c}
+        declare! {non updatable}
+    }
+
+    #[test]
+    fn test_spend_conditions() {
+        use sapio_base::Clause;
+        let obj = Either {
+            a: key(1),
+            b: key(2),
+            c: key(3),
+        }
+        .compile(ctx(10_000))
+        .unwrap();
+        let mut conditions = obj.spend_conditions(&obj.root_path.0);
+        conditions.sort();
+        let mut ab = vec![Clause::Key(key(1)), Clause::Key(key(2))];
+        ab.sort();
+        let mut expected = vec![ab, vec![Clause::Key(key(3))]];
+        expected.sort();
+        assert_eq!(conditions, expected);
+        let elsewhere = sapio_base::effects::EffectPath::try_from("@root/elsewhere").unwrap();
+        assert!(obj.spend_conditions(&elsewhere).is_empty());
+    }
+
+    #[test]
+    fn test_spend_conditions_ctv_only() {
+        use sapio_base::Clause;
+        let obj = Leaf { to: key(1) }.compile(ctx(10_000)).unwrap();
+        let conditions = obj.spend_conditions(&obj.root_path.0);
+        assert_eq!(conditions.len(), 1);
+        assert!(matches!(conditions[0][..], [Clause::TxTemplate(_)]));
+        assert!(!conditions.concat().contains(&Clause::Key(nums_key())));
+    }
+
+    /// one branch pays out of its own funds, the other needs an extra input
+    struct Topped {
+        to: XOnlyPublicKey,
+    }
+    impl Topped {
+        #[then]
+        fn plain(self, ctx: sapio::Context) {
+            ctx.template()
+                .add_output(Amount::from_sat(1000), &self.to, None)?
+                .into()
+        }
+        #[then]
+        fn topped_up(self, ctx: sapio::Context) {
+            ctx.template()
+                .add_amount(Amount::from_sat(5000))
+                .add_output(Amount::from_sat(6000), &self.to, None)?
+                .into()
+        }
+    }
+    impl Contract for Topped {
+        declare! {then, Self::plain, Self::topped_up}
+        declare! {non updatable}
+    }
+
+    struct Funder;
+    impl Funder {
+        #[then]
+        fn fund(self, ctx: sapio::Context) {
+            ctx.template()
+                .add_output(Amount::from_sat(2000), &Topped { to: key(1) }, None)?
+                .into()
+        }
+    }
+    impl Contract for Funder {
+        declare! {then, Self::fund}
+        declare! {non updatable}
+    }
+
+    #[test]
+    fn test_check_all_branches_fundable() {
+        let obj = Funder.compile(ctx(10_000)).unwrap();
+        let errors = obj.check_all_branches_fundable().unwrap_err();
+        assert_eq!(errors.len(), 1);
+        let (path, error) = &errors[0];
+        assert_eq!(
+            String::from(path.clone()),
+            "@root/@action/fund/@next/@default_effect/#0/@action/topped_up/@next/@default_effect"
+        );
+        assert!(matches!(
+            error,
+            CompilationError::OutOfFunds { available, required }
+                if *available == Amount::from_sat(2000) && *required == Amount::from_sat(6000)
+        ));
+        assert!(Topped { to: key(1) }
+            .compile(ctx(10_000))
+            .unwrap()
+            .check_all_branches_fundable()
+            .is_ok());
+    }
+
+    #[test]
+    fn test_root_descriptor() {
+        let obj = Multisig {
+            keys: [key(1), key(2)],
+        }
+        .compile(ctx(10_000))
+        .unwrap();
+        let d = obj.root_descriptor().unwrap().to_string();
+        assert!(d.starts_with("tr("), "{}", d);
+        let multisig = format!("thresh(2,pk({}),s:pk({}))", key(1), key(2));
+        assert!(d.contains(&multisig), "{}", d);
+        // includes the checksum wallets require
+        assert_eq!(d.rsplit_once('#').map(|(_, c)| c.len()), Some(8));
+        assert_eq!(
+            obj.root_descriptor().unwrap().script_pubkey(),
+            bitcoin::Script::from(obj.address.clone())
+        );
+        let all = obj.descriptors();
+        assert_eq!(all.len(), 2);
+        assert_eq!(String::from(all[0].0.clone()), "@root");
+        let child = &obj.ctv_to_tx.values().next().unwrap().outputs[0].contract;
+        assert_eq!(
+            all[1].1.to_string(),
+            child.root_descriptor().unwrap().to_string()
+        );
+    }
+}
@@ -0,0 +1,195 @@
+// Copyright Judica, Inc 2022
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+//  License, v. 2.0. If a copy of the MPL was not distributed with this
+//  file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! a Merkle commitment to every template an Object can produce
+use crate::contract::object::Object;
+use crate::contract::CompilationError;
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use sapio_base::effects::EffectPath;
+use serde::{Deserialize, Serialize};
+
+/// One step from a node up to its parent in a [`MerkleProof`]: the sibling
+/// hash, and which side of the node it is on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MerkleStep {
+    /// the sibling is on the left
+    Left(sha256::Hash),
+    /// the sibling is on the right
+    Right(sha256::Hash),
+}
+
+/// A proof that the template with CTV hash `template`, built at `path`, is
+/// committed to by an [`Object::template_merkle_root`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MerkleProof {
+    /// the path the template was built at
+    pub path: EffectPath,
+    /// the template's CTV hash
+    pub template: sha256::Hash,
+    /// the siblings from the leaf up to the root
+    pub steps: Vec<MerkleStep>,
+}
+
+impl MerkleProof {
+    /// does this proof lead from its template to `root`?
+    pub fn verify(&self, root: &sha256::Hash) -> bool {
+        let top = self
+            .steps
+            .iter()
+            .fold(leaf(&self.path, &self.template), |h, s| match s {
+                MerkleStep::Left(l) => node(l, &h),
+                MerkleStep::Right(r) => node(&h, r),
+            });
+        top == *root
+    }
+}
+
+/// leaves and inner nodes are prefixed differently so that one can not be
+/// passed off as the other
+fn leaf(path: &EffectPath, template: &sha256::Hash) -> sha256::Hash {
+    let mut engine = sha256::Hash::engine();
+    engine.input(&[0]);
+    engine.input(String::from(path.clone()).as_bytes());
+    engine.input(&template[..]);
+    sha256::Hash::from_engine(engine)
+}
+
+fn node(left: &sha256::Hash, right: &sha256::Hash) -> sha256::Hash {
+    let mut engine = sha256::Hash::engine();
+    engine.input(&[1]);
+    engine.input(&left[..]);
+    engine.input(&right[..]);
+    sha256::Hash::from_engine(engine)
+}
+
+/// the (path, template) leaves, and the levels of the tree above them
+type MerkleLevels = (Vec<(EffectPath, sha256::Hash)>, Vec<Vec<sha256::Hash>>);
+
+impl Object {
+    /// every (path, template) leaf, in a canonical order
+    fn merkle_leaves(&self) -> Result<Vec<(EffectPath, sha256::Hash)>, CompilationError> {
+        let mut leaves: Vec<(String, EffectPath, sha256::Hash)> = self
+            .template_manifest()?
+            .into_iter()
+            .map(|(p, h, _)| (String::from(p.clone()), p, h))
+            .collect();
+        leaves.sort_by(|a, b| (&a.0, a.2).cmp(&(&b.0, b.2)));
+        leaves.dedup_by(|a, b| (&a.0, a.2) == (&b.0, b.2));
+        Ok(leaves.into_iter().map(|(_, p, h)| (p, h)).collect())
+    }
+
+    /// The levels of the tree, leaves first. A node without a sibling is
+    /// carried up to the next level unchanged.
+    fn merkle_levels(&self) -> Result<MerkleLevels, CompilationError> {
+        let leaves = self.merkle_leaves()?;
+        let mut levels = vec![leaves.iter().map(|(p, h)| leaf(p, h)).collect::<Vec<_>>()];
+        while let Some(level) = levels.last().filter(|l| l.len() > 1) {
+            let next = level
+                .chunks(2)
+                .map(|c| match c {
+                    [l, r] => node(l, r),
+                    [only] => *only,
+                    _ => unreachable!(),
+                })
+                .collect();
+            levels.push(next);
+        }
+        Ok((leaves, levels))
+    }
+
+    /// A single hash committing to every template this object (and every
+    /// object it creates) can produce, along with the path each was built
+    /// at. Use [`Object::merkle_proof`] to later show that a given template
+    /// is included. An object with no templates has the all-zero root.
+    /// Fails if any template's path is not known.
+    pub fn template_merkle_root(&self) -> Result<sha256::Hash, CompilationError> {
+        let (_, levels) = self.merkle_levels()?;
+        Ok(levels
+            .last()
+            .and_then(|l| l.first())
+            .copied()
+            .unwrap_or_else(|| sha256::Hash::from_inner([0; 32])))
+    }
+
+    /// A proof that the template built at `path` is committed to by
+    /// [`Object::template_merkle_root`], or `None` if no template was built
+    /// there (or the paths of the templates are not known).
+    pub fn merkle_proof(&self, path: &EffectPath) -> Option<MerkleProof> {
+        let (leaves, levels) = self.merkle_levels().ok()?;
+        let index = leaves.iter().position(|(p, _)| p == path)?;
+        let mut i = index;
+        let mut steps = vec![];
+        for level in &levels[..levels.len() - 1] {
+            if i % 2 == 1 {
+                steps.push(MerkleStep::Left(level[i - 1]));
+            } else if let Some(r) = level.get(i + 1) {
+                steps.push(MerkleStep::Right(*r));
+            }
+            i /= 2;
+        }
+        Some(MerkleProof {
+            path: leaves[index].0.clone(),
+            template: leaves[index].1,
+            steps,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::contract::test_helpers::{ctx, key, Leaf, Split};
+    use crate::contract::Compilable;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn test_merkle_proof() {
+        let obj = Split {
+            a: key(1),
+            b: key(2),
+        }
+        .compile(ctx(10_000))
+        .unwrap();
+        let root = obj.template_merkle_root().unwrap();
+        let manifest = obj.template_manifest().unwrap();
+        // an odd number of leaves, so one is carried up a level
+        assert_eq!(manifest.len(), 3);
+        for (path, h, _) in &manifest {
+            let proof = obj.merkle_proof(path).unwrap();
+            assert_eq!(proof.template, *h);
+            assert!(proof.verify(&root));
+            let mut forged = proof.clone();
+            forged.template = sha256::Hash::hash(b"not a template");
+            assert!(!forged.verify(&root));
+        }
+        let path = EffectPath::try_from(
+            "@root/@action/divide/@next/@default_effect/#1/@action/pay/@next/@default_effect",
+        )
+        .unwrap();
+        let proof = obj.merkle_proof(&path).unwrap();
+        assert!(!proof.verify(
+            &Leaf { to: key(1) }
+                .compile(ctx(10_000))
+                .unwrap()
+                .template_merkle_root()
+                .unwrap()
+        ));
+        assert!(obj
+            .merkle_proof(&EffectPath::try_from("@root/nowhere").unwrap())
+            .is_none());
+        assert_eq!(
+            Split {
+                a: key(1),
+                b: key(2)
+            }
+            .compile(ctx(10_000))
+            .unwrap()
+            .template_merkle_root()
+            .unwrap(),
+            root
+        );
+    }
+}
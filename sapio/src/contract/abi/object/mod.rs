@@ -11,6 +11,13 @@ pub use error::*;
 pub mod bind;
 pub mod descriptors;
 pub use descriptors::*;
+pub mod diff;
+pub use diff::{verify_against, Difference, ObjectDiff};
+pub mod inspect;
+pub mod merkle;
+pub use merkle::{MerkleProof, MerkleStep};
+pub mod program;
+pub use program::*;
 use sapio_base::simp::CompiledObjectLT;
 use sapio_base::simp::SIMPAttachableAt;
 use sapio_base::Clause;
@@ -104,8 +111,42 @@ impl ObjectMetadata {
     }
 }
 
+/// How a CTV-committed template is enforced on chain
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GuardKind {
+    /// # CTV
+    /// by `OP_CHECKTEMPLATEVERIFY`
+    Ctv,
+    /// # Emulated
+    /// by signatures from a CTV emulator
+    Emulated,
+}
+
+impl GuardKind {
+    /// classify `clause`, the clause committing to template `h`
+    pub fn of(clause: &Clause, h: sha256::Hash) -> Self {
+        fn has_ctv(c: &Clause, h: sha256::Hash) -> bool {
+            match c {
+                Clause::TxTemplate(t) => *t == h,
+                Clause::And(v) | Clause::Threshold(_, v) => v.iter().any(|c| has_ctv(c, h)),
+                Clause::Or(v) => v.iter().any(|(_, c)| has_ctv(c, h)),
+                _ => false,
+            }
+        }
+        if has_ctv(clause, h) {
+            GuardKind::Ctv
+        } else {
+            GuardKind::Emulated
+        }
+    }
+}
+
 /// Object holds a contract's complete context required post-compilation
 /// There is no guarantee that Object is properly constructed presently.
+///
+/// What compilation learned about the contract (e.g. `unused_effect_paths`)
+/// is only serialized with the `serde-introspection` feature, and is empty
+/// in an Object deserialized without it.
 //TODO: Make type immutable and correct by construction...
 #[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
 pub struct Object {
@@ -149,6 +190,49 @@ pub struct Object {
     pub amount_range: AmountRange,
     /// metadata generated for this contract
     pub metadata: ObjectMetadata,
+    /// branches whose conditional compilation marked them `Never`, and so
+    /// were not compiled
+    #[serde(rename = "unreachable_branches", default)]
+    #[cfg_attr(
+        feature = "serde-introspection",
+        serde(skip_serializing_if = "Vec::is_empty")
+    )]
+    #[cfg_attr(not(feature = "serde-introspection"), serde(skip_serializing))]
+    pub unreachable_branches: Vec<SArc<EffectPath>>,
+    /// for each branch with conditional compilation, the path its
+    /// conditions were evaluated at and whether the branch was compiled
+    #[serde(rename = "cond_comp_decisions", default)]
+    #[cfg_attr(
+        feature = "serde-introspection",
+        serde(skip_serializing_if = "Vec::is_empty")
+    )]
+    #[cfg_attr(not(feature = "serde-introspection"), serde(skip_serializing))]
+    pub cond_comp_decisions: Vec<(SArc<EffectPath>, bool)>,
+    /// how each template in `ctv_to_tx` is enforced
+    #[serde(rename = "template_guard_kinds", default)]
+    #[cfg_attr(
+        feature = "serde-introspection",
+        serde(skip_serializing_if = "BTreeMap::is_empty")
+    )]
+    #[cfg_attr(not(feature = "serde-introspection"), serde(skip_serializing))]
+    pub template_guard_kinds: BTreeMap<sha256::Hash, GuardKind>,
+    /// paths in the effect DB, at or below this object, that compilation
+    /// never consulted
+    #[serde(rename = "unused_effects", default)]
+    #[cfg_attr(
+        feature = "serde-introspection",
+        serde(skip_serializing_if = "Vec::is_empty")
+    )]
+    #[cfg_attr(not(feature = "serde-introspection"), serde(skip_serializing))]
+    pub unused_effect_paths: Vec<SArc<EffectPath>>,
+    /// arbitrary annotations (e.g. doc strings or UI hints) for paths such
+    /// as named fields, see [`Object::set_path_metadata`]
+    #[serde(
+        rename = "path_metadata",
+        skip_serializing_if = "BTreeMap::is_empty",
+        default
+    )]
+    pub path_metadata: BTreeMap<SArc<EffectPath>, BTreeMap<String, serde_json::Value>>,
 }
 
 impl Object {
@@ -172,6 +256,11 @@ impl Object {
                 a
             }),
             metadata: Default::default(),
+            unreachable_branches: vec![],
+            template_guard_kinds: Default::default(),
+            cond_comp_decisions: vec![],
+            unused_effect_paths: vec![],
+            path_metadata: Default::default(),
         }
     }
 
@@ -203,6 +292,11 @@ impl Object {
             descriptor: None,
             amount_range: AmountRange::new(),
             metadata: Default::default(),
+            unreachable_branches: vec![],
+            template_guard_kinds: Default::default(),
+            cond_comp_decisions: vec![],
+            unused_effect_paths: vec![],
+            path_metadata: Default::default(),
         })
     }
 
@@ -230,6 +324,183 @@ impl Object {
                 a
             }),
             metadata: Default::default(),
+            unreachable_branches: vec![],
+            template_guard_kinds: Default::default(),
+            cond_comp_decisions: vec![],
+            unused_effect_paths: vec![],
+            path_metadata: Default::default(),
+        }
+    }
+
+    /// Attach `value` under `key` to `path` (typically a
+    /// `PathFragment::Named` field), replacing any previous value for `key`.
+    pub fn set_path_metadata(&mut self, path: EffectPath, key: &str, value: serde_json::Value) {
+        self.path_metadata
+            .entry(SArc(Arc::new(path)))
+            .or_default()
+            .insert(key.into(), value);
+    }
+
+    /// Remove everything (templates, continuations, and their records)
+    /// built at a path failing `keep`, in this object and every object it
+    /// creates, e.g. to strip test-only branches before deployment.
+    ///
+    /// The address and descriptor are not changed: a pruned branch remains
+    /// spendable on chain, it is just no longer tracked.
+    pub fn prune<F: Fn(&EffectPath) -> bool>(&mut self, keep: F) {
+        self.prune_with(&keep)
+    }
+
+    fn prune_with(&mut self, keep: &dyn Fn(&EffectPath) -> bool) {
+        let kept = |p: &SArc<EffectPath>| keep(p.0.as_ref());
+        let root = &self.root_path;
+        for txs in [&mut self.ctv_to_tx, &mut self.suggested_txs] {
+            let pruned: Vec<sha256::Hash> = txs
+                .iter()
+                .filter(|(_, t)| !kept(t.path.as_ref().unwrap_or(root)))
+                .map(|(h, _)| *h)
+                .collect();
+            for h in pruned {
+                txs.remove(&h);
+                self.template_guard_kinds.remove(&h);
+            }
+            for t in txs.values_mut() {
+                for o in t.outputs.iter_mut() {
+                    o.contract.prune_with(keep);
+                }
+            }
+        }
+        self.continue_apis.retain(|p, _| kept(p));
+        self.unreachable_branches.retain(kept);
+        self.cond_comp_decisions.retain(|(p, _)| kept(p));
+        self.unused_effect_paths.retain(kept);
+        self.path_metadata.retain(|p, _| kept(p));
+    }
+
+    /// The metadata attached to `path` under `key`, if any
+    pub fn path_metadata(&self, path: &EffectPath, key: &str) -> Option<&serde_json::Value> {
+        self.path_metadata
+            .get(&SArc(Arc::new(path.clone())))?
+            .get(key)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate as sapio;
+    use crate::contract::test_helpers::{ctx, key};
+    use crate::contract::{Compilable, Contract};
+    use crate::*;
+    use bitcoin::XOnlyPublicKey;
+    use std::convert::TryFrom;
+
+    struct Split {
+        to: XOnlyPublicKey,
+    }
+    impl Split {
+        #[then]
+        fn halves(self, mut ctx: sapio::Context) {
+            let a = ctx.derive_num(0u64)?.template().add_output(
+                Amount::from_sat(1000),
+                &self.to,
+                None,
+            )?;
+            let b = ctx.derive_num(1u64)?.template().add_output(
+                Amount::from_sat(2000),
+                &self.to,
+                None,
+            )?;
+            Ok(Box::new(vec![a.into(), b.into()].into_iter().map(Ok)))
+        }
+    }
+    impl Contract for Split {
+        declare! {then, Self::halves}
+        declare! {non updatable}
+    }
+
+    #[test]
+    fn test_prune() {
+        let mut obj = Split { to: key(1) }.compile(ctx(10_000)).unwrap();
+        assert_eq!(obj.ctv_to_tx.len(), 2);
+        let address = bitcoin::Script::from(obj.address.clone());
+        obj.prune(|p| !p.iter().any(|f| *f == PathFragment::Branch(0)));
+        let left: Vec<String> = obj
+            .ctv_to_tx
+            .values()
+            .map(|t| String::from(t.path.clone().unwrap().0.as_ref().clone()))
+            .collect();
+        assert_eq!(left, vec!["@root/@action/halves/@next/@default_effect/#1"]);
+        assert_eq!(
+            obj.ctv_to_tx.values().next().unwrap().max,
+            Amount::from_sat(2000)
+        );
+        assert_eq!(obj.template_guard_kinds.len(), 1);
+        assert!(obj
+            .template_guard_kinds
+            .contains_key(obj.ctv_to_tx.keys().next().unwrap()));
+        assert_eq!(bitcoin::Script::from(obj.address.clone()), address);
+    }
+
+    #[test]
+    fn test_path_metadata() {
+        let addr = bitcoin::Address::p2wsh(&bitcoin::Script::new(), bitcoin::Network::Regtest);
+        let mut obj = Object::from_address(addr, None);
+        let field = EffectPath::try_from("@root/@action/pay/amount").unwrap();
+        assert!(obj.path_metadata(&field, "doc").is_none());
+        obj.set_path_metadata(field.clone(), "doc", "sats to pay".into());
+        obj.set_path_metadata(field.clone(), "widget", "slider".into());
+        obj.set_path_metadata(field.clone(), "doc", "satoshis to pay".into());
+        assert_eq!(
+            obj.path_metadata(&field, "doc"),
+            Some(&serde_json::json!("satoshis to pay"))
+        );
+        let json = serde_json::to_value(&obj).unwrap();
+        assert_eq!(
+            json["path_metadata"]["@root/@action/pay/amount"]["widget"],
+            "slider"
+        );
+        let back: Object = serde_json::from_value(json).unwrap();
+        assert_eq!(back.path_metadata, obj.path_metadata);
+    }
+
+    #[test]
+    fn test_introspection_wire_format() {
+        let obj = Split { to: key(1) }.compile(ctx(10_000)).unwrap();
+        assert!(!obj.template_guard_kinds.is_empty());
+        let json = serde_json::to_value(&obj).unwrap();
+        let serialized = cfg!(feature = "serde-introspection");
+        assert_eq!(json.get("template_guard_kinds").is_some(), serialized);
+        for t in json["template_hash_to_template_map"]
+            .as_object()
+            .unwrap()
+            .values()
+        {
+            assert_eq!(t.get("template_path").is_some(), serialized);
+        }
+        let back: Object = serde_json::from_value(json).unwrap();
+        assert_eq!(back.template_guard_kinds.is_empty(), !serialized);
+        assert_eq!(
+            back.ctv_to_tx.keys().collect::<Vec<_>>(),
+            obj.ctv_to_tx.keys().collect::<Vec<_>>()
+        );
+        // queries by path fail, rather than guess, without the paths
+        let hash = *obj.ctv_to_tx.keys().next().unwrap();
+        let path = obj.path_for_template(&hash).unwrap();
+        if serialized {
+            assert_eq!(
+                back.template_manifest().unwrap(),
+                obj.template_manifest().unwrap()
+            );
+            assert_eq!(back.path_for_template(&hash), Some(path));
+        } else {
+            assert!(matches!(
+                back.template_manifest(),
+                Err(CompilationError::UnknownTemplatePath { hash: h }) if h == hash
+            ));
+            assert!(back.path_for_template(&hash).is_none());
+            assert!(back.template_guard_kind(&path).is_none());
+            assert!(back.check_branch_selection(&path).is_err());
         }
     }
 }
@@ -0,0 +1,164 @@
+// Copyright Judica, Inc 2022
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+//  License, v. 2.0. If a copy of the MPL was not distributed with this
+//  file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! The canonical wire format for a compiled contract
+use crate::contract::object::Object;
+use crate::util::extended_address::ExtendedAddress;
+use bitcoin::consensus::encode::serialize_hex;
+use bitcoin::hashes::sha256;
+use bitcoin::util::amount::Amount;
+use sapio_base::effects::EffectPath;
+use sapio_base::serialization_helpers::SArc;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+/// The version of the [`ProgramJson`] format emitted by this library
+pub const PROGRAM_JSON_VERSION: u64 = 1;
+
+/// # Sapio Program
+/// The canonical representation of a compiled contract consumed by other
+/// Sapio tools: every contract it creates, where each is located, and the
+/// templates each may spend to.
+///
+/// Changes to this format must bump [`PROGRAM_JSON_VERSION`].
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
+pub struct ProgramJson {
+    /// # Format Version
+    pub version: u64,
+    /// # Contracts
+    /// The root contract first, followed by every contract it creates
+    /// (parents before children).
+    pub contracts: Vec<ProgramContract>,
+}
+
+/// # Contract
+/// A single contract within a [`ProgramJson`]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
+pub struct ProgramContract {
+    /// # Path
+    /// The path this contract was compiled at
+    pub path: SArc<EffectPath>,
+    /// # Address
+    pub address: ExtendedAddress,
+    /// # Templates
+    /// Templates this contract may spend to
+    pub templates: Vec<ProgramTemplate>,
+    /// # Continuation Points
+    /// Paths at which this contract accepts updates
+    pub continuation_points: Vec<SArc<EffectPath>>,
+}
+
+/// # Template
+/// A transaction template within a [`ProgramContract`]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
+pub struct ProgramTemplate {
+    /// # Path
+    /// The path this template was built at
+    pub path: SArc<EffectPath>,
+    /// # CTV Hash
+    pub ctv_hash: sha256::Hash,
+    /// # CTV Enforced
+    /// is this template enforced by CTV (rather than only suggested)?
+    pub ctv_enforced: bool,
+    /// # Amount Required (sats)
+    #[serde(with = "bitcoin::util::amount::serde::as_sat")]
+    #[schemars(with = "i64")]
+    pub amount_sats: Amount,
+    /// # Transaction
+    /// Hex encoded unsigned transaction
+    pub tx_hex: String,
+    /// # Output Addresses
+    /// The address of the contract created by each output, in order
+    pub outputs: Vec<ExtendedAddress>,
+}
+
+impl Object {
+    /// Convert to the canonical [`ProgramJson`] representation
+    pub fn to_program_json(&self) -> ProgramJson {
+        let mut contracts = vec![];
+        let mut queue = VecDeque::from([self]);
+        while let Some(obj) = queue.pop_front() {
+            let committed = obj.ctv_to_tx.values().map(|t| (t, true));
+            let suggested = obj.suggested_txs.values().map(|t| (t, false));
+            let templates = committed
+                .chain(suggested)
+                .map(|(t, ctv_enforced)| {
+                    queue.extend(t.outputs.iter().map(|o| &o.contract));
+                    ProgramTemplate {
+                        path: t.path.clone().unwrap_or_else(|| obj.root_path.clone()),
+                        ctv_hash: t.hash(),
+                        ctv_enforced,
+                        amount_sats: t.max,
+                        tx_hex: serialize_hex(&t.tx),
+                        outputs: t
+                            .outputs
+                            .iter()
+                            .map(|o| o.contract.address.clone())
+                            .collect(),
+                    }
+                })
+                .collect();
+            contracts.push(ProgramContract {
+                path: obj.root_path.clone(),
+                address: obj.address.clone(),
+                templates,
+                continuation_points: obj.continue_apis.keys().cloned().collect(),
+            });
+        }
+        ProgramJson {
+            version: PROGRAM_JSON_VERSION,
+            contracts,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate as sapio;
+    use crate::contract::test_helpers::{ctx, key};
+    use crate::contract::{Compilable, Contract};
+    use crate::*;
+    use bitcoin::util::amount::Amount;
+    use bitcoin::XOnlyPublicKey;
+
+    struct Escrow {
+        buyer: XOnlyPublicKey,
+        seller: XOnlyPublicKey,
+    }
+    impl Escrow {
+        #[then]
+        fn release(self, ctx: sapio::Context) {
+            ctx.template()
+                .add_output(Amount::from_sat(5000), &self.seller, None)?
+                .into()
+        }
+        #[then]
+        fn refund(self, ctx: sapio::Context) {
+            ctx.template()
+                .add_output(Amount::from_sat(5000), &self.buyer, None)?
+                .into()
+        }
+    }
+    impl Contract for Escrow {
+        declare! {then, Self::release, Self::refund}
+        declare! {non updatable}
+    }
+
+    /// Pins the wire format. If this fails because the format was changed
+    /// on purpose, bump `PROGRAM_JSON_VERSION` and update the snapshot.
+    #[test]
+    fn test_program_json_snapshot() {
+        let obj = Escrow {
+            buyer: key(1),
+            seller: key(2),
+        }
+        .compile(ctx(10_000))
+        .unwrap();
+        let json = serde_json::to_string_pretty(&obj.to_program_json()).unwrap();
+        assert_eq!(json, include_str!("snapshots/program_json.json").trim_end());
+    }
+}
@@ -162,6 +162,13 @@ where
         (self.func)(cself, ctx, args)
     }
     fn call_json(&self, cself: &ContractSelf, ctx: Context, o: serde_json::Value) -> TxTmplIt {
+        // check against the schema first, for a precise message per field
+        if let Some(schema) = &self.schema {
+            let errors = crate::util::json_schema::validate(schema, &o);
+            if !errors.is_empty() {
+                return Err(CompilationError::SchemaValidationFailed { errors });
+            }
+        }
         serde_json::from_value(o)
             .map_err(EffectDBError::SerializationError)
             .map_err(CompilationError::EffectDBError)
@@ -21,18 +21,17 @@ use sapio_base::{
 /// instance*.
 pub enum Guard<ContractSelf> {
     /// Cache Variant should only be called one time per contract and the result saved
-    Cache(
-        fn(&ContractSelf, Context) -> Clause,
-        Option<SimpGen<ContractSelf>>,
-    ),
+    Cache(GuardFn<ContractSelf>, Option<SimpGen<ContractSelf>>),
     /// Fresh Variant may be called repeatedly
-    Fresh(
-        fn(&ContractSelf, Context) -> Clause,
-        Option<SimpGen<ContractSelf>>,
-    ),
+    Fresh(GuardFn<ContractSelf>, Option<SimpGen<ContractSelf>>),
 }
 
-/// A Function that can be used to generate metadata for a Guard
+/// A Function that generates a Guard's clause, or explains why it could not
+pub type GuardFn<ContractSelf> = fn(&ContractSelf, Context) -> Result<Clause, CompilationError>;
+
+/// A Function that can be used to generate metadata for a Guard. Its context
+/// is at `@guard/@metadata` when the guard is the only one of a function, and
+/// at `@guard/#i/@metadata` for the `i`th of several.
 pub type SimpGen<ContractSelf> =
     fn(
         cself: &ContractSelf,
@@ -5,12 +5,14 @@
 //  file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
 //! Caches for guards
+use super::util::{check_guard_context, find_guard_conflict};
 use super::Context;
 use super::InternalCompilerTag;
 use crate::contract::actions::Guard;
+use crate::contract::actions::GuardFn;
 use crate::contract::actions::SimpGen;
 use crate::contract::CompilationError;
-use sapio_base::effects::PathFragment;
+use sapio_base::effects::{EffectPath, PathFragment};
 use sapio_base::simp::GuardLT;
 use sapio_base::simp::SIMPAttachableAt;
 use sapio_base::Clause;
@@ -18,9 +20,11 @@ use std::collections::BTreeMap;
 use std::sync::Arc;
 
 pub type GuardSimps = Vec<Arc<dyn SIMPAttachableAt<GuardLT>>>;
+/// a guard's path, clause, and SIMPs
+pub(crate) type GuardEntry = (Arc<EffectPath>, Clause, GuardSimps);
 pub(crate) enum CacheEntry<T> {
     Cached(Clause, GuardSimps),
-    Fresh(fn(&T, Context) -> Clause, Option<SimpGen<T>>),
+    Fresh(GuardFn<T>, Option<SimpGen<T>>),
 }
 
 /// GuardCache assists with caching the computation of guard functions
@@ -28,6 +32,15 @@ pub(crate) enum CacheEntry<T> {
 pub(crate) struct GuardCache<T> {
     cache: BTreeMap<usize, Option<CacheEntry<T>>>,
 }
+/// run a guard, attaching its path to any error it returns
+fn call_guard<T>(f: GuardFn<T>, t: &T, ctx: Context) -> Result<Clause, CompilationError> {
+    let path = ctx.path().as_ref().clone();
+    f(t, ctx).map_err(|error| CompilationError::GuardFailed {
+        path,
+        error: Box::new(error),
+    })
+}
+
 impl<T> GuardCache<T> {
     pub fn new() -> Self {
         GuardCache {
@@ -41,10 +54,13 @@ impl<T> GuardCache<T> {
         simp_ctx: Context,
     ) -> Result<Option<CacheEntry<T>>, CompilationError> {
         match g {
-            Some(Guard::Cache(f, Some(simp_gen))) => {
-                Ok(Some(CacheEntry::Cached(f(t, ctx), simp_gen(t, simp_ctx)?)))
+            Some(Guard::Cache(f, Some(simp_gen))) => Ok(Some(CacheEntry::Cached(
+                call_guard(f, t, ctx)?,
+                simp_gen(t, simp_ctx)?,
+            ))),
+            Some(Guard::Cache(f, None)) => {
+                Ok(Some(CacheEntry::Cached(call_guard(f, t, ctx)?, vec![])))
             }
-            Some(Guard::Cache(f, None)) => Ok(Some(CacheEntry::Cached(f(t, ctx), vec![]))),
             Some(Guard::Fresh(f, simp_gen)) => Ok(Some(CacheEntry::Fresh(f, simp_gen))),
             None => Ok(None),
         }
@@ -73,7 +89,7 @@ impl<T> GuardCache<T> {
         match r {
             Some(CacheEntry::Cached(s, v)) => Ok(Some((s.clone(), v.to_vec()))),
             Some(CacheEntry::Fresh(f, s)) => Ok(Some((
-                f(t, ctx),
+                call_guard(*f, t, ctx)?,
                 match s {
                     Some(f2) => f2(t, simp_ctx)?,
                     None => vec![],
@@ -89,27 +105,48 @@ pub(crate) fn create_guards<T>(
     mut ctx: Context,
     guards: &[fn() -> Option<Guard<T>>],
     gc: &mut GuardCache<T>,
-) -> Result<(Clause, Vec<(Clause, GuardSimps)>), CompilationError> {
+) -> Result<(Clause, Vec<GuardEntry>), CompilationError> {
+    // a lone guard generates its SIMPs at `@guard/@metadata`, but with more
+    // than one each needs its own, at `@guard/#i/@metadata`
+    let single = guards.len() == 1;
     let v = guards
         .iter()
-        .zip((0..).flat_map(|i| {
-            let new = ctx.derive(PathFragment::Branch(i)).ok()?;
-            let simp = ctx.derive(PathFragment::Metadata).ok()?;
-            Some((new, simp))
-        }))
-        .filter_map(|(x, (c, simp_c))| gc.get(self_ref, *x, c, simp_c).transpose())
-        .collect::<Result<Vec<_>, _>>()?;
+        .enumerate()
+        .map(|(i, x)| {
+            let mut c = ctx.derive(PathFragment::Branch(i as u64))?;
+            let simp_c = if single {
+                ctx.derive(PathFragment::Metadata)?
+            } else {
+                c.derive(PathFragment::Metadata)?
+            };
+            let path = c.path().clone();
+            let context = c.script_context();
+            let entry = gc.get(self_ref, *x, c, simp_c)?;
+            if let Some((g, _)) = &entry {
+                check_guard_context(g, context)?;
+            }
+            Ok(entry.map(|(g, simps)| (path, g, simps)))
+        })
+        .filter_map(Result::transpose)
+        .collect::<Result<Vec<_>, CompilationError>>()?;
     let mut clauses: Vec<_> = v
         .iter()
-        .map(|x| &x.0)
+        .map(|x| &x.1)
         .filter(|x| **x != Clause::Trivial)
         .cloned()
         .collect(); // no point in using any Trivials
-    if clauses.is_empty() {
-        Ok((Clause::Trivial, v))
+    let combined = if clauses.is_empty() {
+        Clause::Trivial
     } else if clauses.len() == 1 {
-        Ok((clauses.pop().unwrap(), v))
+        clauses.pop().unwrap()
     } else {
-        Ok((Clause::And(clauses), v))
+        Clause::And(clauses)
+    };
+    if let Some(detail) = find_guard_conflict(&combined) {
+        return Err(CompilationError::GuardConflict {
+            path: ctx.path().as_ref().clone(),
+            detail,
+        });
     }
+    Ok((combined, v))
 }
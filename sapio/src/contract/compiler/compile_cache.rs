@@ -0,0 +1,202 @@
+// Copyright Judica, Inc 2022
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+//  License, v. 2.0. If a copy of the MPL was not distributed with this
+//  file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! a cache of whole compilations, for servers seeing repeated contracts
+use crate::contract::{Compilable, CompilationError, Compiled, Context};
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+
+/// The result of a compilation, shareable so that failures can be cached
+pub type CachedResult = Result<Compiled, Arc<CompilationError>>;
+
+/// A `CompileCache` remembers the result of compiling a contract, keyed by
+/// a hash of the contract's type name, its serialization, and the
+/// [`Context`]'s parameters
+/// (see [`CompileCache::key`]), holding at most `capacity` results and
+/// evicting the oldest first.
+///
+/// The context's emulator, fee estimator, and key resolver are not part of
+/// the key, so contexts which differ in those must not share a cache.
+#[derive(Debug, Default)]
+pub struct CompileCache {
+    capacity: usize,
+    entries: HashMap<sha256::Hash, CachedResult>,
+    order: VecDeque<sha256::Hash>,
+    hits: usize,
+}
+
+impl CompileCache {
+    /// a cache holding at most `capacity` results
+    pub fn new(capacity: usize) -> Self {
+        CompileCache {
+            capacity,
+            ..Default::default()
+        }
+    }
+
+    /// the key `contract` compiled under `ctx` is cached at
+    pub fn key<C: Serialize>(
+        contract: &C,
+        ctx: &Context,
+    ) -> Result<sha256::Hash, CompilationError> {
+        let mut engine = sha256::Hash::engine();
+        // types which serialize alike (e.g. unit structs) must not collide
+        engine.input(std::any::type_name::<C>().as_bytes());
+        engine.input(&[0]);
+        serde_json::to_writer(&mut engine, contract)?;
+        engine.input(&[0]);
+        ctx.hash_params(&mut engine)?;
+        Ok(sha256::Hash::from_engine(engine))
+    }
+
+    /// the result cached at `key`, if any
+    pub fn get(&self, key: &sha256::Hash) -> Option<&CachedResult> {
+        self.entries.get(key)
+    }
+
+    /// cache `result` at `key`, evicting the oldest results if full
+    pub fn insert(&mut self, key: sha256::Hash, result: CachedResult) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.insert(key, result).is_none() {
+            self.order.push_back(key);
+        }
+        while self.entries.len() > self.capacity {
+            if let Some(old) = self.order.pop_front() {
+                self.entries.remove(&old);
+            }
+        }
+    }
+
+    /// Compile `contract` under `ctx`, returning the cached result if
+    /// there is one.
+    pub fn compile<C: Compilable + Serialize>(
+        &mut self,
+        contract: &C,
+        ctx: Context,
+    ) -> CachedResult {
+        let key = Self::key(contract, &ctx)?;
+        if let Some(r) = self.entries.get(&key).cloned() {
+            self.hits += 1;
+            return r;
+        }
+        let result = contract.compile(ctx).map_err(Arc::new);
+        self.insert(key, result.clone());
+        result
+    }
+
+    /// how many compilations have been answered from the cache
+    pub fn hits(&self) -> usize {
+        self.hits
+    }
+
+    /// how many results are cached
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// is nothing cached?
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate as sapio;
+    use crate::contract::test_helpers::{ctx, key};
+    use crate::contract::Contract;
+    use crate::*;
+    use bitcoin::util::amount::Amount;
+    use bitcoin::XOnlyPublicKey;
+
+    #[derive(Serialize)]
+    struct Pay {
+        to: XOnlyPublicKey,
+        sats: u64,
+    }
+    impl Pay {
+        #[then]
+        fn send(self, ctx: sapio::Context) {
+            ctx.template()
+                .add_output(Amount::from_sat(self.sats), &self.to, None)?
+                .into()
+        }
+    }
+    impl Contract for Pay {
+        declare! {then, Self::send}
+        declare! {non updatable}
+    }
+
+    #[test]
+    fn test_compile_cache() {
+        let mut cache = CompileCache::new(2);
+        let pay = |sats| Pay { to: key(1), sats };
+        let first = cache.compile(&pay(1000), ctx(10_000)).unwrap();
+        assert_eq!(cache.hits(), 0);
+        let second = cache.compile(&pay(1000), ctx(10_000)).unwrap();
+        assert_eq!(cache.hits(), 1);
+        assert_eq!(first.diff(&second), Default::default());
+
+        // different funds are a different key, and failures are cached too
+        assert!(cache.compile(&pay(1000), ctx(500)).is_err());
+        assert_eq!(cache.len(), 2);
+        let e = cache.compile(&pay(1000), ctx(500)).unwrap_err();
+        assert!(matches!(*e, CompilationError::OutOfFunds { .. }));
+        assert_eq!(cache.hits(), 2);
+
+        // the oldest result is evicted when full
+        cache.compile(&pay(2000), ctx(10_000)).unwrap();
+        assert_eq!(cache.len(), 2);
+        let k = CompileCache::key(&pay(1000), &ctx(10_000)).unwrap();
+        assert!(cache.get(&k).is_none());
+    }
+
+    #[derive(Serialize)]
+    struct ToOne;
+    impl ToOne {
+        #[then]
+        fn send(self, ctx: sapio::Context) {
+            ctx.template()
+                .add_output(Amount::from_sat(1000), &key(1), None)?
+                .into()
+        }
+    }
+    impl Contract for ToOne {
+        declare! {then, Self::send}
+        declare! {non updatable}
+    }
+    #[derive(Serialize)]
+    struct ToTwo;
+    impl ToTwo {
+        #[then]
+        fn send(self, ctx: sapio::Context) {
+            ctx.template()
+                .add_output(Amount::from_sat(1000), &key(2), None)?
+                .into()
+        }
+    }
+    impl Contract for ToTwo {
+        declare! {then, Self::send}
+        declare! {non updatable}
+    }
+
+    #[test]
+    fn test_compile_cache_keys_by_type() {
+        let mut cache = CompileCache::new(2);
+        let one = cache.compile(&ToOne, ctx(10_000)).unwrap();
+        let two = cache.compile(&ToTwo, ctx(10_000)).unwrap();
+        assert_eq!(cache.hits(), 0);
+        assert_ne!(
+            one.ctv_to_tx.keys().collect::<Vec<_>>(),
+            two.ctv_to_tx.keys().collect::<Vec<_>>()
+        );
+    }
+}
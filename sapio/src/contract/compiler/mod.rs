@@ -11,13 +11,20 @@ use super::CompilationError;
 use super::Compiled;
 use super::Context;
 use crate::contract::abi::continuation::ContinuationPoint;
+use crate::contract::abi::object::SupportedDescriptors;
 use crate::contract::actions::conditional_compile::CCILWrapper;
 use crate::contract::actions::CallableAsFoF;
+use crate::contract::context::ScriptContext;
+use crate::contract::context::ThenOrder;
+use crate::contract::object::GuardKind;
 use crate::contract::TxTmplIt;
 use crate::util::amountrange::AmountRange;
 
+use crate::template::{has_timelock, Template};
 use ::miniscript::*;
+use bitcoin::hashes::sha256;
 use bitcoin::schnorr::TweakedPublicKey;
+use bitcoin::util::amount::Amount;
 use bitcoin::XOnlyPublicKey;
 use sapio_base::effects::EffectDB;
 use sapio_base::effects::EffectPath;
@@ -29,8 +36,13 @@ use std::collections::{BTreeMap, BTreeSet};
 
 use std::sync::Arc;
 mod cache;
+pub mod compile_cache;
+pub mod resume;
+pub mod timing;
 mod util;
 use cache::*;
+pub use util::check_policy_context;
+pub use util::nums_key;
 use util::*;
 /// Used to prevent unintended callers to internal_clone.
 pub struct InternalCompilerTag {
@@ -90,6 +102,7 @@ fn compute_all_effects<C, A: Default>(
         return Ok(def);
     }
     let mut applied_effects_ctx = top_effect_ctx.derive(PathFragment::Effects)?;
+    top_effect_ctx.record_effect_lookup(InternalCompilerTag { _secret: () });
     top_effect_ctx
         .get_effects(InternalCompilerTag { _secret: () })
         .get_value(top_effect_ctx.path())
@@ -152,7 +165,20 @@ where
     /// The main Compilation Logic for a Contract.
     /// TODO: Better Document Semantics
     fn compile(&self, mut ctx: Context) -> Result<Compiled, CompilationError> {
+        let started = std::time::Instant::now();
+        let shallow = ctx.is_shallow();
+        if let Some(c) = ctx.checkpoints().filter(|_| !shallow) {
+            let resumed = c.lock().expect("checkpoint lock poisoned").resume(&ctx)?;
+            if let Some(obj) = resumed {
+                return Ok(obj);
+            }
+        }
+        let _frame = ctx.enter_compile((
+            self as *const Self as *const () as usize,
+            std::any::type_name::<Self>(),
+        ))?;
         let self_ref = self.get_inner_ref();
+        let deterministic = ctx.is_deterministic();
         let mut guard_clauses = GuardCache::new();
 
         // The below maps track metadata that is useful for consumers / verification.
@@ -160,6 +186,8 @@ where
         let mut comitted_txns = BTreeMap::new();
         // All other transactions
         let mut other_txns = BTreeMap::new();
+        // the function (and template path) which first produced each template
+        let mut template_origins = BTreeMap::new();
 
         // the min and max amount of funds spendable in the transactions
         let mut amount_range = AmountRange::new();
@@ -169,6 +197,14 @@ where
         // TODO: Maybe do not just cloned?
         let amount_range_ctx = ctx.derive(PathFragment::Cloned)?;
         let ensured_amount = self.ensure_amount(amount_range_ctx)?;
+        // fail fast, before expanding any templates, if the contract can
+        // not possibly be funded
+        if ensured_amount > ctx.funds() {
+            return Err(CompilationError::OutOfFunds {
+                available: ctx.funds(),
+                required: ensured_amount,
+            });
+        }
         amount_range.update_range(ensured_amount);
 
         // The code for then_fns and finish_or_fns is very similar, differing
@@ -179,7 +215,10 @@ where
         // we need a unique context for each.
         let mut action_ctx = ctx.derive(PathFragment::Action)?;
         let mut renamer = Renamer::new();
-        let all_values = self
+        let mut unreachable_branches = vec![];
+        let mut guard_kinds = BTreeMap::new();
+        let mut cond_comp_decisions = vec![];
+        let mut then_fns = self
             .then_fns()
             .iter()
             .filter_map(|func| func())
@@ -187,6 +226,12 @@ where
             // trait object since it only exists temporarily.
             // TODO: Without allocations?
             .map(|x| -> Box<dyn CallableAsFoF<_, _>> { Box::new(x) })
+            .collect::<Vec<_>>();
+        if ctx.then_order() == ThenOrder::ByName {
+            then_fns.sort_by(|a, b| a.get_name().cmp(b.get_name()));
+        }
+        let all_values = then_fns
+            .into_iter()
             .chain(self.finish_or_fns().iter().filter_map(|func| func()))
             .map(|mut x| {
                 let new_name = Arc::new(renamer.get_name(x.get_name().as_ref()));
@@ -202,9 +247,18 @@ where
                     // this should always be Ok(_)
                     .derive(PathFragment::CondCompIf)
                     .expect(UNIQUE_DERIVE_PANIC_MSG);
-                match CCILWrapper(func.get_conditional_compile_if())
-                    .assemble(self_ref, &mut this_ctx)
-                {
+                let conditions = func.get_conditional_compile_if();
+                let decision = CCILWrapper(conditions).assemble(self_ref, &mut this_ctx);
+                if !conditions.is_empty() {
+                    let taken = !matches!(
+                        decision,
+                        ConditionalCompileType::Skippable
+                            | ConditionalCompileType::Never
+                            | ConditionalCompileType::Fail(_)
+                    );
+                    cond_comp_decisions.push((SArc(this_ctx.path().clone()), taken));
+                }
+                match decision {
                     // Throw errors
                     ConditionalCompileType::Fail(errors) => {
                         Some(Err(CompilationError::ConditionalCompilationFailed(errors)))
@@ -216,22 +270,50 @@ where
                     // Nullable
                     ConditionalCompileType::Nullable => Some(Ok((f_ctx, func, Nullable::Yes))),
                     // Drop these
-                    ConditionalCompileType::Skippable | ConditionalCompileType::Never => None,
+                    ConditionalCompileType::Skippable => None,
+                    // Drop these, remembering them for linting
+                    ConditionalCompileType::Never => {
+                        unreachable_branches.push(SArc(f_ctx.path().clone()));
+                        None
+                    }
                 }
             })
             .map(|r| {
                 let (mut f_ctx, func, nullability) = r?;
+                let origin = f_ctx.path().clone();
                 let gctx = f_ctx.derive(PathFragment::Guard)?;
                 let simp_ctx = f_ctx.derive(PathFragment::Metadata)?;
                 // TODO: Suggested path frag?
                 let (guards, guard_metadata) =
                     create_guards(self_ref, gctx, func.get_guard(), &mut guard_clauses)?;
+                let timelock_guards: Vec<_> = guard_metadata
+                    .iter()
+                    .filter(|(_, g, _)| has_timelock(g))
+                    .map(|(path, g, _)| (SArc(path.clone()), g.clone()))
+                    .collect();
                 let effect_ctx = f_ctx.derive(if func.get_returned_txtmpls_modify_guards() {
                     PathFragment::Next
                 } else {
                     PathFragment::Suggested
                 })?;
                 let effect_path = effect_ctx.path().clone();
+                // a continuation always compiles its guards into a script,
+                // which would be a dead output (CTV branches check this
+                // once their templates are known)
+                if !func.get_returned_txtmpls_modify_guards()
+                    && simplify_policy(&guards) == Clause::Unsatisfiable
+                {
+                    return Err(CompilationError::UnspendableBranch {
+                        path: origin.as_ref().clone(),
+                    });
+                }
+                // continuations never change the address, so a shallow
+                // compile skips straight to their guards
+                if shallow && !func.get_returned_txtmpls_modify_guards() {
+                    let v = optimizer_flatten_and_compile(guards, deterministic)?;
+                    check_script_size(&v, &origin)?;
+                    return Ok((None, v, guard_metadata));
+                }
                 let transactions = compute_all_effects(effect_ctx, self_ref, func.as_ref());
                 // If no guards and not CTV, then nothing gets added (not
                 // interpreted as Trivial True)
@@ -241,8 +323,12 @@ where
                 // instead of just an empty iterator.
                 let txtmpl_clauses = transactions?
                     .map(|r_txtmpl| {
-                        let txtmpl = r_txtmpl?;
+                        let mut txtmpl = r_txtmpl?;
+                        txtmpl.timelock_guards = timelock_guards.clone();
                         let h = txtmpl.hash();
+                        check_duplicate_template(&mut template_origins, &ctx, &origin, h, &txtmpl)?;
+                        warn_nonstandard_timelocks(&ctx, &origin, &txtmpl);
+                        check_weight(&origin, &txtmpl)?;
                         amount_range.update_range(txtmpl.max);
                         // Add the addition guards to these clauses
                         let txtmpl = if func.get_returned_txtmpls_modify_guards() {
@@ -253,7 +339,13 @@ where
                         .entry(h)
                         .or_insert(txtmpl);
                         let extractor = func.get_extract_clause_from_txtmpl();
-                        (extractor)(txtmpl, &ctx)
+                        let clause = (extractor)(txtmpl, &ctx)?;
+                        if let (true, Some(c)) =
+                            (func.get_returned_txtmpls_modify_guards(), &clause)
+                        {
+                            guard_kinds.insert(h, GuardKind::of(c, h));
+                        }
+                        Ok(clause)
                     })
                     // Drop None values
                     .filter_map(|s| s.transpose())
@@ -261,11 +353,16 @@ where
                     .collect::<Result<Vec<Clause>, CompilationError>>()?;
 
                 // N.B. the order of the matches below is significant
-                Ok(if func.get_returned_txtmpls_modify_guards() {
+                let (api, v) = if func.get_returned_txtmpls_modify_guards() {
                     (
                         None,
-                        combine_txtmpls(nullability, txtmpl_clauses, guards)?,
-                        guard_metadata,
+                        combine_txtmpls(
+                            nullability,
+                            txtmpl_clauses,
+                            guards,
+                            deterministic,
+                            &origin,
+                        )?,
                     )
                 } else {
                     let mut cp =
@@ -273,9 +370,11 @@ where
                     for simp in func.gen_simps(self_ref, simp_ctx)? {
                         cp = cp.add_simp(simp.as_ref())?;
                     }
-                    let v = optimizer_flatten_and_compile(guards)?;
-                    (Some((SArc(effect_path), cp)), v, guard_metadata)
-                })
+                    let v = optimizer_flatten_and_compile(guards, deterministic)?;
+                    (Some((SArc(effect_path), cp)), v)
+                };
+                check_script_size(&v, &origin)?;
+                Ok((api, v, guard_metadata))
             })
             .collect::<Result<Vec<(_, Vec<Miniscript<XOnlyPublicKey, Tap>>, _)>, CompilationError>>(
             )?;
@@ -286,7 +385,7 @@ where
         for (v, b, c) in all_values {
             continue_apis.extend(std::iter::once(v));
             clause_accumulator.push(b);
-            for (pol, mut simps) in c {
+            for (_, pol, mut simps) in c {
                 all_guard_simps.entry(pol).or_default().append(&mut simps)
             }
         }
@@ -294,6 +393,12 @@ where
             guard_simps.sort_by_key(|k| k as *const _ as usize);
             guard_simps.dedup_by(|a, b| std::ptr::eq(a, b))
         }
+        // every key a branch may have been compiled from
+        let mut keys: BTreeSet<XOnlyPublicKey> = all_guard_simps
+            .keys()
+            .flat_map(|c| c.keys())
+            .copied()
+            .collect();
 
         let branches: Vec<Miniscript<XOnlyPublicKey, Tap>> = {
             let mut finish_fns_ctx = ctx.derive(PathFragment::FinishFn)?;
@@ -308,13 +413,28 @@ where
                     Some((new, simp))
                 }))
                 .filter_map(|(func, (c, simp_c))| {
-                    guard_clauses.get(self_ref, *func, c, simp_c).transpose()
+                    let path = c.path().clone();
+                    guard_clauses
+                        .get(self_ref, *func, c, simp_c)
+                        .map(|r| r.map(|g| (path, g)))
+                        .transpose()
                 })
                 .collect::<Result<Vec<_>, _>>()?;
+            keys.extend(guards.iter().flat_map(|(_, (c, _))| c.keys()).copied());
             let all_g = guards
                 .into_iter()
-                .map(|(policy, _m)| optimizer_flatten_and_compile(policy))
-                .collect::<Result<Vec<_>, _>>()?;
+                .map(|(path, (policy, _m))| {
+                    check_guard_context(&policy, ctx.script_context())?;
+                    if simplify_policy(&policy) == Clause::Unsatisfiable {
+                        return Err(CompilationError::UnspendableBranch {
+                            path: path.as_ref().clone(),
+                        });
+                    }
+                    let v = optimizer_flatten_and_compile(policy, deterministic)?;
+                    check_script_size(&v, &path)?;
+                    Ok(v)
+                })
+                .collect::<Result<Vec<_>, CompilationError>>()?;
 
             all_g
                 .into_iter()
@@ -322,31 +442,68 @@ where
                 .flatten()
                 .collect()
         };
-        // TODO: Pick a better branch that is guaranteed to work!
-        let some_key = pick_key_from_miniscripts(branches.iter());
-        // Don't remove the key from the scripts in case it was bogus
-        let tree = branches_to_tree(branches);
-        let descriptor = Descriptor::Tr(descriptor::Tr::new(some_key, tree)?);
-        let estimated_max_size = descriptor.max_satisfaction_weight()?;
-        // TODO: Convert into an address instead of keeping descriptor,
-        // hot-fix workaround
-        let address = descriptor.clone().into();
-        let descriptor = Some(descriptor.into());
+        let descriptor = match ctx.script_context() {
+            ScriptContext::Tap => {
+                // TODO: Pick a better branch that is guaranteed to work!
+                let some_key = pick_key_from_miniscripts(branches.iter());
+                // Don't remove the key from the scripts in case it was bogus
+                let tree = branches_to_tree(branches);
+                SupportedDescriptors::from(Descriptor::Tr(descriptor::Tr::new(some_key, tree)?))
+            }
+            context => non_tap_descriptor(&branches, &keys, context)?.into(),
+        };
+        let (estimated_max_size, address) = match &descriptor {
+            // TODO: Convert into an address instead of keeping descriptor,
+            // hot-fix workaround
+            SupportedDescriptors::XOnly(d) => (d.max_satisfaction_weight()?, d.clone().into()),
+            SupportedDescriptors::Pk(d) => {
+                (d.max_satisfaction_weight()?, d.address(ctx.network)?.into())
+            }
+        };
+        let descriptor = Some(descriptor);
         let root_path = SArc(ctx.path().clone());
 
-        let failed_estimate = comitted_txns.values().any(|a| {
-            // witness space not scaled
-            let tx_size = a.tx.get_weight() + estimated_max_size;
-            let fees = amount_range.max() - a.total_amount();
+        // a shallow compile is only after the address
+        if shallow {
+            return Ok(Compiled {
+                ctv_to_tx: comitted_txns,
+                suggested_txs: other_txns,
+                continue_apis: Default::default(),
+                root_path,
+                address,
+                descriptor,
+                amount_range,
+                metadata: Default::default(),
+                unreachable_branches,
+                template_guard_kinds: guard_kinds,
+                cond_comp_decisions,
+                unused_effect_paths: vec![],
+                path_metadata: Default::default(),
+            });
+        }
+
+        let skip_fee_check = ctx.allows_zero_fee();
+        let failed_estimate = comitted_txns.values().find_map(|a| {
+            // weight includes the witness satisfying this contract
+            let vbytes = (a.tx.weight() + estimated_max_size).div_ceil(4) as u64;
+            let fees = amount_range
+                .max()
+                .checked_sub(a.total_amount())
+                .unwrap_or_else(|| Amount::from_sat(0));
+            // a template's own floor overrides the context's default, which
+            // in turn overrides the context's estimator
             a.min_feerate_sats_vbyte
-                .map(|m| fees.as_sat() < (m.as_sat() * tx_size as u64))
-                == Some(false)
+                .or_else(|| ctx.min_feerate())
+                .or_else(|| ctx.fee_estimator().map(|e| e.feerate()))
+                .map(|m| Amount::from_sat(m.as_sat() * vbytes))
+                .filter(|required| !skip_fee_check && fees < *required)
+                .map(|required| (fees, required))
         });
-        if failed_estimate {
-            Err(CompilationError::MinFeerateError)
+        if let Some((fees, required)) = failed_estimate {
+            Err(CompilationError::MinFeerateError { fees, required })
         } else {
             let metadata_ctx = ctx.derive(PathFragment::Metadata)?;
-            Ok(Compiled {
+            let obj = Compiled {
                 ctv_to_tx: comitted_txns,
                 suggested_txs: other_txns,
                 continue_apis: continue_apis.inner,
@@ -357,17 +514,183 @@ where
                 metadata: self
                     .metadata(metadata_ctx)?
                     .add_guard_simps(all_guard_simps)?,
-            })
+                unreachable_branches,
+                template_guard_kinds: guard_kinds,
+                cond_comp_decisions,
+                unused_effect_paths: ctx.unused_effects(InternalCompilerTag { _secret: () }),
+                path_metadata: Default::default(),
+            };
+            // an effect nobody looked up may have picked a branch that was
+            // never produced, which is worth a precise error
+            for p in &obj.unused_effect_paths {
+                obj.check_branch_selection(&p.0)?;
+            }
+            if let Some(max) = ctx.max_serialized_bytes() {
+                let bytes = serialized_size(&obj)?;
+                if bytes > max {
+                    return Err(CompilationError::OutputTooLarge { bytes, max });
+                }
+            }
+            if let Some(c) = ctx.checkpoints() {
+                c.lock()
+                    .expect("checkpoint lock poisoned")
+                    .record(&ctx, &obj)?;
+            }
+            if let Some(t) = ctx.timings() {
+                t.record(ctx.path().as_ref().clone(), started.elapsed());
+            }
+            Ok(obj)
         }
     }
 }
 
+/// The length of `obj`'s JSON serialization, counted without buffering it
+fn serialized_size(obj: &Compiled) -> Result<usize, CompilationError> {
+    struct Counter(usize);
+    impl std::io::Write for Counter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0 += buf.len();
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+    let mut c = Counter(0);
+    serde_json::to_writer(&mut c, obj).map_err(CompilationError::SerializationError)?;
+    Ok(c.0)
+}
+
+/// Errors (or warns, per [`Context::duplicate_templates`]) if `txtmpl` was
+/// already produced by a function other than the one at `origin`. The same
+/// function producing a template more than once (e.g. under an effect that
+/// matches the default) is not a collision.
+fn check_duplicate_template(
+    origins: &mut BTreeMap<sha256::Hash, (Arc<EffectPath>, EffectPath)>,
+    ctx: &Context,
+    origin: &Arc<EffectPath>,
+    h: sha256::Hash,
+    txtmpl: &Template,
+) -> Result<(), CompilationError> {
+    let path = txtmpl
+        .path
+        .as_ref()
+        .map(|p| p.0.as_ref().clone())
+        .unwrap_or_else(|| origin.as_ref().clone());
+    match origins.get(&h) {
+        Some((first_origin, first_path)) if first_origin != origin => {
+            let paths = vec![first_path.clone(), path.clone()];
+            ctx.warnings().report(
+                ctx.duplicate_templates(),
+                &path,
+                CompilationError::DuplicateTemplate { hash: h, paths },
+            )
+        }
+        Some(_) => Ok(()),
+        None => {
+            origins.insert(h, (origin.clone(), path));
+            Ok(())
+        }
+    }
+}
+
+/// the bits of nSequence BIP 68 gives meaning to: the type flag and value
+const SEQUENCE_LOCKTIME_MASK: u32 = (1 << 22) | 0xffff;
+/// set when nSequence is not a relative lock
+const SEQUENCE_LOCKTIME_DISABLE_FLAG: u32 = 1 << 31;
+
+/// Records a warning (with [`CompilationError::NonStandardTimelock`]) for
+/// each way `txtmpl`'s version, sequences or locktime are valid but
+/// unlikely to behave as intended: not relayed by default nodes, not
+/// enforced, or reserved for future soft forks.
+fn warn_nonstandard_timelocks(ctx: &Context, origin: &Arc<EffectPath>, txtmpl: &Template) {
+    let path = txtmpl
+        .path
+        .as_ref()
+        .map(|p| p.0.as_ref().clone())
+        .unwrap_or_else(|| origin.as_ref().clone());
+    let tx = &txtmpl.tx;
+    let mut detail = vec![];
+    if !(1..=2).contains(&tx.version) {
+        detail.push(format!(
+            "transaction version {} is not relayed by default",
+            tx.version
+        ));
+    }
+    for (i, input) in tx.input.iter().enumerate() {
+        let relative = tx.version >= 2 && input.sequence & SEQUENCE_LOCKTIME_DISABLE_FLAG == 0;
+        if relative && input.sequence & !SEQUENCE_LOCKTIME_MASK != 0 {
+            detail.push(format!(
+                "input {} sequence {:#010x} sets bits BIP 68 reserves",
+                i, input.sequence
+            ));
+        }
+    }
+    if tx.lock_time != 0 && tx.input.iter().all(|i| i.sequence == u32::MAX) {
+        detail.push(format!(
+            "locktime {} is not enforced since every input is final",
+            tx.lock_time
+        ));
+    }
+    for detail in detail {
+        ctx.warnings().push(
+            path.clone(),
+            CompilationError::NonStandardTimelock {
+                path: path.clone(),
+                detail,
+            },
+        );
+    }
+}
+
+/// The consensus limit on block (and so transaction) weight
+pub const MAX_TX_WEIGHT: usize = 4_000_000;
+
+/// Errors if `txtmpl` could never be mined because it is too heavy. The
+/// witness is not known yet, so this is a lower bound on the final weight.
+fn check_weight(origin: &Arc<EffectPath>, txtmpl: &Template) -> Result<(), CompilationError> {
+    let weight = txtmpl.tx.weight();
+    if weight > MAX_TX_WEIGHT {
+        Err(CompilationError::TransactionTooHeavy {
+            weight,
+            limit: MAX_TX_WEIGHT,
+            path: txtmpl
+                .path
+                .as_ref()
+                .map(|p| p.0.as_ref().clone())
+                .unwrap_or_else(|| origin.as_ref().clone()),
+        })
+    } else {
+        Ok(())
+    }
+}
+
+/// The largest script consensus allows (outside of taproot, where policy
+/// still rejects larger ones)
+pub const MAX_SCRIPT_SIZE: usize = 10_000;
+
+/// Errors if any script compiled for the branch at `path` is too large
+fn check_script_size(
+    scripts: &[Miniscript<XOnlyPublicKey, Tap>],
+    path: &Arc<EffectPath>,
+) -> Result<(), CompilationError> {
+    match scripts.iter().map(|ms| ms.script_size()).max() {
+        Some(bytes) if bytes > MAX_SCRIPT_SIZE => Err(CompilationError::ScriptTooLarge {
+            bytes,
+            limit: MAX_SCRIPT_SIZE,
+            path: path.as_ref().clone(),
+        }),
+        _ => Ok(()),
+    }
+}
+
 fn optimizer_flatten_and_compile(
     guards: policy::Concrete<XOnlyPublicKey>,
+    deterministic: bool,
 ) -> Result<Vec<Miniscript<XOnlyPublicKey, Tap>>, CompilationError> {
     let v = optimizer_flatten_policy(guards)
         .into_iter()
-        .map(|g| g.compile())
+        .map(|g| compile_policy(&g, deterministic))
         .collect::<Result<Vec<_>, _>>()?;
     Ok(v)
 }
@@ -376,6 +699,8 @@ fn combine_txtmpls(
     nullability: Nullable,
     txtmpl_clauses: Vec<Clause>,
     guards: Clause,
+    deterministic: bool,
+    origin: &EffectPath,
 ) -> Result<Vec<Miniscript<XOnlyPublicKey, Tap>>, CompilationError> {
     match (nullability, txtmpl_clauses.len(), guards) {
         // This is a nullable branch without any proposed
@@ -386,17 +711,17 @@ fn combine_txtmpls(
         // was unsatisfiable, irrespective of nullability. This is because
         // the behavior should be captured through a compile_if if it is
         // intended.
-        (_, n, Clause::Unsatisfiable) if n > 0 => {
-            // TODO: Turn into a warning that the intended
-            // behavior should be to compile_if
-            Err(CompilationError::MissingTemplates)
+        (_, n, ref guards) if n > 0 && simplify_policy(guards) == Clause::Unsatisfiable => {
+            Err(CompilationError::UnspendableBranch {
+                path: origin.clone(),
+            })
         }
         // Error if 0 templates return and we don't want to be nullable
         (Nullable::No, 0, _) => Err(CompilationError::MissingTemplates),
         // If the guard is trivial, return the hashes standalone
         (_, _, Clause::Trivial) => Ok(txtmpl_clauses
             .into_iter()
-            .map(|policy| policy.compile().map_err(Into::<CompilationError>::into))
+            .map(|policy| compile_policy(&policy, deterministic))
             .collect::<Result<Vec<_>, _>>()?),
         // If the guard is non-trivial, zip it to each hash
         // TODO: Arc in miniscript to dedup memory?
@@ -405,9 +730,10 @@ fn combine_txtmpls(
             .into_iter()
             // extra_guards will contain any CTV
             .map(|extra_guards| {
-                Clause::And(vec![guards.clone(), extra_guards])
-                    .compile()
-                    .map_err(Into::<CompilationError>::into)
+                compile_policy(
+                    &Clause::And(vec![guards.clone(), extra_guards]),
+                    deterministic,
+                )
             })
             .collect::<Result<Vec<_>, _>>()?),
     }
@@ -425,3 +751,980 @@ fn optimizer_flatten_policy(p: Clause) -> Vec<Clause> {
         p => vec![p],
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate as sapio;
+    use crate::contract::actions::ConditionalCompileType;
+    use crate::contract::test_helpers::{ctx, key};
+    use crate::contract::warnings::LintLevel;
+    use crate::contract::{Compilable, CompilationError, Contract};
+    use crate::*;
+    use bitcoin::util::amount::Amount;
+    use bitcoin::XOnlyPublicKey;
+    use sapio_base::effects::{EffectPath, PathFragment};
+    use sapio_base::timelocks::{AbsTime, RelHeight, RelTime};
+    use sapio_base::Clause;
+    use std::convert::TryFrom;
+    use std::sync::Arc;
+
+    struct CopyPaste {
+        to: XOnlyPublicKey,
+    }
+    impl CopyPaste {
+        #[then]
+        fn first(self, ctx: sapio::Context) {
+            ctx.template()
+                .add_output(Amount::from_sat(1000), &self.to, None)?
+                .into()
+        }
+        #[then]
+        fn second(self, ctx: sapio::Context) {
+            ctx.template()
+                .add_output(Amount::from_sat(1000), &self.to, None)?
+                .into()
+        }
+    }
+    impl Contract for CopyPaste {
+        declare! {then, Self::first, Self::second}
+        declare! {non updatable}
+    }
+
+    struct Conflicted {
+        to: XOnlyPublicKey,
+    }
+    impl Conflicted {
+        #[guard]
+        fn wait_blocks(self, _ctx: sapio::Context) {
+            RelHeight::from(10).into()
+        }
+        #[guard]
+        fn wait_time(self, _ctx: sapio::Context) {
+            RelTime::from(5).into()
+        }
+        #[then(guarded_by = "[Self::wait_blocks, Self::wait_time]")]
+        fn pay(self, ctx: sapio::Context) {
+            ctx.template()
+                .add_output(Amount::from_sat(1000), &self.to, None)?
+                .into()
+        }
+    }
+    impl Contract for Conflicted {
+        declare! {then, Self::pay}
+        declare! {non updatable}
+    }
+
+    struct Deadline {
+        to: XOnlyPublicKey,
+    }
+    impl Deadline {
+        #[then]
+        fn expire(self, ctx: sapio::Context) {
+            let lock = AbsTime::try_from(ctx.now() + 1000)?;
+            ctx.template()
+                .set_lock_time(lock.into())?
+                .add_output(Amount::from_sat(1000), &self.to, None)?
+                .into()
+        }
+    }
+    impl Contract for Deadline {
+        declare! {then, Self::expire}
+        declare! {non updatable}
+    }
+
+    #[test]
+    fn test_fixed_now() {
+        let lock_time = |now| {
+            let c = (Deadline { to: key(1) })
+                .compile(ctx(10_000).with_now(now))
+                .unwrap();
+            let t = c.ctv_to_tx.values().next().unwrap();
+            t.tx.lock_time
+        };
+        assert_eq!(lock_time(1_700_000_000), 1_700_001_000);
+        assert_eq!(lock_time(1_700_000_000), lock_time(1_700_000_000));
+        assert_eq!(lock_time(1_800_000_000), 1_800_001_000);
+    }
+
+    struct Preview {
+        to: XOnlyPublicKey,
+    }
+    fn coerce_extra(k: Option<u64>) -> Result<Option<u64>, CompilationError> {
+        Ok(k)
+    }
+    impl Preview {
+        #[guard]
+        fn signed(self, _ctx: sapio::Context) {
+            Clause::Key(self.to)
+        }
+        #[then]
+        fn fund(self, ctx: sapio::Context) {
+            ctx.template()
+                .add_output(
+                    Amount::from_sat(1000),
+                    &NeedsKey { to: Some(self.to) },
+                    None,
+                )?
+                .into()
+        }
+        #[continuation(guarded_by = "[Self::signed]", web_api, coerce_args = "coerce_extra")]
+        fn extra(self, ctx: sapio::Context, extra: Option<u64>) {
+            match extra {
+                Some(extra) => ctx
+                    .template()
+                    .add_output(Amount::from_sat(extra), &self.to, None)?
+                    .into(),
+                None => crate::contract::empty(),
+            }
+        }
+    }
+    impl Contract for Preview {
+        declare! {then, Self::fund}
+        declare! {updatable<Option<u64>>, Self::extra}
+    }
+
+    #[test]
+    fn test_shallow_compile() {
+        let full = (Preview { to: key(2) }).compile(ctx(10_000)).unwrap();
+        let shallow = ctx(10_000).shallow_compile(Preview { to: key(2) }).unwrap();
+        assert_eq!(bitcoin::Script::from(shallow), full.address.into());
+    }
+
+    struct NeedsKey {
+        to: Option<XOnlyPublicKey>,
+    }
+    impl NeedsKey {
+        #[guard]
+        fn signed(self, ctx: sapio::Context) -> Result<Clause, CompilationError> {
+            self.to
+                .map(Clause::Key)
+                .ok_or_else(|| ctx.reject_arg("to", "a key is required"))
+        }
+        #[guard]
+        fn cosigned(self, _ctx: sapio::Context) -> Clause {
+            Clause::Key(key(3))
+        }
+        #[then(guarded_by = "[Self::signed, Self::cosigned]")]
+        fn pay(self, ctx: sapio::Context) {
+            ctx.template()
+                .add_output(Amount::from_sat(1000), &key(1), None)?
+                .into()
+        }
+    }
+    impl Contract for NeedsKey {
+        declare! {then, Self::pay}
+        declare! {non updatable}
+    }
+
+    #[test]
+    fn test_guard_error() {
+        assert!((NeedsKey { to: Some(key(2)) }).compile(ctx(10_000)).is_ok());
+        match (NeedsKey { to: None }).compile(ctx(10_000)) {
+            Err(CompilationError::GuardFailed { path, error }) => {
+                assert_eq!(String::from(path), "@root/@action/pay/@guard/#0");
+                assert!(matches!(
+                    *error,
+                    CompilationError::InvalidArgument { ref field, .. } if field == "to"
+                ));
+            }
+            r => panic!("expected GuardFailed, got {:?}", r.err()),
+        }
+    }
+
+    /// pays more than it is given
+    struct Greedy;
+    impl Greedy {
+        #[then]
+        fn grab(self, ctx: sapio::Context) {
+            ctx.template()
+                .add_output(Amount::from_sat(5000), &key(1), None)?
+                .into()
+        }
+    }
+    impl Contract for Greedy {
+        declare! {then, Self::grab}
+        declare! {non updatable}
+    }
+    struct Patron;
+    impl Patron {
+        #[then]
+        fn fund(self, ctx: sapio::Context) {
+            ctx.template()
+                .add_output(Amount::from_sat(1000), &Greedy, None)?
+                .into()
+        }
+    }
+    impl Contract for Patron {
+        declare! {then, Self::fund}
+        declare! {non updatable}
+    }
+
+    #[test]
+    fn test_subcontract_error_at() {
+        // a subcontract's error arrives as is, for callers matching on it
+        let e = Patron.compile(ctx(10_000)).unwrap_err();
+        assert!(matches!(e, CompilationError::OutOfFunds { .. }));
+        // and may be located by the caller
+        let at = "@root/@action/fund/@next/@default_effect/#0";
+        let e = e.at(&EffectPath::try_from(at).unwrap());
+        assert!(e.log_fields().contains(&("path", at.to_string())));
+        let (path, inner) = e.flatten_ref();
+        assert_eq!(String::from(path), at);
+        assert!(matches!(inner, CompilationError::OutOfFunds { .. }));
+        #[cfg(feature = "anyhow")]
+        assert_eq!(e.context_chain().to_string(), format!("at {}", at));
+    }
+
+    struct Ouroboros {
+        me: std::sync::Weak<Ouroboros>,
+    }
+    impl Ouroboros {
+        #[then]
+        fn again(self, ctx: sapio::Context) {
+            let me = self.me.upgrade().unwrap();
+            ctx.template()
+                .add_output(Amount::from_sat(1000), &*me, None)?
+                .into()
+        }
+    }
+    impl Contract for Ouroboros {
+        declare! {then, Self::again}
+        declare! {non updatable}
+    }
+
+    #[test]
+    fn test_cyclic_dependency() {
+        let c = std::sync::Arc::new_cyclic(|me| Ouroboros { me: me.clone() });
+        match c.compile(ctx(10_000)) {
+            Err(CompilationError::CyclicDependency { cycle }) => {
+                let cycle: Vec<String> = cycle.into_iter().map(String::from).collect();
+                // first reached with 10000 sats, then twice with 1000
+                assert_eq!(
+                    cycle,
+                    vec![
+                        "@root/@action/again/@next/@default_effect/#0",
+                        "@root/@action/again/@next/@default_effect/#0\
+                         /@action/again/@next/@default_effect/#0",
+                    ]
+                );
+            }
+            r => panic!("expected CyclicDependency, got {:?}", r.err()),
+        }
+    }
+
+    /// a zero sized contract paying half its funds to another instance of
+    /// itself, until there is too little left to split
+    struct Halve;
+    impl Halve {
+        #[then]
+        fn split(self, ctx: sapio::Context) {
+            let half = Amount::from_sat(ctx.funds().as_sat() / 2);
+            if half < Amount::from_sat(1000) {
+                ctx.template().add_output(half, &key(1), None)?.into()
+            } else {
+                ctx.template().add_output(half, &Halve, None)?.into()
+            }
+        }
+    }
+    impl Contract for Halve {
+        declare! {then, Self::split}
+        declare! {non updatable}
+    }
+
+    /// pays half its funds to itself, until there is too little left
+    struct Drip {
+        to: XOnlyPublicKey,
+    }
+    impl Drip {
+        #[then]
+        fn pay(self, ctx: sapio::Context) {
+            let half = Amount::from_sat(ctx.funds().as_sat() / 2);
+            if half < Amount::from_sat(1000) {
+                ctx.template().add_output(half, &self.to, None)?.into()
+            } else {
+                ctx.template().add_output(half, self, None)?.into()
+            }
+        }
+    }
+    impl Contract for Drip {
+        declare! {then, Self::pay}
+        declare! {non updatable}
+    }
+
+    #[test]
+    fn test_recursion_with_fewer_funds() {
+        assert!(Halve.compile(ctx(10_000)).is_ok());
+        assert!((Drip { to: key(1) }).compile(ctx(10_000)).is_ok());
+    }
+
+    #[test]
+    fn test_output_too_large() {
+        let compile =
+            |c: sapio::Context| (Deadline { to: key(1) }).compile(c.with_now(1_700_000_000));
+        let size = serde_json::to_vec(&compile(ctx(10_000)).unwrap())
+            .unwrap()
+            .len();
+        assert!(compile(ctx(10_000).with_max_serialized_bytes(size)).is_ok());
+        match compile(ctx(10_000).with_max_serialized_bytes(100)) {
+            Err(CompilationError::OutputTooLarge { bytes, max }) => {
+                assert_eq!((bytes, max), (size, 100));
+            }
+            r => panic!("expected OutputTooLarge, got {:?}", r.err()),
+        }
+    }
+
+    struct TwoThens {
+        to: XOnlyPublicKey,
+    }
+    impl TwoThens {
+        #[then]
+        fn zeta(self, ctx: sapio::Context) {
+            ctx.log("zeta");
+            ctx.template()
+                .add_output(Amount::from_sat(1000), &self.to, None)?
+                .into()
+        }
+        #[then]
+        fn alpha(self, ctx: sapio::Context) {
+            ctx.log("alpha");
+            ctx.template()
+                .add_output(Amount::from_sat(2000), &self.to, None)?
+                .into()
+        }
+    }
+    impl Contract for TwoThens {
+        declare! {then, Self::zeta, Self::alpha}
+        declare! {non updatable}
+    }
+
+    #[test]
+    fn test_then_order() {
+        use crate::contract::context::ThenOrder;
+        use std::sync::Mutex;
+        let order = |o: ThenOrder| {
+            let lines = Arc::new(Mutex::new(vec![]));
+            let sink = lines.clone();
+            let c = ctx(10_000)
+                .with_then_order(o)
+                .with_logger(Arc::new(move |l: &str| {
+                    sink.lock()
+                        .unwrap()
+                        .push(l.rsplit(' ').next().unwrap().to_string())
+                }));
+            (TwoThens { to: key(1) }).compile(c).unwrap();
+            let v = lines.lock().unwrap().clone();
+            v
+        };
+        assert_eq!(order(ThenOrder::Declaration), vec!["zeta", "alpha"]);
+        assert_eq!(order(ThenOrder::ByName), vec!["alpha", "zeta"]);
+    }
+
+    struct Underfunded {
+        expanded: Arc<std::sync::atomic::AtomicBool>,
+    }
+    impl Underfunded {
+        #[then]
+        fn pay(self, ctx: sapio::Context) {
+            self.expanded
+                .store(true, std::sync::atomic::Ordering::SeqCst);
+            ctx.template()
+                .add_output(Amount::from_sat(5000), &key(1), None)?
+                .into()
+        }
+    }
+    impl Contract for Underfunded {
+        declare! {then, Self::pay}
+        declare! {non updatable}
+        fn ensure_amount(&self, _ctx: sapio::Context) -> Result<Amount, CompilationError> {
+            Ok(Amount::from_sat(5000))
+        }
+    }
+
+    #[test]
+    fn test_early_out_of_funds() {
+        let contract = Underfunded {
+            expanded: Default::default(),
+        };
+        match contract.compile(ctx(1000)) {
+            Err(CompilationError::OutOfFunds {
+                available,
+                required,
+            }) => {
+                assert_eq!(available, Amount::from_sat(1000));
+                assert_eq!(required, Amount::from_sat(5000));
+            }
+            r => panic!("expected OutOfFunds, got {:?}", r.err()),
+        }
+        assert!(!contract.expanded.load(std::sync::atomic::Ordering::SeqCst));
+        assert!(contract.compile(ctx(10_000)).is_ok());
+        assert!(contract.expanded.load(std::sync::atomic::Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_emulator_required() {
+        let no_emulator = Context::new(
+            bitcoin::Network::Regtest,
+            Amount::from_sat(10_000),
+            Arc::new(sapio_ctv_emulator_trait::CTVUnavailable),
+            EffectPath::from(PathFragment::Root),
+            Default::default(),
+        );
+        assert!(matches!(
+            (Deadline { to: key(1) }).compile(no_emulator.with_now(1_700_000_000)),
+            Err(CompilationError::EmulatorRequired)
+        ));
+    }
+
+    #[test]
+    fn test_guard_conflict() {
+        match (Conflicted { to: key(1) }).compile(ctx(10_000)) {
+            Err(CompilationError::GuardConflict { path, detail }) => {
+                assert_eq!(String::from(path), "@root/@action/pay/@guard");
+                assert!(detail.contains("older(10)"), "{}", detail);
+            }
+            r => panic!("expected GuardConflict, got {:?}", r.map(|_| ())),
+        }
+    }
+
+    /// guarded by `and(pk, and(after(100), false))`, which no one can
+    /// satisfy, through either a `then` or a continuation
+    struct Dead {
+        continuation: bool,
+    }
+    impl Dead {
+        #[compile_if]
+        fn is_then(self, _ctx: sapio::Context) {
+            if self.continuation {
+                ConditionalCompileType::Never
+            } else {
+                ConditionalCompileType::Required
+            }
+        }
+        #[compile_if]
+        fn is_continuation(self, _ctx: sapio::Context) {
+            if self.continuation {
+                ConditionalCompileType::Required
+            } else {
+                ConditionalCompileType::Never
+            }
+        }
+        #[guard]
+        fn signed(self, _ctx: sapio::Context) {
+            Clause::Key(key(1))
+        }
+        #[guard]
+        fn never(self, _ctx: sapio::Context) {
+            Clause::And(vec![Clause::After(100), Clause::Unsatisfiable])
+        }
+        #[then(
+            compile_if = "[Self::is_then]",
+            guarded_by = "[Self::signed, Self::never]"
+        )]
+        fn pay(self, ctx: sapio::Context) {
+            ctx.template()
+                .add_output(Amount::from_sat(1000), &key(1), None)?
+                .into()
+        }
+        #[continuation(
+            compile_if = "[Self::is_continuation]",
+            guarded_by = "[Self::signed, Self::never]",
+            web_api,
+            coerce_args = "coerce_bump"
+        )]
+        fn bump(self, _ctx: sapio::Context, _x: Option<u64>) {
+            crate::contract::empty()
+        }
+    }
+    fn coerce_bump(k: Option<u64>) -> Result<Option<u64>, CompilationError> {
+        Ok(k)
+    }
+    impl Contract for Dead {
+        declare! {then, Self::pay}
+        declare! {updatable<Option<u64>>, Self::bump}
+    }
+
+    #[test]
+    fn test_unspendable_branch() {
+        for (continuation, at) in [(false, "@root/@action/pay"), (true, "@root/@action/bump")] {
+            match (Dead { continuation }).compile(ctx(10_000)) {
+                Err(CompilationError::UnspendableBranch { path }) => {
+                    assert_eq!(String::from(path), at)
+                }
+                r => panic!("expected UnspendableBranch, got {:?}", r.map(|_| ())),
+            }
+        }
+    }
+
+    #[test]
+    fn test_duplicate_template() {
+        let contract = CopyPaste { to: key(1) };
+        match contract.compile(ctx(10_000)) {
+            Err(CompilationError::DuplicateTemplate { paths, .. }) => {
+                let paths: Vec<String> = paths.into_iter().map(String::from).collect();
+                assert_eq!(
+                    paths,
+                    vec![
+                        "@root/@action/first/@next/@default_effect",
+                        "@root/@action/second/@next/@default_effect"
+                    ]
+                );
+            }
+            r => panic!("expected DuplicateTemplate, got {:?}", r.map(|_| ())),
+        }
+
+        let c = ctx(10_000).with_duplicate_templates(LintLevel::Warn);
+        let warnings = c.warnings();
+        assert!(contract.compile(c).is_ok());
+        let w = warnings.take();
+        assert_eq!(w.len(), 1);
+        assert!(matches!(
+            w[0].error,
+            CompilationError::DuplicateTemplate { .. }
+        ));
+    }
+
+    /// a guard needing every one of hundreds of keys and preimages
+    struct Everyone;
+    impl Everyone {
+        #[guard]
+        fn all_of_us(self, _ctx: sapio::Context) {
+            use bitcoin::hashes::{sha256, Hash};
+            let keys = (1..=250).map(|b| Clause::Key(key(b)));
+            let hashes = (0..80u8).map(|b| Clause::Sha256(sha256::Hash::hash(&[b])));
+            // pair up clauses level by level to keep the tree shallow
+            let mut level: Vec<Clause> = keys.chain(hashes).collect();
+            while level.len() > 1 {
+                level = level
+                    .chunks(2)
+                    .map(|c| match c {
+                        [a, b] => Clause::And(vec![a.clone(), b.clone()]),
+                        [a] => a.clone(),
+                        _ => unreachable!(),
+                    })
+                    .collect();
+            }
+            level.remove(0)
+        }
+        #[then(guarded_by = "[Self::all_of_us]")]
+        fn pay(self, ctx: sapio::Context) {
+            ctx.template()
+                .add_output(Amount::from_sat(1000), &key(1), None)?
+                .into()
+        }
+    }
+    impl Contract for Everyone {
+        declare! {then, Self::pay}
+        declare! {non updatable}
+    }
+
+    /// pays `to` via CTV, additionally requiring a signature from `signer`
+    /// if set
+    struct Locked {
+        signer: Option<XOnlyPublicKey>,
+    }
+    impl Locked {
+        #[guard]
+        fn signed(self, _ctx: sapio::Context) {
+            self.signer.map_or(Clause::Trivial, Clause::Key)
+        }
+        #[then(guarded_by = "[Self::signed]")]
+        fn pay(self, ctx: sapio::Context) {
+            ctx.template()
+                .add_output(Amount::from_sat(1000), &key(9), None)?
+                .into()
+        }
+    }
+    impl Contract for Locked {
+        declare! {then, Self::pay}
+        declare! {non updatable}
+    }
+
+    #[test]
+    fn test_script_context() {
+        use super::super::context::ScriptContext;
+        let spk = |c: ScriptContext| {
+            let obj = Locked { signer: None }
+                .compile(ctx(10_000).with_script_context(c))
+                .unwrap();
+            bitcoin::Script::from(obj.address)
+        };
+        assert!(spk(ScriptContext::Tap).is_v1_p2tr());
+        assert!(spk(ScriptContext::Segwitv0).is_v0_p2wsh());
+        assert!(spk(ScriptContext::Legacy).is_p2sh());
+        // the default is Taproot
+        let obj = Locked { signer: None }.compile(ctx(10_000)).unwrap();
+        assert_eq!(bitcoin::Script::from(obj.address), spk(ScriptContext::Tap));
+        // outside of Taproot, x-only keys are signed for with their even key
+        let signed = Locked {
+            signer: Some(key(1)),
+        };
+        assert!(signed.compile(ctx(10_000)).is_ok());
+        let obj = signed
+            .compile(ctx(10_000).with_script_context(ScriptContext::Segwitv0))
+            .unwrap();
+        assert!(bitcoin::Script::from(obj.address).is_v0_p2wsh());
+        let descriptor = obj.descriptor.unwrap().to_string();
+        assert!(descriptor.contains(&super::even_key(&key(1)).to_string()));
+    }
+
+    /// may be spent by any one of twenty keys
+    struct Committee;
+    impl Committee {
+        #[guard]
+        fn any_of_us(self, _ctx: sapio::Context) {
+            Clause::Threshold(1, (1..=20).map(|k| Clause::Key(key(k))).collect())
+        }
+        #[then(guarded_by = "[Self::any_of_us]")]
+        fn pay(self, ctx: sapio::Context) {
+            ctx.template()
+                .add_output(Amount::from_sat(1000), &key(21), None)?
+                .into()
+        }
+    }
+    impl Contract for Committee {
+        declare! {then, Self::pay}
+        declare! {non updatable}
+    }
+
+    #[test]
+    fn test_unsupported_guard() {
+        use super::super::context::ScriptContext;
+        assert!(Committee.compile(ctx(10_000)).is_ok());
+        // too large for a 520 byte P2SH script
+        match Committee.compile(ctx(10_000).with_script_context(ScriptContext::Legacy)) {
+            Err(CompilationError::Unsupported { context, .. }) => {
+                assert_eq!(context, ScriptContext::Legacy)
+            }
+            r => panic!("expected Unsupported, got {:?}", r.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn test_script_too_large() {
+        match Everyone.compile(ctx(10_000)) {
+            Err(CompilationError::ScriptTooLarge { bytes, limit, path }) => {
+                assert_eq!(limit, super::MAX_SCRIPT_SIZE);
+                assert!(bytes > limit);
+                assert_eq!(String::from(path), "@root/@action/pay");
+            }
+            r => panic!("expected ScriptTooLarge, got {:?}", r.map(|_| ())),
+        }
+    }
+
+    /// spends its entire value, leaving nothing for fees
+    struct NoFee;
+    impl NoFee {
+        #[then]
+        fn spend_all(self, ctx: sapio::Context) {
+            ctx.template()
+                .add_output(Amount::from_sat(10_000), &key(1), None)?
+                .set_min_feerate(Amount::from_sat(1))
+                .into()
+        }
+    }
+    impl Contract for NoFee {
+        declare! {then, Self::spend_all}
+        declare! {non updatable}
+    }
+
+    #[test]
+    fn test_allow_zero_fee() {
+        assert!(matches!(
+            NoFee.compile(ctx(10_000)),
+            Err(CompilationError::MinFeerateError { fees, .. }) if fees == Amount::from_sat(0)
+        ));
+        assert!(NoFee.compile(ctx(10_000).with_allow_zero_fee(true)).is_ok());
+        assert!(matches!(
+            NoFee.compile(ctx(10_000).with_allow_zero_fee(false)),
+            Err(CompilationError::MinFeerateError { .. })
+        ));
+    }
+
+    /// pays `fees` sats in fees with a 10 sat/vbyte floor
+    struct Floor {
+        fees: u64,
+    }
+    impl Floor {
+        #[then]
+        fn close(self, ctx: sapio::Context) {
+            ctx.template()
+                .add_output(Amount::from_sat(10_000 - self.fees), &key(1), None)?
+                .add_fees(Amount::from_sat(self.fees))?
+                .set_min_feerate(Amount::from_sat(10))
+                .into()
+        }
+    }
+    impl Contract for Floor {
+        declare! {then, Self::close}
+        declare! {non updatable}
+    }
+
+    #[test]
+    fn test_min_feerate_floor() {
+        // the floor is priced per vbyte, witness included, not per weight unit
+        let required = match (Floor { fees: 0 }).compile(ctx(10_000)) {
+            Err(CompilationError::MinFeerateError { fees, required }) => {
+                assert_eq!(fees, Amount::from_sat(0));
+                required
+            }
+            r => panic!("expected MinFeerateError, got {:?}", r.map(|_| ())),
+        };
+        // 113 vbytes, where 452 weight units would have required 4520 sats
+        assert_eq!(required, Amount::from_sat(10 * 113));
+        // paying exactly the floor is enough, one sat less is not
+        assert!(Floor {
+            fees: required.as_sat()
+        }
+        .compile(ctx(10_000))
+        .is_ok());
+        assert!(matches!(
+            Floor {
+                fees: required.as_sat() - 1
+            }
+            .compile(ctx(10_000)),
+            Err(CompilationError::MinFeerateError { .. })
+        ));
+    }
+
+    /// pays 2000 sats in fees from either branch, but only `emergency` sets
+    /// its own floor
+    struct Close {
+        normal: bool,
+    }
+    impl Close {
+        #[compile_if]
+        fn has_normal(self, _ctx: sapio::Context) {
+            if self.normal {
+                ConditionalCompileType::Required
+            } else {
+                ConditionalCompileType::Never
+            }
+        }
+        #[then]
+        fn emergency(self, ctx: sapio::Context) {
+            ctx.template()
+                .add_output(Amount::from_sat(8000), &key(1), None)?
+                .add_fees(Amount::from_sat(2000))?
+                .set_min_feerate(Amount::from_sat(1))
+                .into()
+        }
+        #[then(compile_if = "[Self::has_normal]")]
+        fn normal(self, ctx: sapio::Context) {
+            ctx.template()
+                .add_output(Amount::from_sat(8000), &key(2), None)?
+                .add_fees(Amount::from_sat(2000))?
+                .into()
+        }
+    }
+    impl Contract for Close {
+        declare! {then, Self::emergency, Self::normal}
+        declare! {non updatable}
+    }
+
+    #[test]
+    fn test_branch_min_feerate() {
+        assert!(Close { normal: true }.compile(ctx(10_000)).is_ok());
+        assert!(Close { normal: true }
+            .compile(ctx(10_000).with_min_feerate(Amount::from_sat(5)))
+            .is_ok());
+        // `emergency` keeps its own 1 sat/vbyte floor, but `normal` uses
+        // the default and can not pay 50 sats/vbyte for its 121 vbytes
+        let close = Close { normal: true };
+        match close.compile(ctx(10_000).with_min_feerate(Amount::from_sat(50))) {
+            Err(CompilationError::MinFeerateError { fees, required }) => {
+                assert_eq!(fees, Amount::from_sat(2000));
+                assert_eq!(required, Amount::from_sat(50 * 121));
+            }
+            r => panic!("expected MinFeerateError, got {:?}", r.map(|_| ())),
+        }
+        assert!(Close { normal: false }
+            .compile(ctx(10_000).with_min_feerate(Amount::from_sat(50)))
+            .is_ok());
+    }
+
+    #[test]
+    fn test_estimator_min_feerate() {
+        use crate::template::fees::StaticFeeRate;
+        let estimator = |rate| std::sync::Arc::new(StaticFeeRate(Amount::from_sat(rate)));
+        assert!(Close { normal: true }
+            .compile(ctx(10_000).with_fee_estimator(estimator(5)))
+            .is_ok());
+        // without a default floor, `normal` must pay what the estimator asks
+        let close = Close { normal: true };
+        match close.compile(ctx(10_000).with_fee_estimator(estimator(50))) {
+            Err(CompilationError::MinFeerateError { fees, required }) => {
+                assert_eq!(fees, Amount::from_sat(2000));
+                assert_eq!(required, Amount::from_sat(50 * 121));
+            }
+            r => panic!("expected MinFeerateError, got {:?}", r.map(|_| ())),
+        }
+        // an explicit default floor takes precedence over the estimator
+        assert!(Close { normal: true }
+            .compile(
+                ctx(10_000)
+                    .with_fee_estimator(estimator(50))
+                    .with_min_feerate(Amount::from_sat(5))
+            )
+            .is_ok());
+    }
+
+    /// sets a locktime but then makes every input final, disabling it
+    struct IgnoredLock;
+    impl IgnoredLock {
+        #[then]
+        fn pay(self, ctx: sapio::Context) {
+            let mut t: crate::template::Template = ctx
+                .template()
+                .add_output(Amount::from_sat(1000), &key(1), None)?
+                .set_lock_time(AbsTime::try_from(1_700_000_000)?.into())?
+                .into();
+            t.tx.input[0].sequence = u32::MAX;
+            t.ctv = sapio_base::CTVHash::get_ctv_hash(&t.tx, 0);
+            Ok(Box::new(std::iter::once(Ok(t))))
+        }
+    }
+    impl Contract for IgnoredLock {
+        declare! {then, Self::pay}
+        declare! {non updatable}
+    }
+
+    #[test]
+    fn test_nonstandard_timelock_warning() {
+        let c = ctx(10_000);
+        let warnings = c.warnings();
+        assert!(IgnoredLock.compile(c).is_ok());
+        let w = warnings.take();
+        assert_eq!(w.len(), 1);
+        assert_eq!(
+            String::from(w[0].path.clone()),
+            "@root/@action/pay/@next/@default_effect"
+        );
+        match &w[0].error {
+            CompilationError::NonStandardTimelock { detail, .. } => {
+                assert!(detail.contains("locktime 1700000000 is not enforced"))
+            }
+            e => panic!("expected NonStandardTimelock, got {:?}", e),
+        }
+        // the compiler's own templates are standard
+        let c = ctx(10_000);
+        let warnings = c.warnings();
+        assert!((Deadline { to: key(1) })
+            .compile(c.with_now(1_700_000_000))
+            .is_ok());
+        assert!(warnings.is_empty());
+    }
+
+    /// pays to a script so large that the template can never be mined
+    struct Heavy;
+    impl Heavy {
+        #[then]
+        fn pay(self, ctx: sapio::Context) {
+            let mut blob = crate::contract::object::Object::from_op_return(&[0u8; 1][..])?;
+            blob.address = crate::util::extended_address::ExtendedAddress::Unknown(
+                bitcoin::Script::from(vec![0x6a; 1_000_000]),
+            );
+            ctx.template()
+                .add_output(Amount::from_sat(1000), &blob, None)?
+                .into()
+        }
+    }
+    impl Contract for Heavy {
+        declare! {then, Self::pay}
+        declare! {non updatable}
+    }
+
+    #[test]
+    fn test_transaction_too_heavy() {
+        match Heavy.compile(ctx(10_000)) {
+            Err(CompilationError::TransactionTooHeavy {
+                weight,
+                limit,
+                path,
+            }) => {
+                assert_eq!(limit, super::MAX_TX_WEIGHT);
+                assert!(weight > limit);
+                assert_eq!(
+                    String::from(path),
+                    "@root/@action/pay/@next/@default_effect"
+                );
+            }
+            r => panic!("expected TransactionTooHeavy, got {:?}", r.map(|_| ())),
+        }
+    }
+
+    /// a SIMP recording the path it was generated at
+    struct AtPath(String);
+    impl sapio_base::simp::SIMP for AtPath {
+        fn static_get_protocol_number() -> i64 {
+            -1
+        }
+        fn get_protocol_number(&self) -> i64 {
+            Self::static_get_protocol_number()
+        }
+        fn to_json(&self) -> Result<serde_json::Value, serde_json::Error> {
+            Ok(self.0.clone().into())
+        }
+        fn from_json(value: serde_json::Value) -> Result<Self, serde_json::Error> {
+            serde_json::from_value(value).map(AtPath)
+        }
+    }
+    impl sapio_base::simp::SIMPAttachableAt<sapio_base::simp::GuardLT> for AtPath {}
+
+    struct Witnessed;
+    impl Witnessed {
+        fn simp_path(
+            &self,
+            ctx: sapio::Context,
+        ) -> Result<
+            Vec<std::sync::Arc<dyn sapio_base::simp::SIMPAttachableAt<sapio_base::simp::GuardLT>>>,
+            CompilationError,
+        > {
+            let path = String::from(ctx.path().as_ref().clone());
+            Ok(vec![std::sync::Arc::new(AtPath(path))])
+        }
+        #[guard(simps = "Some(Self::simp_path)")]
+        fn signed_1(self, _ctx: sapio::Context) {
+            Clause::Key(key(1))
+        }
+        #[guard(simps = "Some(Self::simp_path)")]
+        fn signed_2(self, _ctx: sapio::Context) {
+            Clause::Key(key(2))
+        }
+        #[guard(simps = "Some(Self::simp_path)")]
+        fn signed_3(self, _ctx: sapio::Context) {
+            Clause::Key(key(3))
+        }
+        #[then(guarded_by = "[Self::signed_1, Self::signed_2]")]
+        fn both(self, ctx: sapio::Context) {
+            ctx.template()
+                .add_output(Amount::from_sat(1000), &key(4), None)?
+                .into()
+        }
+        #[then(guarded_by = "[Self::signed_3]")]
+        fn alone(self, ctx: sapio::Context) {
+            ctx.template()
+                .add_output(Amount::from_sat(1000), &key(5), None)?
+                .into()
+        }
+    }
+    impl Contract for Witnessed {
+        declare! {then, Self::both, Self::alone}
+        declare! {non updatable}
+    }
+
+    #[test]
+    fn test_guard_simps() {
+        let obj = Witnessed.compile(ctx(10_000)).unwrap();
+        let simp_at = |k| {
+            obj.metadata.simps_for_guards[&Clause::Key(key(k))][&-1]
+                .iter()
+                .map(|v| v.as_str().unwrap().to_owned())
+                .collect::<Vec<_>>()
+        };
+        // several guards each get their own metadata path, a lone guard
+        // keeps the shared one
+        assert_eq!(simp_at(1), ["@root/@action/both/@guard/#0/@metadata"]);
+        assert_eq!(simp_at(2), ["@root/@action/both/@guard/#1/@metadata"]);
+        assert_eq!(simp_at(3), ["@root/@action/alone/@guard/@metadata"]);
+    }
+}
@@ -0,0 +1,172 @@
+// Copyright Judica, Inc 2022
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+//  License, v. 2.0. If a copy of the MPL was not distributed with this
+//  file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! checkpoints for resuming a compilation part way through
+use crate::contract::{CompilationError, Compiled, Context};
+use bitcoin::hashes::{sha256, Hash};
+use sapio_base::effects::EffectPath;
+use sapio_base::serialization_helpers::SArc;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+
+/// A `PartialCompilation` records every contract compiled under a
+/// [`crate::contract::Context`] it is attached to, keyed by the path it was
+/// compiled at.
+///
+/// When a later compilation reaches a recorded path with the same context
+/// parameters (funds, effects, network, ...), the recorded `Object` is used
+/// rather than recompiling that subtree. This allows a large contract to be
+/// compiled in pieces, saving the pieces in between. The contract's own
+/// arguments are not checked, so they must not change between the pieces.
+/// A saved piece keeps its introspection (e.g. template paths) only with the
+/// `serde-introspection` feature.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct PartialCompilation {
+    objects: BTreeMap<SArc<EffectPath>, Checkpoint>,
+    #[serde(skip, default)]
+    resumed: BTreeSet<SArc<EffectPath>>,
+}
+
+/// an `Object` along with the hash of the context parameters it was
+/// compiled with
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct Checkpoint {
+    params: sha256::Hash,
+    object: Compiled,
+}
+
+fn params_hash(ctx: &Context) -> Result<sha256::Hash, CompilationError> {
+    let mut engine = sha256::Hash::engine();
+    ctx.hash_params(&mut engine)?;
+    Ok(sha256::Hash::from_engine(engine))
+}
+
+impl PartialCompilation {
+    /// the `Object` compiled at `ctx`'s path, if recorded with the same
+    /// parameters. Marks it as resumed.
+    pub(crate) fn resume(&mut self, ctx: &Context) -> Result<Option<Compiled>, CompilationError> {
+        let path = SArc(ctx.path().clone());
+        let params = params_hash(ctx)?;
+        match self.objects.get(&path) {
+            Some(c) if c.params == params => {
+                let obj = c.object.clone();
+                self.resumed.insert(path);
+                Ok(Some(obj))
+            }
+            _ => Ok(None),
+        }
+    }
+    /// record the `Object` compiled under `ctx`
+    pub(crate) fn record(&mut self, ctx: &Context, obj: &Compiled) -> Result<(), CompilationError> {
+        let params = params_hash(ctx)?;
+        self.objects.insert(
+            SArc(ctx.path().clone()),
+            Checkpoint {
+                params,
+                object: obj.clone(),
+            },
+        );
+        Ok(())
+    }
+    /// every path with a recorded `Object`
+    pub fn paths(&self) -> impl Iterator<Item = &EffectPath> {
+        self.objects.keys().map(|p| p.0.as_ref())
+    }
+    /// the `Object` recorded at `path`, if any
+    pub fn get(&self, path: &EffectPath) -> Option<&Compiled> {
+        self.objects
+            .get(&SArc(Arc::new(path.clone())))
+            .map(|c| &c.object)
+    }
+    /// the paths which were resumed from a recorded `Object` (rather than
+    /// compiled) since this was created or deserialized
+    pub fn resumed(&self) -> impl Iterator<Item = &EffectPath> {
+        self.resumed.iter().map(|p| p.0.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::contract::test_helpers::{ctx, key, Leaf, Split};
+    use crate::contract::{Compilable, Context};
+    use bitcoin::util::amount::Amount;
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_resume_matches_one_shot() {
+        let contract = Split {
+            a: key(1),
+            b: key(2),
+        };
+        let one_shot = contract.compile(ctx(10_000)).unwrap();
+        let t = one_shot.ctv_to_tx.values().next().unwrap();
+        let sub_path = t.outputs[0].contract.root_path.0.clone();
+
+        // compile only the first subcontract, at the path it would have in
+        // the full compilation
+        let store = Arc::new(Mutex::new(PartialCompilation::default()));
+        let sub_ctx = Context::new(
+            bitcoin::Network::Regtest,
+            Amount::from_sat(2000),
+            Arc::new(sapio_ctv_emulator_trait::CTVAvailable),
+            sub_path.as_ref().clone(),
+            Default::default(),
+        )
+        .with_checkpoints(store.clone());
+        Leaf { to: key(1) }.compile(sub_ctx).unwrap();
+        let saved = serde_json::to_string(&*store.lock().unwrap()).unwrap();
+
+        // finish the compilation from the saved state
+        let restored: PartialCompilation = serde_json::from_str(&saved).unwrap();
+        assert!(restored.get(&sub_path).is_some());
+        let store = Arc::new(Mutex::new(restored));
+        let resumed = contract
+            .compile(ctx(10_000).with_checkpoints(store.clone()))
+            .unwrap();
+        assert_eq!(
+            store.lock().unwrap().resumed().collect::<Vec<_>>(),
+            vec![sub_path.as_ref()]
+        );
+        assert_eq!(
+            serde_json::to_value(&resumed).unwrap(),
+            serde_json::to_value(&one_shot).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_resume_checks_params() {
+        let contract = Split {
+            a: key(1),
+            b: key(2),
+        };
+        let one_shot = contract.compile(ctx(10_000)).unwrap();
+        let t = one_shot.ctv_to_tx.values().next().unwrap();
+        let sub_path = t.outputs[0].contract.root_path.0.clone();
+
+        // the first subcontract, at the right path but with the wrong funds
+        let store = Arc::new(Mutex::new(PartialCompilation::default()));
+        let sub_ctx = Context::new(
+            bitcoin::Network::Regtest,
+            Amount::from_sat(3000),
+            Arc::new(sapio_ctv_emulator_trait::CTVAvailable),
+            sub_path.as_ref().clone(),
+            Default::default(),
+        )
+        .with_checkpoints(store.clone());
+        Leaf { to: key(1) }.compile(sub_ctx).unwrap();
+
+        let recompiled = contract
+            .compile(ctx(10_000).with_checkpoints(store.clone()))
+            .unwrap();
+        assert_eq!(store.lock().unwrap().resumed().count(), 0);
+        assert_eq!(
+            serde_json::to_value(&recompiled).unwrap(),
+            serde_json::to_value(&one_shot).unwrap()
+        );
+    }
+}
@@ -0,0 +1,74 @@
+// Copyright Judica, Inc 2022
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+//  License, v. 2.0. If a copy of the MPL was not distributed with this
+//  file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! wall-clock profiling of a compilation
+use sapio_base::effects::EffectPath;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// A shared record of how long each contract took to compile, keyed by the
+/// path it was compiled at. Attach one with
+/// [`crate::contract::Context::with_timings`] and read it back afterwards.
+///
+/// A contract's time includes every contract it creates, so the entries
+/// nest like a flame graph: an expensive branch shows up as the deepest
+/// path that still accounts for most of its parent's time. Entries are in
+/// the order compilation finished, i.e. children before their parents, and
+/// only contracts which compiled successfully are recorded.
+#[derive(Clone, Default, Debug)]
+pub struct Timings(Arc<Mutex<Vec<(EffectPath, Duration)>>>);
+
+impl Timings {
+    /// an empty record
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// record that compiling at `path` took `elapsed`
+    pub(crate) fn record(&self, path: EffectPath, elapsed: Duration) {
+        self.0
+            .lock()
+            .expect("timings lock poisoned")
+            .push((path, elapsed));
+    }
+    /// remove and return all timings recorded so far
+    pub fn take(&self) -> Vec<(EffectPath, Duration)> {
+        std::mem::take(&mut *self.0.lock().expect("timings lock poisoned"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::contract::test_helpers::{ctx, key, Split};
+    use crate::contract::Compilable;
+
+    #[test]
+    fn test_timings() {
+        let timings = Timings::new();
+        Split {
+            a: key(1),
+            b: key(2),
+        }
+        .compile(ctx(10_000).with_timings(timings.clone()))
+        .unwrap();
+        let entries = timings.take();
+        let paths: Vec<String> = entries
+            .iter()
+            .map(|(p, _)| String::from(p.clone()))
+            .collect();
+        assert_eq!(
+            paths,
+            vec![
+                "@root/@action/divide/@next/@default_effect/#0",
+                "@root/@action/divide/@next/@default_effect/#1",
+                "@root",
+            ]
+        );
+        // a parent's time covers its children's
+        assert!(entries[..2].iter().all(|(_, d)| *d <= entries[2].1));
+        assert!(timings.take().is_empty());
+    }
+}
@@ -6,15 +6,28 @@
 
 //! utility functions for compiler
 
+use crate::contract::context::ScriptContext;
+use crate::contract::CompilationError;
 use ::miniscript::descriptor::TapTree;
 use ::miniscript::*;
 use bitcoin::hashes::sha256::Hash as Sha256;
 use bitcoin::hashes::Hash;
+use bitcoin::secp256k1::Parity;
+use bitcoin::PublicKey;
 use bitcoin::XOnlyPublicKey;
+use sapio_base::Clause;
 use std::cmp::Reverse;
-use std::collections::BinaryHeap;
+use std::collections::{BTreeMap, BTreeSet, BinaryHeap};
+use std::str::FromStr;
 use std::sync::Arc;
-/// picks a key from an iter of miniscripts, or returns a static default key
+/// the static key [`pick_key_from_miniscripts`] falls back to when no branch
+/// has a key of its own; nobody knows its secret key, so a taproot output
+/// using it as the internal key has no key path
+pub fn nums_key() -> XOnlyPublicKey {
+    XOnlyPublicKey::from_slice(&Sha256::hash(&[1u8; 32]).into_inner()).expect("constant")
+}
+
+/// picks a key from an iter of miniscripts, or returns [`nums_key`]
 pub fn pick_key_from_miniscripts<'a, I: Iterator<Item = &'a Miniscript<XOnlyPublicKey, Tap>>>(
     branches: I,
 ) -> XOnlyPublicKey {
@@ -28,9 +41,7 @@ pub fn pick_key_from_miniscripts<'a, I: Iterator<Item = &'a Miniscript<XOnlyPubl
             None
         })
         .next()
-        .unwrap_or(
-            XOnlyPublicKey::from_slice(&Sha256::hash(&[1u8; 32]).into_inner()).expect("constant"),
-        )
+        .unwrap_or_else(nums_key)
 }
 
 /// Convert the branches into a heap for taproot tree consumption
@@ -51,3 +62,504 @@ pub fn branches_to_tree(
     }
     scripts.pop().map(|v| v.1)
 }
+
+/// Breaks ties between equally optimal compilations by picking the
+/// candidate with the shortest script, then the lexicographically smallest
+/// serialized script.
+pub fn pick_canonical_miniscript<I: IntoIterator<Item = Miniscript<XOnlyPublicKey, Tap>>>(
+    candidates: I,
+) -> Option<Miniscript<XOnlyPublicKey, Tap>> {
+    candidates.into_iter().min_by_key(|m| {
+        let script = m.encode();
+        (script.len(), script.into_bytes())
+    })
+}
+
+/// Sort the children of every And/Or/Threshold in `policy` (recursively)
+/// into a stable order, so that policies differing only in the order
+/// commutative clauses were written in become identical.
+pub fn canonicalize_policy(policy: &Clause) -> Clause {
+    match policy {
+        Clause::And(v) => {
+            let mut v: Vec<_> = v.iter().map(canonicalize_policy).collect();
+            v.sort();
+            Clause::And(v)
+        }
+        Clause::Or(v) => {
+            let mut v: Vec<_> = v
+                .iter()
+                .map(|(w, c)| (*w, canonicalize_policy(c)))
+                .collect();
+            v.sort();
+            Clause::Or(v)
+        }
+        Clause::Threshold(k, v) => {
+            let mut v: Vec<_> = v.iter().map(canonicalize_policy).collect();
+            v.sort();
+            Clause::Threshold(*k, v)
+        }
+        p => p.clone(),
+    }
+}
+
+/// Remove trivial redundancy from `policy` (recursively), returning an
+/// equivalent policy that compiles to a script no larger:
+/// - duplicate children of an And or Or are dropped (weights of duplicate Or
+///   branches are added), e.g. `and(a, a)` becomes `a`
+/// - always satisfied children are dropped from an And, and never satisfiable
+///   children from an Or or Threshold, e.g. `or(false, x)` becomes `x`
+/// - an And with a never satisfiable child, or an Or with an always
+///   satisfied child, is replaced by that child
+/// - a Threshold counts its always satisfied children as already met
+/// - an And, Or or Threshold left with one child is replaced by it
+pub fn simplify_policy(policy: &Clause) -> Clause {
+    match policy {
+        Clause::And(v) => {
+            let mut children: Vec<Clause> = vec![];
+            for c in v.iter().map(simplify_policy) {
+                match c {
+                    Clause::Trivial => {}
+                    Clause::Unsatisfiable => return Clause::Unsatisfiable,
+                    c if children.contains(&c) => {}
+                    c => children.push(c),
+                }
+            }
+            match children.len() {
+                0 => Clause::Trivial,
+                1 => children.remove(0),
+                _ => Clause::And(children),
+            }
+        }
+        Clause::Or(v) => {
+            let mut children: Vec<(usize, Clause)> = vec![];
+            for (w, c) in v.iter().map(|(w, c)| (*w, simplify_policy(c))) {
+                match c {
+                    Clause::Unsatisfiable => {}
+                    Clause::Trivial => return Clause::Trivial,
+                    c => match children.iter_mut().find(|(_, d)| *d == c) {
+                        Some((weight, _)) => *weight += w,
+                        None => children.push((w, c)),
+                    },
+                }
+            }
+            match children.len() {
+                0 => Clause::Unsatisfiable,
+                1 => children.remove(0).1,
+                _ => Clause::Or(children),
+            }
+        }
+        Clause::Threshold(k, v) => {
+            let mut k = *k;
+            let mut children = vec![];
+            for c in v.iter().map(simplify_policy) {
+                match c {
+                    Clause::Unsatisfiable => {}
+                    Clause::Trivial => k = k.saturating_sub(1),
+                    c => children.push(c),
+                }
+            }
+            if k == 0 {
+                Clause::Trivial
+            } else if k > children.len() {
+                Clause::Unsatisfiable
+            } else if children.len() == 1 {
+                children.remove(0)
+            } else {
+                Clause::Threshold(k, children)
+            }
+        }
+        p => p.clone(),
+    }
+}
+
+/// The most children a top level And/Or/Threshold may have for
+/// [`compile_policy`] to try every ordering of them.
+pub const MAX_PERMUTED_CHILDREN: usize = 5;
+
+/// every ordering of `v`
+fn orderings<T: Clone>(v: &[T]) -> Vec<Vec<T>> {
+    if v.len() <= 1 {
+        return vec![v.to_vec()];
+    }
+    let mut all = vec![];
+    for i in 0..v.len() {
+        let mut rest = v.to_vec();
+        let first = rest.remove(i);
+        for mut o in orderings(&rest) {
+            o.insert(0, first.clone());
+            all.push(o);
+        }
+    }
+    all
+}
+
+/// Compile a policy to a miniscript.
+///
+/// The policy is first simplified (see [`simplify_policy`]). If
+/// `deterministic` is set, it is then canonicalized (see
+/// [`canonicalize_policy`]) and compiled once for every ordering of the
+/// children of its top level And/Or/Threshold, and the canonical candidate
+/// (see [`pick_canonical_miniscript`]) is returned, so that the result does
+/// not depend on clause order or on which of several optimal scripts the
+/// compiler settled on. Nested children are only compiled in their
+/// canonical order, as is a top level with more than
+/// [`MAX_PERMUTED_CHILDREN`] children.
+pub fn compile_policy(
+    policy: &Clause,
+    deterministic: bool,
+) -> Result<Miniscript<XOnlyPublicKey, Tap>, CompilationError> {
+    let policy = &simplify_policy(policy);
+    if !deterministic {
+        return Ok(policy.compile()?);
+    }
+    let policy = canonicalize_policy(policy);
+    let reordered = match &policy {
+        Clause::And(v) if v.len() <= MAX_PERMUTED_CHILDREN => {
+            orderings(v).into_iter().map(Clause::And).collect()
+        }
+        Clause::Or(v) if v.len() <= MAX_PERMUTED_CHILDREN => {
+            orderings(v).into_iter().map(Clause::Or).collect()
+        }
+        Clause::Threshold(k, v) if v.len() <= MAX_PERMUTED_CHILDREN => orderings(v)
+            .into_iter()
+            .map(|v| Clause::Threshold(*k, v))
+            .collect(),
+        _ => vec![policy],
+    };
+    let candidates: Vec<_> = reordered.iter().map(|p| p.compile()).collect();
+    let first_error = candidates.iter().find_map(|c| c.as_ref().err().copied());
+    pick_canonical_miniscript(candidates.into_iter().flatten())
+        .ok_or_else(|| first_error.expect("at least one candidate failed").into())
+}
+
+/// The full key standing in for the x-only `key` outside of Taproot: the
+/// one with the same x coordinate and an even y coordinate.
+pub fn even_key(key: &XOnlyPublicKey) -> PublicKey {
+    PublicKey::new(key.public_key(Parity::Even))
+}
+
+/// Check that `policy` can be expressed in the script context `context`,
+/// returning [`CompilationError::Unsupported`] if it compiles for Taproot but
+/// not for `context` (e.g. as it is too large for a P2SH script). Outside of
+/// Taproot, x-only keys are replaced by their [`even_key`].
+pub fn check_policy_context(
+    policy: &Clause,
+    context: ScriptContext,
+) -> Result<(), CompilationError> {
+    let full_keys = policy
+        .translate_pk(|k| Ok::<_, ()>(even_key(k)))
+        .expect("infallible");
+    let in_context = match context {
+        ScriptContext::Tap => return Ok(()),
+        ScriptContext::Legacy => full_keys.compile::<Legacy>().map(|_| ()),
+        ScriptContext::Segwitv0 => full_keys.compile::<Segwitv0>().map(|_| ()),
+    };
+    in_context.map_err(|e| match policy.compile::<Tap>() {
+        Ok(_) => CompilationError::Unsupported {
+            feature: e.to_string(),
+            context,
+        },
+        Err(e) => e.into(),
+    })
+}
+
+/// Check a guard's clause with [`check_policy_context`] while compiling. A
+/// guard alone may not yet be a valid script (e.g. a bare timelock), so only
+/// a context mismatch is reported.
+pub(crate) fn check_guard_context(
+    guard: &Clause,
+    context: ScriptContext,
+) -> Result<(), CompilationError> {
+    match check_policy_context(guard, context) {
+        Err(e @ CompilationError::Unsupported { .. }) => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// Re-express the Taproot script paths `branches` as a single script for
+/// `context` (which must not be [`ScriptContext::Tap`]), choosing between
+/// them with `or_i`, and wrap it in a P2WSH (or P2SH) descriptor.
+///
+/// Every x-only key is replaced by its [`even_key`]. The compiler may have
+/// hashed a key (`pk_h`), so `keys` must include every key the branches were
+/// compiled from.
+///
+/// Fails with [`CompilationError::Unsupported`] if a branch uses something
+/// the context lacks, as in [`check_policy_context`].
+pub(crate) fn non_tap_descriptor(
+    branches: &[Miniscript<XOnlyPublicKey, Tap>],
+    keys: &BTreeSet<XOnlyPublicKey>,
+    context: ScriptContext,
+) -> Result<Descriptor<PublicKey>, CompilationError> {
+    let unsupported = |feature: String| CompilationError::Unsupported { feature, context };
+    let hashes: BTreeMap<_, _> = keys
+        .iter()
+        .map(|k| (k.to_pubkeyhash(), even_key(k).to_pubkeyhash()))
+        .collect();
+    let joined = branches
+        .iter()
+        .map(|b| {
+            b.translate_pk(
+                |k| Ok(even_key(k)),
+                |h| {
+                    hashes
+                        .get(h)
+                        .copied()
+                        .ok_or_else(|| unsupported(format!("a hash of an unknown key {}", h)))
+                },
+            )
+        })
+        .collect::<Result<Vec<Miniscript<PublicKey, Tap>>, _>>()?
+        .iter()
+        .map(ToString::to_string)
+        .reduce(|a, b| format!("or_i({},{})", a, b))
+        .ok_or_else(|| unsupported("a contract with no spending paths".into()))?;
+    // a failure here is either a fragment or a top level script (e.g. one
+    // with no signature) that only Taproot allows
+    match context {
+        ScriptContext::Segwitv0 => {
+            Miniscript::<PublicKey, Segwitv0>::from_str(&joined).and_then(Descriptor::new_wsh)
+        }
+        ScriptContext::Legacy => {
+            Miniscript::<PublicKey, Legacy>::from_str(&joined).and_then(Descriptor::new_sh)
+        }
+        ScriptContext::Tap => return Err(unsupported("a single script for taproot".into())),
+    }
+    .map_err(|e| unsupported(e.to_string()))
+}
+
+/// Find timelocks required together by `policy` that no single transaction
+/// can satisfy, i.e. relative (or absolute) locks mixing heights and times.
+/// Returns an explanation of the first conflict found.
+pub(crate) fn find_guard_conflict(policy: &Clause) -> Option<String> {
+    fn conjuncts<'a>(p: &'a Clause, out: &mut Vec<&'a Clause>) {
+        match p {
+            Clause::And(v) => v.iter().for_each(|c| conjuncts(c, out)),
+            Clause::Threshold(k, v) if *k == v.len() => v.iter().for_each(|c| conjuncts(c, out)),
+            c => out.push(c),
+        }
+    }
+    let mut all = vec![];
+    conjuncts(policy, &mut all);
+    let find = |is_lock: fn(&Clause) -> Option<u32>, is_time: fn(u32) -> bool| {
+        let locks: Vec<u32> = all.iter().filter_map(|c| is_lock(c)).collect();
+        let time = locks.iter().find(|l| is_time(**l))?;
+        let height = locks.iter().find(|l| !is_time(**l))?;
+        Some((*height, *time))
+    };
+    let older = |c: &Clause| match c {
+        Clause::Older(n) => Some(*n),
+        _ => None,
+    };
+    let after = |c: &Clause| match c {
+        Clause::After(n) => Some(*n),
+        _ => None,
+    };
+    if let Some((h, t)) = find(older, |n| n & (1 << 22) != 0) {
+        return Some(format!(
+            "relative height lock older({}) and relative time lock older({}) can not both be satisfied",
+            h, t
+        ));
+    }
+    if let Some((h, t)) = find(after, |n| n >= 500_000_000) {
+        return Some(format!(
+            "absolute height lock after({}) and absolute time lock after({}) can not both be satisfied",
+            h, t
+        ));
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::contract::test_helpers::key;
+    #[test]
+    fn test_deterministic_compile_is_stable() -> Result<(), CompilationError> {
+        let policy = Clause::Or(vec![
+            (1, Clause::Key(key(1))),
+            (1, Clause::And(vec![Clause::Key(key(2)), Clause::Older(10)])),
+        ]);
+        let first = compile_policy(&policy, true)?;
+        for _ in 0..10 {
+            assert_eq!(compile_policy(&policy, true)?, first);
+        }
+        Ok(())
+    }
+    #[test]
+    fn test_deterministic_compile_tries_every_ordering() -> Result<(), CompilationError> {
+        let children = vec![
+            Clause::Key(key(1)),
+            Clause::And(vec![Clause::Key(key(2)), Clause::Older(10)]),
+            Clause::And(vec![Clause::Key(key(3)), Clause::After(100)]),
+        ];
+        let all = orderings(&children);
+        assert_eq!(all.len(), 6);
+        let thresh = |o: Vec<Clause>| canonicalize_policy(&Clause::Threshold(2, o));
+        let compiled = all
+            .iter()
+            .map(|o| Clause::Threshold(2, o.clone()).compile())
+            .collect::<Result<Vec<_>, _>>()?;
+        // which script the compiler settles on depends on the order
+        assert!(compiled.iter().any(|m| *m != compiled[0]));
+        let best = pick_canonical_miniscript(compiled);
+        for o in all {
+            assert_eq!(Some(compile_policy(&thresh(o), true)?), best);
+        }
+        Ok(())
+    }
+    #[test]
+    fn test_simplify_policy() -> Result<(), CompilationError> {
+        let (a, b) = (Clause::Key(key(1)), Clause::Key(key(2)));
+        let cases = vec![
+            (Clause::And(vec![a.clone(), a.clone()]), a.clone()),
+            (
+                Clause::Or(vec![(1, Clause::Unsatisfiable), (1, b.clone())]),
+                b.clone(),
+            ),
+            (Clause::And(vec![Clause::Trivial, a.clone()]), a.clone()),
+            (
+                Clause::And(vec![a.clone(), Clause::Unsatisfiable]),
+                Clause::Unsatisfiable,
+            ),
+            (Clause::Or(vec![(1, a.clone()), (3, a.clone())]), a.clone()),
+            (
+                Clause::Threshold(2, vec![Clause::Trivial, a.clone(), Clause::Unsatisfiable]),
+                a.clone(),
+            ),
+            (
+                Clause::Threshold(3, vec![a.clone(), b.clone(), Clause::Unsatisfiable]),
+                Clause::Unsatisfiable,
+            ),
+            // nested redundancy collapses bottom up
+            (
+                Clause::Or(vec![
+                    (1, Clause::And(vec![b.clone(), b.clone()])),
+                    (1, Clause::And(vec![a.clone(), Clause::Trivial])),
+                ]),
+                Clause::Or(vec![(1, b.clone()), (1, a.clone())]),
+            ),
+        ];
+        for (redundant, simplified) in cases {
+            assert_eq!(simplify_policy(&redundant), simplified);
+        }
+        // already minimal policies are untouched
+        let minimal = Clause::Or(vec![
+            (1, a.clone()),
+            (1, Clause::And(vec![b.clone(), Clause::Older(10)])),
+        ]);
+        assert_eq!(simplify_policy(&minimal), minimal);
+        // policies are simplified before they are compiled
+        assert_eq!(
+            compile_policy(&Clause::And(vec![a.clone(), a.clone()]), false)?,
+            a.compile()?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_canonical_clause_order() -> Result<(), CompilationError> {
+        let a = Clause::Or(vec![
+            (1, Clause::Key(key(1))),
+            (
+                1,
+                Clause::Threshold(
+                    2,
+                    vec![Clause::Key(key(2)), Clause::Key(key(3)), Clause::Older(10)],
+                ),
+            ),
+        ]);
+        let b = Clause::Or(vec![
+            (
+                1,
+                Clause::Threshold(
+                    2,
+                    vec![Clause::Older(10), Clause::Key(key(3)), Clause::Key(key(2))],
+                ),
+            ),
+            (1, Clause::Key(key(1))),
+        ]);
+        assert_ne!(a, b);
+        assert_eq!(canonicalize_policy(&a), canonicalize_policy(&b));
+        assert_eq!(
+            compile_policy(&a, true)?.encode(),
+            compile_policy(&b, true)?.encode()
+        );
+        Ok(())
+    }
+    #[test]
+    fn test_taproot_only_policy() {
+        // outside of Taproot, x-only keys stand in for their even keys
+        let policy = Clause::Or(vec![
+            (1, Clause::Key(key(1))),
+            (1, Clause::And(vec![Clause::Key(key(2)), Clause::Older(10)])),
+        ]);
+        for context in [ScriptContext::Tap, ScriptContext::Segwitv0] {
+            assert!(check_policy_context(&policy, context).is_ok());
+        }
+        let committee = Clause::Threshold(1, (1..=20).map(|k| Clause::Key(key(k))).collect());
+        assert!(check_policy_context(&committee, ScriptContext::Segwitv0).is_ok());
+        match check_policy_context(&committee, ScriptContext::Legacy) {
+            Err(CompilationError::Unsupported { context, .. }) => {
+                assert_eq!(context, ScriptContext::Legacy);
+            }
+            r => panic!("expected Unsupported, got {:?}", r),
+        }
+        // policies no context can express are the compiler's errors
+        assert!(matches!(
+            check_policy_context(&Clause::Older(10), ScriptContext::Segwitv0),
+            Err(CompilationError::Miniscript(_))
+        ));
+    }
+    #[test]
+    fn test_non_tap_key_hashes() -> Result<(), CompilationError> {
+        // the rarely used branch's keys are compiled to key hashes
+        let policy = Clause::Or(vec![
+            (99, Clause::Key(key(1))),
+            (
+                1,
+                Clause::And(vec![Clause::Key(key(2)), Clause::Key(key(3))]),
+            ),
+        ]);
+        let branches = [compile_policy(&policy, true)?];
+        let keys: BTreeSet<_> = policy.keys().into_iter().copied().collect();
+        let d = non_tap_descriptor(&branches, &keys, ScriptContext::Segwitv0)?.to_string();
+        for k in [key(2), key(3)] {
+            assert!(d.contains(&even_key(&k).to_pubkeyhash().to_string()));
+        }
+        // a hash can not be translated without its key
+        assert!(matches!(
+            non_tap_descriptor(&branches, &Default::default(), ScriptContext::Segwitv0),
+            Err(CompilationError::Unsupported { .. })
+        ));
+        Ok(())
+    }
+    #[test]
+    fn test_find_guard_conflict() {
+        let rel_time = (1 << 22) | 5;
+        let conflict = Clause::And(vec![
+            Clause::Key(key(1)),
+            Clause::And(vec![Clause::Older(10), Clause::Older(rel_time)]),
+        ]);
+        assert!(find_guard_conflict(&conflict)
+            .unwrap()
+            .contains("older(10)"));
+        let ok = Clause::And(vec![Clause::Older(10), Clause::Older(20)]);
+        assert!(find_guard_conflict(&ok).is_none());
+        // alternatives do not conflict
+        let or = Clause::Or(vec![(1, Clause::Older(10)), (1, Clause::Older(rel_time))]);
+        assert!(find_guard_conflict(&or).is_none());
+        let abs = Clause::And(vec![Clause::After(100), Clause::After(600_000_000)]);
+        assert!(find_guard_conflict(&abs).is_some());
+    }
+    #[test]
+    fn test_pick_canonical_ignores_candidate_order() -> Result<(), CompilationError> {
+        let a = Clause::Key(key(1)).compile()?;
+        let b = Clause::Key(key(2)).compile()?;
+        assert_eq!(
+            pick_canonical_miniscript(vec![a.clone(), b.clone()]),
+            pick_canonical_miniscript(vec![b, a])
+        );
+        Ok(())
+    }
+}
@@ -6,32 +6,112 @@
 
 //! general non-parameter compilation state required by all contracts
 use super::{Amount, Compilable, CompilationError, Compiled};
+use crate::contract::compiler::resume::PartialCompilation;
+use crate::contract::compiler::timing::Timings;
 use crate::contract::compiler::InternalCompilerTag;
+use crate::contract::error::DisplayIn;
+use crate::contract::keys::KeyResolver;
+use crate::contract::warnings::{LintLevel, Warnings};
+use crate::template::fees::FeeEstimator;
+use crate::util::extended_address::ExtendedAddress;
 
+use bitcoin::util::amount::Denomination;
 use bitcoin::Network;
 
 use sapio_base::effects::EffectPath;
 use sapio_base::effects::PathFragment;
+use sapio_base::effects::ValidFragmentError;
+use sapio_base::effects::DEFAULT_MAX_BRANCH;
 pub use sapio_base::effects::{EffectDB, MapEffectDB};
+use sapio_base::serialization_helpers::SArc;
 
+use miniscript::policy::Concrete;
 use sapio_ctv_emulator_trait::CTVEmulator;
 use std::convert::TryInto;
 
-use std::collections::HashSet;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
 
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+
+/// The miniscript context a policy is compiled for
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ScriptContext {
+    /// Bare / P2SH scripts
+    Legacy,
+    /// Segwit v0 (P2WSH) scripts
+    Segwitv0,
+    /// Taproot script paths
+    Tap,
+}
+
+/// The order in which a contract's `then` functions are compiled (and so
+/// the order of the branches they produce), see [`Context::with_then_order`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ThenOrder {
+    /// the order the functions are listed in `declare! {then, ...}`
+    Declaration,
+    /// ascending by function name, as `PathFragment::Named` orders
+    ByName,
+}
+
+/// The contracts currently being compiled, outermost first, identified by
+/// address and type name (a field at offset zero shares its parent's
+/// address, and every zero sized contract shares one) and paired with the
+/// funds available to and path of each
+type CompileStack = Arc<Mutex<Vec<(ContractId, Amount, Arc<EffectPath>)>>>;
+pub(crate) type ContractId = (usize, &'static str);
+
+/// Marks a contract as being compiled until dropped, see
+/// [`Context::enter_compile`]
+pub(crate) struct CompileFrame(CompileStack);
+impl Drop for CompileFrame {
+    fn drop(&mut self) {
+        self.0.lock().expect("compile stack lock poisoned").pop();
+    }
+}
+
+/// A sink for [`Context::log`] lines
+pub type Logger = Arc<dyn Fn(&str) + Send + Sync>;
+
+/// The settings and shared compilation state a [`Context`] passes on
+/// unchanged to every context derived from it
+#[derive(Clone)]
+struct CompileOptions {
+    emulator: Arc<dyn CTVEmulator>,
+    effects: Arc<MapEffectDB>,
+    effect_lookups: Arc<Mutex<BTreeSet<SArc<EffectPath>>>>,
+    compiling: CompileStack,
+    deterministic: bool,
+    now: Option<u32>,
+    fee_estimator: Option<Arc<dyn FeeEstimator>>,
+    key_resolver: Option<Arc<dyn KeyResolver>>,
+    max_branch: u64,
+    allowed_builtins: Option<Arc<[PathFragment]>>,
+    max_serialized_bytes: Option<usize>,
+    checkpoints: Option<Arc<Mutex<PartialCompilation>>>,
+    warnings: Warnings,
+    duplicate_templates: LintLevel,
+    then_order: ThenOrder,
+    logger: Option<Logger>,
+    shallow: bool,
+    allow_zero_fee: bool,
+    timings: Option<Timings>,
+    min_feerate: Option<Amount>,
+    script_context: ScriptContext,
+    amount_unit: Denomination,
+}
 
 /// Context is used to track statet during compilation such as remaining value.
 pub struct Context {
     /* TODO: Add Context Fields! */
     available_funds: Amount,
-    emulator: Arc<dyn CTVEmulator>,
     /// which network is the contract building for?
     pub network: Network,
     /// TODO: reversed linked list of ARCs to better de-duplicate memory.
     path: Arc<EffectPath>,
     already_derived: HashSet<PathFragment>,
-    effects: Arc<MapEffectDB>,
+    frozen: bool,
+    opts: CompileOptions,
 }
 
 impl Context {
@@ -46,21 +126,92 @@ impl Context {
     ) -> Self {
         Context {
             available_funds,
-            emulator,
             network,
             // TODO: Should return Option Self if path is not length > 0
             path: Arc::new(path),
             already_derived: Default::default(),
-            effects,
+            frozen: false,
+            opts: CompileOptions {
+                emulator,
+                effects,
+                effect_lookups: Default::default(),
+                compiling: Default::default(),
+                deterministic: false,
+                now: None,
+                fee_estimator: None,
+                key_resolver: None,
+                max_branch: DEFAULT_MAX_BRANCH,
+                allowed_builtins: None,
+                max_serialized_bytes: None,
+                checkpoints: None,
+                warnings: Default::default(),
+                duplicate_templates: LintLevel::Deny,
+                then_order: ThenOrder::Declaration,
+                logger: None,
+                shallow: false,
+                allow_zero_fee: false,
+                timings: None,
+                min_feerate: None,
+                script_context: ScriptContext::Tap,
+                amount_unit: Denomination::Bitcoin,
+            },
         }
     }
     /// Get this Context's effect database, for clients
     pub unsafe fn get_effects_internal(&self) -> &Arc<MapEffectDB> {
-        &self.effects
+        &self.opts.effects
     }
     /// Get this Context's effect database
     pub(crate) fn get_effects(&self, _: InternalCompilerTag) -> &Arc<MapEffectDB> {
-        &self.effects
+        &self.opts.effects
+    }
+    /// Note that the compiler consulted the effect DB at this context's path
+    pub(crate) fn record_effect_lookup(&self, _: InternalCompilerTag) {
+        self.opts
+            .effect_lookups
+            .lock()
+            .expect("effect lookups lock poisoned")
+            .insert(SArc(self.path.clone()));
+    }
+    /// The effect DB paths at or below this context's path that the compiler
+    /// has not consulted
+    pub(crate) fn unused_effects(&self, _: InternalCompilerTag) -> Vec<SArc<EffectPath>> {
+        let used = self
+            .opts
+            .effect_lookups
+            .lock()
+            .expect("effect lookups lock poisoned");
+        self.opts
+            .effects
+            .paths()
+            .filter(|p| p.starts_with(&self.path) && !used.contains(&SArc((*p).clone())))
+            .map(|p| SArc(p.clone()))
+            .collect()
+    }
+    /// Mark the contract `id` as being compiled at this context's
+    /// path until the returned frame is dropped. Fails with
+    /// [`CompilationError::CyclicDependency`] if it is already being compiled
+    /// further up with the same funds available, i.e. if it would
+    /// (transitively) contain itself without making progress. A contract
+    /// may contain itself with fewer funds (e.g. halving them each time
+    /// until it pays out), as that recursion ends.
+    pub(crate) fn enter_compile(&self, id: ContractId) -> Result<CompileFrame, CompilationError> {
+        let mut stack = self
+            .opts
+            .compiling
+            .lock()
+            .expect("compile stack lock poisoned");
+        let funds = self.available_funds;
+        if let Some(start) = stack.iter().position(|(i, f, _)| *i == id && *f == funds) {
+            let mut cycle: Vec<EffectPath> = stack[start..]
+                .iter()
+                .map(|(_, _, p)| (**p).clone())
+                .collect();
+            cycle.push((*self.path).clone());
+            return Err(CompilationError::CyclicDependency { cycle });
+        }
+        stack.push((id, funds, self.path.clone()));
+        Ok(CompileFrame(self.opts.compiling.clone()))
     }
     /// Gets this Context's Path, but does not clone (left to caller)
     pub fn path(&self) -> &Arc<EffectPath> {
@@ -82,6 +233,11 @@ impl Context {
     }
     /// Derive a new contextual path
     pub(crate) fn derive(&mut self, path: PathFragment) -> Result<Self, CompilationError> {
+        if self.frozen {
+            return Err(CompilationError::FrozenContext);
+        }
+        path.check_branch(self.opts.max_branch)?;
+        self.check_allowed(&path)?;
         if self.already_derived.contains(&path) {
             Err(CompilationError::ContexPathAlreadyDerived)
         } else {
@@ -89,27 +245,291 @@ impl Context {
             let new_path = EffectPath::push(Some(self.path.clone()), path);
             Ok(Context {
                 available_funds: self.available_funds,
-                emulator: self.emulator.clone(),
-                path: new_path,
                 network: self.network,
+                path: new_path,
                 already_derived: Default::default(),
-                effects: self.effects.clone(),
+                frozen: false,
+                opts: self.opts.clone(),
             })
         }
     }
+    /// Forbid deriving any further paths from this context, so that a late
+    /// derivation (which would be a bug) fails with
+    /// [`CompilationError::FrozenContext`] rather than silently extending
+    /// the contract. Contexts already derived from it are unaffected.
+    pub fn freeze(&mut self) {
+        self.frozen = true;
+    }
+    /// has [`Context::freeze`] been called on this context?
+    pub fn is_frozen(&self) -> bool {
+        self.frozen
+    }
     /// Method is unsafe, but may (provably!) be only called from within
     /// compiler.rs where the `InternalCompilerTag` may be generated.
     pub(crate) fn internal_clone(&self, _i: InternalCompilerTag) -> Self {
         Context {
             available_funds: self.available_funds,
-            emulator: self.emulator.clone(),
-            path: self.path.clone(),
             network: self.network,
+            path: self.path.clone(),
             already_derived: self.already_derived.clone(),
-            effects: self.effects.clone(),
+            frozen: self.frozen,
+            opts: self.opts.clone(),
         }
     }
 
+    /// Enable/disable deterministic mode. When enabled, policy compilation
+    /// breaks ties between equally optimal scripts with a stable ordering so
+    /// that output is reproducible across versions and platforms.
+    pub fn with_deterministic(mut self, deterministic: bool) -> Self {
+        self.opts.deterministic = deterministic;
+        self
+    }
+
+    /// is this context compiling in deterministic mode?
+    pub fn is_deterministic(&self) -> bool {
+        self.opts.deterministic
+    }
+
+    /// Fix the value [`Context::now`] returns (a block height or unix time,
+    /// whichever the contract expects) so compilation is reproducible.
+    pub fn with_now(mut self, now: u32) -> Self {
+        self.opts.now = Some(now);
+        self
+    }
+
+    /// The current time for contracts computing timelocks: the value set by
+    /// [`Context::with_now`], or else the system clock's unix time.
+    pub fn now(&self) -> u32 {
+        self.opts.now.unwrap_or_else(|| {
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_secs() as u32)
+                .unwrap_or(0)
+        })
+    }
+
+    /// Skip the minimum feerate check on templates, so that e.g. regtest
+    /// contracts may pay no fee at all without a
+    /// [`CompilationError::MinFeerateError`]. Off by default; never enable
+    /// it for contracts meant for mainnet.
+    pub fn with_allow_zero_fee(mut self, allow: bool) -> Self {
+        self.opts.allow_zero_fee = allow;
+        self
+    }
+
+    /// may templates compiled in this context ignore their minimum feerate?
+    pub fn allows_zero_fee(&self) -> bool {
+        self.opts.allow_zero_fee
+    }
+
+    /// Set the minimum feerate (in sats per vbyte) for committed templates
+    /// compiled in this context (and those derived from it) which do not
+    /// set their own with [`crate::template::Builder::set_min_feerate`]. A
+    /// template's own floor always takes precedence, higher or lower, so
+    /// e.g. an emergency branch can pay less than the contract default.
+    pub fn with_min_feerate(mut self, feerate: Amount) -> Self {
+        self.opts.min_feerate = Some(feerate);
+        self
+    }
+
+    /// the default minimum feerate for templates without their own, if any,
+    /// not counting the [`Context::fee_estimator`]
+    pub fn min_feerate(&self) -> Option<Amount> {
+        self.opts.min_feerate
+    }
+
+    /// Set the script context contracts compiled in this context (and those
+    /// derived from it) produce outputs for. Defaults to
+    /// [`ScriptContext::Tap`]; otherwise every spending path is combined
+    /// into one P2WSH (or P2SH) script, with each x-only key replaced by
+    /// its even full key, failing with
+    /// [`CompilationError::Unsupported`] if a path can not be expressed
+    /// there (see [`crate::contract::compiler::check_policy_context`]).
+    pub fn with_script_context(mut self, context: ScriptContext) -> Self {
+        self.opts.script_context = context;
+        self
+    }
+
+    /// the script context outputs are compiled for
+    pub fn script_context(&self) -> ScriptContext {
+        self.opts.script_context
+    }
+
+    /// Set the unit [`Context::display_error`] shows amounts in (e.g. those
+    /// of [`CompilationError::OutOfFunds`]). Defaults to BTC.
+    pub fn with_amount_unit(mut self, unit: Denomination) -> Self {
+        self.opts.amount_unit = unit;
+        self
+    }
+
+    /// the unit [`Context::display_error`] shows amounts in
+    pub fn amount_unit(&self) -> Denomination {
+        self.opts.amount_unit
+    }
+
+    /// Display `e` with its amounts in this context's
+    /// [unit](Context::with_amount_unit), e.g. for reporting a failed
+    /// compilation to the caller who configured the context.
+    pub fn display_error<'a>(&self, e: &'a CompilationError) -> DisplayIn<'a> {
+        e.display_in(self.opts.amount_unit)
+    }
+
+    /// [`CompilationError::log_fields`] for `e`, with its message in this
+    /// context's [unit](Context::with_amount_unit).
+    pub fn error_log_fields(&self, e: &CompilationError) -> Vec<(&'static str, String)> {
+        e.log_fields_in(self.opts.amount_unit)
+    }
+
+    /// Set the largest `PathFragment::Branch` index this context (and those
+    /// derived from it) will accept. Defaults to [`DEFAULT_MAX_BRANCH`].
+    pub fn with_max_branch(mut self, max: u64) -> Self {
+        self.opts.max_branch = max;
+        self
+    }
+
+    /// Permit only the listed builtin fragments (e.g. to forbid
+    /// `@suggested` in a restricted contract format), matched by kind.
+    /// Deriving or resolving a path with any other builtin fails with
+    /// [`ValidFragmentError::ForbiddenFragment`]. Branches and names are
+    /// always permitted; by default so is every builtin.
+    pub fn with_allowed_builtins(mut self, allowed: Vec<PathFragment>) -> Self {
+        self.opts.allowed_builtins = Some(allowed.into());
+        self
+    }
+
+    fn check_allowed(&self, f: &PathFragment) -> Result<(), ValidFragmentError> {
+        match &self.opts.allowed_builtins {
+            Some(allowed) => f.check_allowed(allowed),
+            None => Ok(()),
+        }
+    }
+
+    /// Fail compilation with [`CompilationError::OutputTooLarge`] if the
+    /// JSON serialization of any compiled object (including those created
+    /// by this context's contract) would exceed `max` bytes.
+    pub fn with_max_serialized_bytes(mut self, max: usize) -> Self {
+        self.opts.max_serialized_bytes = Some(max);
+        self
+    }
+
+    /// the largest serialized object this context will produce, if capped
+    pub fn max_serialized_bytes(&self) -> Option<usize> {
+        self.opts.max_serialized_bytes
+    }
+
+    /// Record every contract compiled with this context (and those derived
+    /// from it) into `checkpoints`, and resume from any already recorded
+    /// there rather than recompiling them.
+    pub fn with_checkpoints(mut self, checkpoints: Arc<Mutex<PartialCompilation>>) -> Self {
+        self.opts.checkpoints = Some(checkpoints);
+        self
+    }
+
+    /// the checkpoints this context records into, if any
+    pub fn checkpoints(&self) -> Option<&Arc<Mutex<PartialCompilation>>> {
+        self.opts.checkpoints.as_ref()
+    }
+
+    /// Record how long every contract compiled with this context (and
+    /// those derived from it) takes into `timings`, see [`Timings`]
+    pub fn with_timings(mut self, timings: Timings) -> Self {
+        self.opts.timings = Some(timings);
+        self
+    }
+
+    /// the timings this context records into, if any
+    pub fn timings(&self) -> Option<&Timings> {
+        self.opts.timings.as_ref()
+    }
+
+    /// A handle to the warnings recorded while compiling with this context
+    /// (and those derived from it)
+    pub fn warnings(&self) -> Warnings {
+        self.opts.warnings.clone()
+    }
+
+    /// Set how to treat two different branches producing the same template
+    /// (see [`CompilationError::DuplicateTemplate`]). Defaults to
+    /// [`LintLevel::Deny`].
+    pub fn with_duplicate_templates(mut self, level: LintLevel) -> Self {
+        self.opts.duplicate_templates = level;
+        self
+    }
+
+    /// how two different branches producing the same template is treated
+    pub fn duplicate_templates(&self) -> LintLevel {
+        self.opts.duplicate_templates
+    }
+
+    /// Send [`Context::log`] lines from this context (and those derived from
+    /// it) to `logger`. Without one, lines are discarded.
+    pub fn with_logger(mut self, logger: Logger) -> Self {
+        self.opts.logger = Some(logger);
+        self
+    }
+
+    /// Print [`Context::log`] lines from this context (and those derived
+    /// from it) to stderr.
+    pub fn with_stderr_logger(self) -> Self {
+        self.with_logger(Arc::new(|line: &str| eprintln!("{}", line)))
+    }
+
+    /// An [`CompilationError::InvalidArgument`] rejecting contract parameter
+    /// `field` at this context's path, e.g.
+    /// `return Err(ctx.reject_arg("amount", "must be positive"))`.
+    pub fn reject_arg(&self, field: &str, reason: &str) -> CompilationError {
+        CompilationError::InvalidArgument {
+            field: field.into(),
+            reason: reason.into(),
+            path: (*self.path).clone(),
+        }
+    }
+
+    /// Log a message prefixed with this context's path, e.g.
+    /// `[@root/pay] paying out`, to the [logger](Context::with_logger), if
+    /// any. See also [`crate::compile_log`].
+    pub fn log(&self, msg: impl std::fmt::Display) {
+        if let Some(logger) = &self.opts.logger {
+            logger(&format!("[{}] {}", String::from((*self.path).clone()), msg));
+        }
+    }
+
+    /// Resolve a path written relative to this context (e.g. `../refund` in
+    /// an effect file) into an absolute one. See [`EffectPath::resolve`].
+    pub fn resolve_path(&self, path: &str) -> Result<EffectPath, CompilationError> {
+        let resolved = self.path.resolve(path)?;
+        for f in resolved.iter() {
+            self.check_allowed(f)?;
+        }
+        Ok(resolved)
+    }
+
+    /// Set the order a contract's `then` functions are compiled in. The
+    /// default, [`ThenOrder::Declaration`], keeps the order they are
+    /// declared in.
+    pub fn with_then_order(mut self, order: ThenOrder) -> Self {
+        self.opts.then_order = order;
+        self
+    }
+
+    /// the order a contract's `then` functions are compiled in
+    pub fn then_order(&self) -> ThenOrder {
+        self.opts.then_order
+    }
+
+    /// Set the estimator used to price templates built from this context.
+    /// Without [`Context::with_min_feerate`], its feerate is also the
+    /// minimum for committed templates which do not set their own.
+    pub fn with_fee_estimator(mut self, estimator: Arc<dyn FeeEstimator>) -> Self {
+        self.opts.fee_estimator = Some(estimator);
+        self
+    }
+
+    /// the estimator used to price templates, if any
+    pub fn fee_estimator(&self) -> Option<&Arc<dyn FeeEstimator>> {
+        self.opts.fee_estimator.as_ref()
+    }
+
     /// return the available funds
     pub fn funds(&self) -> Amount {
         self.available_funds
@@ -120,7 +540,78 @@ impl Context {
         &self,
         b: bitcoin::hashes::sha256::Hash,
     ) -> Result<sapio_base::Clause, CompilationError> {
-        Ok(self.emulator.get_signer_for(b)?)
+        Ok(self.opts.emulator.get_signer_for(b)?)
+    }
+
+    /// Set the resolver guards use to look up keys by alias, see
+    /// [`Context::resolve_policy`]
+    pub fn with_key_resolver(mut self, resolver: Arc<dyn KeyResolver>) -> Self {
+        self.opts.key_resolver = Some(resolver);
+        self
+    }
+
+    /// Resolve a policy written over key aliases into a concrete `Clause`
+    /// using this context's [`KeyResolver`].
+    ///
+    /// Returns [`CompilationError::UnknownKey`] naming the first alias the
+    /// resolver does not know, or the first alias at all if there is no
+    /// resolver.
+    pub fn resolve_policy(
+        &self,
+        policy: &Concrete<String>,
+    ) -> Result<sapio_base::Clause, CompilationError> {
+        match &self.opts.key_resolver {
+            Some(r) => self.resolve_keys(policy, r.as_ref()),
+            None => self.resolve_keys(policy, &BTreeMap::new()),
+        }
+    }
+
+    /// Resolve a policy written over key aliases into a concrete `Clause`
+    /// using the provided resolver (e.g. a key map).
+    ///
+    /// Returns [`CompilationError::UnknownKey`] naming the first alias that
+    /// `keys` does not know.
+    pub fn resolve_keys<R: KeyResolver + ?Sized>(
+        &self,
+        policy: &Concrete<String>,
+        keys: &R,
+    ) -> Result<sapio_base::Clause, CompilationError> {
+        policy.translate_pk(|alias: &String| {
+            keys.resolve(alias)
+                .ok_or_else(|| CompilationError::UnknownKey {
+                    alias: alias.clone(),
+                    path: self.path.as_ref().clone(),
+                })
+        })
+    }
+
+    /// Feed every parameter of this context that can affect compilation
+    /// into `engine`, for [`crate::contract::compiler::compile_cache`].
+    /// Trait objects (the emulator, fee estimator, key resolver, and logger)
+    /// can not be hashed and are left out.
+    pub(crate) fn hash_params(
+        &self,
+        engine: &mut bitcoin::hashes::sha256::HashEngine,
+    ) -> Result<(), CompilationError> {
+        let params = serde_json::json!({
+            "network": self.network.to_string(),
+            "funds": self.available_funds.as_sat(),
+            "path": self.path.as_ref(),
+            "effects": self.opts.effects.as_ref(),
+            "deterministic": self.opts.deterministic,
+            "now": self.opts.now,
+            "max_branch": self.opts.max_branch,
+            "allowed_builtins": self.opts.allowed_builtins.as_deref(),
+            "max_serialized_bytes": self.opts.max_serialized_bytes,
+            "duplicate_templates": format!("{:?}", self.opts.duplicate_templates),
+            "then_order": format!("{:?}", self.opts.then_order),
+            "shallow": self.opts.shallow,
+            "allow_zero_fee": self.opts.allow_zero_fee,
+            "min_feerate": self.opts.min_feerate.map(|a| a.as_sat()),
+            "script_context": format!("{:?}", self.opts.script_context),
+        });
+        serde_json::to_writer(engine, &params)?;
+        Ok(())
     }
 
     /// Compile the compilable item with this context.
@@ -128,26 +619,53 @@ impl Context {
         a.compile(self)
     }
 
+    /// Compute only the address `a` would be compiled to, e.g. to preview it
+    /// before committing to a contract.
+    ///
+    /// Continuations (which cannot change the address) are not expanded and
+    /// metadata is not generated, but every template a `then` commits to
+    /// still must be, as its hash is part of the address. Fee requirements
+    /// are not checked.
+    pub fn shallow_compile<A: Compilable>(
+        mut self,
+        a: A,
+    ) -> Result<ExtendedAddress, CompilationError> {
+        self.opts.shallow = true;
+        a.compile(self).map(|obj| obj.address)
+    }
+
+    /// is this context only computing an address, see
+    /// [`Context::shallow_compile`]?
+    pub(crate) fn is_shallow(&self) -> bool {
+        self.opts.shallow
+    }
+
     // TODO: Fix
     /// return a context with the new amount if amount is smaller or equal to available
     pub fn with_amount(self, amount: Amount) -> Result<Self, CompilationError> {
         if self.available_funds < amount {
-            Err(CompilationError::OutOfFunds)
+            Err(CompilationError::OutOfFunds {
+                available: self.available_funds,
+                required: amount,
+            })
         } else {
             Ok(Context {
                 available_funds: amount,
-                emulator: self.emulator.clone(),
-                path: self.path.clone(),
                 network: self.network,
+                path: self.path.clone(),
                 already_derived: self.already_derived.clone(),
-                effects: self.effects.clone(),
+                frozen: self.frozen,
+                opts: self.opts.clone(),
             })
         }
     }
     /// decrease the amount available in this context object.
     pub fn spend_amount(mut self, amount: Amount) -> Result<Self, CompilationError> {
         if self.available_funds < amount {
-            Err(CompilationError::OutOfFunds)
+            Err(CompilationError::OutOfFunds {
+                available: self.available_funds,
+                required: amount,
+            })
         } else {
             self.available_funds -= amount;
             Ok(self)
@@ -165,3 +683,206 @@ impl Context {
         crate::template::Builder::new(self)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate as sapio;
+    use crate::contract::test_helpers::{ctx, key};
+    use crate::*;
+    use bitcoin::XOnlyPublicKey;
+    use sapio_base::effects::ValidFragmentError;
+    use std::str::FromStr;
+
+    struct Order {
+        to: XOnlyPublicKey,
+        quantity: i64,
+    }
+    impl Order {
+        #[then]
+        fn fill(self, ctx: crate::Context) {
+            if self.quantity < 0 {
+                return Err(ctx.reject_arg("quantity", "must not be negative"));
+            }
+            ctx.template()
+                .add_output(Amount::from_sat(self.quantity as u64), &self.to, None)?
+                .into()
+        }
+    }
+    impl crate::contract::Contract for Order {
+        declare! {then, Self::fill}
+        declare! {non updatable}
+    }
+
+    #[test]
+    fn test_reject_arg() {
+        let order = |quantity| Order {
+            to: key(1),
+            quantity,
+        };
+        assert!(order(10).compile(ctx(1000)).is_ok());
+        let e = order(-1).compile(ctx(1000)).err().unwrap();
+        assert_eq!(
+            e.to_string(),
+            "invalid argument `quantity` at @root/@action/fill/@next/@default_effect: must not be negative"
+        );
+        assert!(matches!(e, CompilationError::InvalidArgument { .. }));
+    }
+
+    #[test]
+    fn test_amount_unit() {
+        let order = || Order {
+            to: key(1),
+            quantity: 2000,
+        };
+        let c = ctx(1000);
+        assert_eq!(c.amount_unit(), Denomination::Bitcoin);
+        let e = order().compile(ctx(1000)).unwrap_err();
+        assert_eq!(
+            c.display_error(&e).to_string(),
+            "out of funds: required 0.00002000 BTC but only 0.00001000 BTC available"
+        );
+        let mut c = ctx(1000).with_amount_unit(Denomination::Satoshi);
+        let sub = c.derive_str(Arc::new("pay".into())).unwrap();
+        assert_eq!(sub.amount_unit(), Denomination::Satoshi);
+        assert_eq!(
+            c.display_error(&e).to_string(),
+            "out of funds: required 2000 satoshi but only 1000 satoshi available"
+        );
+        assert!(c.error_log_fields(&e).contains(&(
+            "message",
+            "out of funds: required 2000 satoshi but only 1000 satoshi available".into()
+        )));
+    }
+
+    #[test]
+    fn test_compile_log_path_prefix() {
+        let lines: Arc<Mutex<Vec<String>>> = Default::default();
+        let sink = lines.clone();
+        let mut c = ctx(1000).with_logger(Arc::new(move |l: &str| {
+            sink.lock().unwrap().push(l.to_string())
+        }));
+        crate::compile_log!(c, "at the top");
+        let c = c.derive_str(Arc::new("pay".into())).unwrap();
+        crate::compile_log!(c, "paying {} sats", 10);
+        assert_eq!(
+            *lines.lock().unwrap(),
+            vec!["[@root] at the top", "[@root/pay] paying 10 sats"]
+        );
+    }
+
+    #[test]
+    fn test_compile_log_default_is_silent() {
+        // the message is never even formatted without a logger
+        struct Unprintable;
+        impl std::fmt::Display for Unprintable {
+            fn fmt(&self, _: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                panic!("formatted a log line with no logger")
+            }
+        }
+        ctx(1000).log(Unprintable);
+        assert!(ctx(1000).with_stderr_logger().opts.logger.is_some());
+    }
+
+    #[test]
+    fn test_max_branch() {
+        let mut c = ctx(1000).with_max_branch(10);
+        assert!(c.derive_num(10u64).is_ok());
+        assert!(matches!(
+            c.derive_num(11u64),
+            Err(CompilationError::PathFragmentError(
+                ValidFragmentError::BranchOutOfRange { value: 11, max: 10 }
+            ))
+        ));
+        let mut c = ctx(1000);
+        assert!(c.derive_num(DEFAULT_MAX_BRANCH).is_ok());
+        assert!(c.derive_num(DEFAULT_MAX_BRANCH + 1).is_err());
+    }
+
+    #[test]
+    fn test_freeze() {
+        let mut c = ctx(1000);
+        let mut child = c.derive_num(0u64).unwrap();
+        c.freeze();
+        assert!(c.is_frozen() && !child.is_frozen());
+        assert!(matches!(
+            c.derive_num(1u64),
+            Err(CompilationError::FrozenContext)
+        ));
+        assert!(matches!(
+            c.derive_str(Arc::new("late".into())),
+            Err(CompilationError::FrozenContext)
+        ));
+        // including the derivations a template makes for its outputs
+        assert!(matches!(
+            c.template().add_output(Amount::from_sat(10), &key(1), None),
+            Err(CompilationError::FrozenContext)
+        ));
+        assert!(child.derive_num(0u64).is_ok());
+    }
+
+    #[test]
+    fn test_allowed_builtins() {
+        use PathFragment::*;
+        let mut c = ctx(0).with_allowed_builtins(vec![Root, Action, Guard, Next]);
+        let mut action = c.derive(Action).unwrap();
+        assert!(matches!(
+            action.derive(Suggested),
+            Err(CompilationError::PathFragmentError(
+                ValidFragmentError::ForbiddenFragment(Suggested)
+            ))
+        ));
+        // names and branches are always allowed
+        let mut pay = action.derive_str(Arc::new("pay".into())).unwrap();
+        assert!(pay.derive_num(0u64).is_ok());
+        assert!(pay.resolve_path("./@next").is_ok());
+        assert!(matches!(
+            pay.resolve_path("../refund/@suggested"),
+            Err(CompilationError::PathFragmentError(
+                ValidFragmentError::ForbiddenFragment(Suggested)
+            ))
+        ));
+        assert!(ctx(0).derive(Suggested).is_ok());
+    }
+
+    #[test]
+    fn test_resolve_path() {
+        let mut c = ctx(0);
+        let mut action = c.derive(PathFragment::Action).unwrap();
+        let pay = action.derive_str(Arc::new("pay".into())).unwrap();
+        assert_eq!(
+            String::from(pay.resolve_path("../refund/@suggested").unwrap()),
+            "@root/@action/refund/@suggested"
+        );
+        assert_eq!(
+            String::from(pay.resolve_path("./#1").unwrap()),
+            "@root/@action/pay/#1"
+        );
+        assert!(matches!(
+            pay.resolve_path("../../.."),
+            Err(CompilationError::PathFragmentError(_))
+        ));
+    }
+
+    #[test]
+    fn test_resolve_keys_unknown_alias() {
+        let policy = Concrete::<String>::from_str("and(pk(alice),pk(bob))").unwrap();
+        let mut keys = BTreeMap::new();
+        keys.insert("alice".to_string(), key(1));
+        match ctx(0).resolve_keys(&policy, &keys) {
+            Err(CompilationError::UnknownKey { alias, path }) => {
+                assert_eq!(alias, "bob");
+                assert_eq!(String::from(path), "@root");
+            }
+            r => panic!("expected UnknownKey, got {:?}", r),
+        }
+        keys.insert("bob".to_string(), key(2));
+        assert_eq!(
+            ctx(0).resolve_keys(&policy, &keys).unwrap(),
+            sapio_base::Clause::And(vec![
+                sapio_base::Clause::Key(key(1)),
+                sapio_base::Clause::Key(key(2))
+            ])
+        );
+    }
+}
@@ -7,7 +7,9 @@
 //! error types that can be returned from Sapio.
 //! Where possible, concrete error types are wrapped, but in order to handle
 //! errors created by the user we allow boxing an error trait.
+use crate::contract::context::ScriptContext;
 use crate::contract::object::ObjectError;
+use bitcoin::util::amount::Denomination;
 use sapio_base::effects::EffectDBError;
 use sapio_base::effects::EffectPath;
 use sapio_base::effects::ValidFragmentError;
@@ -17,7 +19,12 @@ use sapio_ctv_emulator_trait::EmulatorError;
 use std::collections::LinkedList;
 use std::error::Error;
 use std::fmt;
-type ErrT = Box<dyn std::error::Error>;
+type ErrT = Box<dyn std::error::Error + Send + Sync>;
+
+/// formats an amount in `unit`
+fn display_amount(a: &bitcoin::Amount, unit: Denomination) -> String {
+    format!("{} {}", a.to_string_in(unit), unit)
+}
 /// Sapio's core error type.
 #[derive(Debug)]
 pub enum CompilationError {
@@ -31,10 +38,19 @@ pub enum CompilationError {
     TerminateWith(String),
     /// Don't Overwrite Metadata
     OverwriteMetadata(String),
-    /// Fee Specification Error
-    MinFeerateError,
+    /// Fee Specification Error: a template pays `fees` where the minimum
+    /// feerate requires at least `required`.
+    MinFeerateError {
+        /// the fees the template actually pays
+        fees: bitcoin::Amount,
+        /// the fees the minimum feerate requires
+        required: bitcoin::Amount,
+    },
     /// Error when ContextPath has already been used.
     ContexPathAlreadyDerived,
+    /// Error when deriving a path from a context after
+    /// [`crate::contract::Context::freeze`]
+    FrozenContext,
     /// Error when ContextPath attempted
     InvalidPathName,
     /// Other Error for Fragment Format
@@ -44,7 +60,12 @@ pub enum CompilationError {
     /// Error if a Policy is empty
     EmptyPolicy,
     /// Error if a contract does not have sufficient funds available
-    OutOfFunds,
+    OutOfFunds {
+        /// the funds the context has available
+        available: bitcoin::Amount,
+        /// the funds the contract attempted to use
+        required: bitcoin::Amount,
+    },
     /// Error if a CheckSequenceVerify clause is incompatible with the sequence already set.
     /// E.g., blocks and time
     IncompatibleSequence,
@@ -107,9 +128,155 @@ pub enum CompilationError {
     /// No Web API enabled, but call_json was called
     WebAPIDisabled,
     /// Unknown Error type -- either from a user or from some unhandled dependency
-    Custom(Box<dyn std::error::Error>),
+    Custom(ErrT),
     /// Error in continuation argument coercion
     ContinuationCoercion(String),
+    /// A policy referenced a key alias that was not provided
+    UnknownKey {
+        /// the alias that could not be resolved
+        alias: String,
+        /// where the alias was being resolved
+        path: EffectPath,
+    },
+    /// Error parsing JSON, e.g. embedded contract parameters
+    Json(serde_json::Error),
+    /// A policy uses something that the target script context can not express
+    /// (e.g. a script too large for P2SH)
+    Unsupported {
+        /// what was used
+        feature: String,
+        /// the context it is not supported in
+        context: ScriptContext,
+    },
+    /// Distinct branches produced templates with the same CTV hash, which
+    /// is usually a copy-paste mistake
+    DuplicateTemplate {
+        /// the colliding template hash
+        hash: bitcoin::hashes::sha256::Hash,
+        /// the paths of the colliding templates
+        paths: Vec<EffectPath>,
+    },
+    /// Error parsing an address
+    AddressError(bitcoin::util::address::Error),
+    /// The guards on a branch contradict each other, so it can never be taken
+    GuardConflict {
+        /// where the guards were combined
+        path: EffectPath,
+        /// why they conflict
+        detail: String,
+    },
+    /// A branch can never be taken
+    UnreachableBranch,
+    /// The guards on a branch reduce to a policy that can never be
+    /// satisfied, so any output it produced could never be spent
+    UnspendableBranch {
+        /// the branch
+        path: EffectPath,
+    },
+    /// An `Object` does not know the path of one of its templates, e.g. one
+    /// deserialized from a format written without the
+    /// `serde-introspection` feature, so a query by path can not be answered
+    UnknownTemplatePath {
+        /// the template's CTV hash
+        hash: bitcoin::hashes::sha256::Hash,
+    },
+    /// A contract rejected one of its parameters
+    /// (see [`crate::contract::Context::reject_arg`])
+    InvalidArgument {
+        /// the parameter that was rejected
+        field: String,
+        /// why it was rejected
+        reason: String,
+        /// where it was rejected
+        path: EffectPath,
+    },
+    /// A guard could not produce its clause
+    GuardFailed {
+        /// the guard's path
+        path: EffectPath,
+        /// why the guard failed
+        error: Box<CompilationError>,
+    },
+    /// A contract (transitively) contains itself with the same funds, so
+    /// compiling it would never finish
+    CyclicDependency {
+        /// the paths the contract was being compiled at, outermost first,
+        /// ending where it was reached again
+        cycle: Vec<EffectPath>,
+    },
+    /// A compiled object's JSON serialization exceeds the size allowed by
+    /// [`crate::contract::Context::with_max_serialized_bytes`]
+    OutputTooLarge {
+        /// the size of the serialization
+        bytes: usize,
+        /// the largest size allowed
+        max: usize,
+    },
+    /// Error parsing an integer, e.g. a contract parameter given as a string
+    IntParse(std::num::ParseIntError),
+    /// Error parsing hex (or a hash from hex), e.g. a hashlock's preimage
+    /// given as a contract parameter
+    HexParse(HexParseError),
+    /// A compiled script exceeds the script size limit (see
+    /// [`crate::contract::compiler::MAX_SCRIPT_SIZE`])
+    ScriptTooLarge {
+        /// the size of the script
+        bytes: usize,
+        /// the largest size allowed
+        limit: usize,
+        /// the branch the script is for
+        path: EffectPath,
+    },
+    /// A template's version, sequences or locktime are valid but
+    /// non-standard or ineffective (reported as a warning)
+    NonStandardTimelock {
+        /// the template's path
+        path: EffectPath,
+        /// what is non-standard
+        detail: String,
+    },
+    /// A template's transaction exceeds the consensus weight limit (see
+    /// [`crate::contract::compiler::MAX_TX_WEIGHT`]), so it can't be mined
+    TransactionTooHeavy {
+        /// the transaction's weight, not counting its witness
+        weight: usize,
+        /// the largest weight allowed
+        limit: usize,
+        /// the template's path
+        path: EffectPath,
+    },
+    /// Error if a path selects a `PathFragment::Branch` beyond those that
+    /// were actually produced beneath its parent
+    BranchIndexOutOfRange {
+        /// the branch index selected
+        requested: u64,
+        /// how many branches exist beneath the parent
+        available: u64,
+        /// the parent path the branch was selected from
+        path: EffectPath,
+    },
+    /// Error if the JSON arguments to a function do not match its schema,
+    /// see [`crate::util::json_schema::validate`]
+    SchemaValidationFailed {
+        /// a message for each violation, e.g. `field amount must be at least 0`
+        errors: Vec<String>,
+    },
+    /// Several independent failures, see [`MultipleErrors`]
+    Multiple(Vec<CompilationError>),
+    /// A contract relies on CTV, but the context has no emulator for it
+    /// (see [`sapio_ctv_emulator_trait::CTVUnavailable`])
+    EmulatorRequired,
+    /// An error annotated with where it occurred, see
+    /// [`CompilationError::in_context`]. The compiler wraps a failure to
+    /// compile an output's contract in the path of that output, unless the
+    /// failure carries a path of its own.
+    InContext {
+        /// where the error occurred, relative to any enclosing `InContext`
+        /// (unless it begins with `@root`)
+        path: EffectPath,
+        /// the error
+        error: Box<CompilationError>,
+    },
 }
 
 impl From<SIMPError> for CompilationError {
@@ -136,9 +303,219 @@ impl From<std::convert::Infallible> for CompilationError {
 
 impl CompilationError {
     /// Create a custom compilation error instance
-    pub fn custom<E: std::error::Error + 'static>(e: E) -> Self {
+    pub fn custom<E: std::error::Error + Send + Sync + 'static>(e: E) -> Self {
         CompilationError::Custom(Box::new(e))
     }
+
+    /// Start building an error with a path and message, see
+    /// [`CompilationErrorBuilder`]
+    pub fn builder() -> CompilationErrorBuilder {
+        CompilationErrorBuilder::default()
+    }
+
+    /// Annotate this error with the path it occurred at
+    pub fn in_context(self, path: EffectPath) -> Self {
+        CompilationError::InContext {
+            path,
+            error: Box::new(self),
+        }
+    }
+
+    /// Annotate this error with `path` (e.g. that of the output whose
+    /// contract failed to compile), unless it already says where it
+    /// occurred. The compiler does not do this itself, so errors keep their
+    /// variant unless the caller opts in.
+    pub fn at(self, path: &EffectPath) -> Self {
+        if self.own_path().is_some() || matches!(self, CompilationError::InContext { .. }) {
+            self
+        } else {
+            self.in_context(path.clone())
+        }
+    }
+
+    /// Collapse any nesting of [`CompilationError::InContext`] into the
+    /// innermost error and the path accumulated through every layer. Errors
+    /// without context have an empty path.
+    pub fn flatten(self) -> (EffectPath, CompilationError) {
+        let (path, _) = self.flatten_ref();
+        let mut error = self;
+        while let CompilationError::InContext { error: e, .. } = error {
+            error = *e;
+        }
+        (path, error)
+    }
+
+    /// [`CompilationError::flatten`], borrowing the innermost error
+    pub fn flatten_ref(&self) -> (EffectPath, &CompilationError) {
+        let mut path = EffectPath::empty();
+        let mut error = self;
+        while let CompilationError::InContext { path: p, error: e } = error {
+            path = if p.is_rooted() {
+                p.clone()
+            } else {
+                path.join(p).unwrap_or_else(|_| p.clone())
+            };
+            error = e;
+        }
+        (path, error)
+    }
+
+    /// the path this error carries in its own fields, if any
+    fn own_path(&self) -> Option<&EffectPath> {
+        match self {
+            CompilationError::UnknownKey { path, .. }
+            | CompilationError::GuardConflict { path, .. }
+            | CompilationError::UnspendableBranch { path }
+            | CompilationError::InvalidArgument { path, .. }
+            | CompilationError::GuardFailed { path, .. }
+            | CompilationError::ScriptTooLarge { path, .. }
+            | CompilationError::NonStandardTimelock { path, .. }
+            | CompilationError::TransactionTooHeavy { path, .. }
+            | CompilationError::BranchIndexOutOfRange { path, .. } => Some(path),
+            // the first template found with the hash
+            CompilationError::DuplicateTemplate { paths, .. } => paths.first(),
+            // where the contract recurred
+            CompilationError::CyclicDependency { cycle } => cycle.last(),
+            _ => None,
+        }
+    }
+
+    /// A fixed message for each variant, omitting any dynamic detail.
+    /// Useful for logging in environments where formatting (and allocating)
+    /// the full `Display` output is not possible.
+    pub fn static_message(&self) -> &'static str {
+        match self {
+            CompilationError::AdditionalGuardsNotAllowedHere => {
+                "additional guards are not allowed in a continuation"
+            }
+            CompilationError::TerminateCompilation => "compilation terminated",
+            CompilationError::TerminateWith(_) => "compilation terminated with a message",
+            CompilationError::OverwriteMetadata(_) => "metadata may not be overwritten",
+            CompilationError::MinFeerateError { .. } => "minimum feerate not satisfied",
+            CompilationError::ContexPathAlreadyDerived => "context path already derived",
+            CompilationError::FrozenContext => "context is frozen",
+            CompilationError::InvalidPathName => "invalid context path name",
+            CompilationError::PathFragmentError(_) => "invalid path fragment",
+            CompilationError::MissingTemplates => "no templates returned",
+            CompilationError::EmptyPolicy => "policy is empty",
+            CompilationError::OutOfFunds { .. } => "out of funds",
+            CompilationError::IncompatibleSequence => "incompatible sequence",
+            CompilationError::IncompatibleLockTime => "incompatible lock time",
+            CompilationError::NoSuchSequence => "no such sequence",
+            CompilationError::ParseAmountError(_) => "could not parse amount",
+            CompilationError::Miniscript(_) => "miniscript policy compiler error",
+            CompilationError::MiniscriptE(_) => "miniscript error",
+            CompilationError::TimeLockError(_) => "timelock error",
+            CompilationError::CompiledObjectError(_) => "error creating compiled object",
+            CompilationError::ConditionalCompilationFailed(_) => "conditional compilation failed",
+            CompilationError::EffectDBError(_) => "effect database error",
+            CompilationError::SIMPError(_) => "SIMP error",
+            CompilationError::UnknownModule => "unknown module",
+            CompilationError::InvalidModule => "invalid module",
+            CompilationError::InternalModuleError(_) => "internal module error",
+            CompilationError::ModuleFailedToGetMemory(_) => "module failed to get memory",
+            CompilationError::ModuleCouldNotAllocateError(_, _) => "module could not allocate",
+            CompilationError::ModuleCouldNotFindFunction(_) => "module could not find function",
+            CompilationError::ModuleCouldNotDeallocate(_, _) => "module could not deallocate",
+            CompilationError::ModuleCouldNotCreateContract(_, _, _) => {
+                "module could not create contract"
+            }
+            CompilationError::ModuleCouldNotGetAPI(_) => "module could not get api",
+            CompilationError::ModuleCouldNotGetLogo(_) => "module could not get logo",
+            CompilationError::ModuleCouldNotGetName(_) => "module could not get name",
+            CompilationError::ModuleRuntimeError(_) => "module runtime error",
+            CompilationError::ModuleFailedAPICheck(_) => "module failed api check",
+            CompilationError::ModuleCompilationErrorUnsendable(_) => {
+                "module compilation error (unsendable)"
+            }
+            CompilationError::SerializationError(_) => "serialization error",
+            CompilationError::DeserializationError(_) => "deserialization error",
+            CompilationError::WebAPIDisabled => "web api disabled",
+            CompilationError::Custom(_) => "custom error",
+            CompilationError::ContinuationCoercion(_) => "continuation argument coercion failed",
+            CompilationError::UnknownKey { .. } => "unknown key alias",
+            CompilationError::Json(_) => "json error",
+            CompilationError::Unsupported { .. } => "unsupported in script context",
+            CompilationError::DuplicateTemplate { .. } => "duplicate template",
+            CompilationError::AddressError(_) => "could not parse address",
+            CompilationError::GuardConflict { .. } => "guards conflict",
+            CompilationError::UnreachableBranch => "unreachable branch",
+            CompilationError::UnspendableBranch { .. } => "unspendable branch",
+            CompilationError::UnknownTemplatePath { .. } => "unknown template path",
+            CompilationError::InvalidArgument { .. } => "invalid contract argument",
+            CompilationError::GuardFailed { .. } => "guard failed",
+            CompilationError::CyclicDependency { .. } => "cyclic dependency",
+            CompilationError::OutputTooLarge { .. } => "compiled output too large",
+            CompilationError::IntParse(_) => "could not parse integer",
+            CompilationError::HexParse(_) => "could not parse hex",
+            CompilationError::ScriptTooLarge { .. } => "script too large",
+            CompilationError::NonStandardTimelock { .. } => "non-standard timelock",
+            CompilationError::TransactionTooHeavy { .. } => "transaction too heavy",
+            CompilationError::BranchIndexOutOfRange { .. } => "branch index out of range",
+            CompilationError::SchemaValidationFailed { .. } => "arguments do not match schema",
+            CompilationError::Multiple(_) => "multiple errors",
+            CompilationError::EmulatorRequired => "ctv emulator required",
+            CompilationError::InContext { .. } => "error in context",
+        }
+    }
+
+    /// Key-value pairs describing this error for structured loggers, so log
+    /// pipelines can index them rather than parse the `Display` output.
+    ///
+    /// Fields are `code` (the innermost error's
+    /// [`CompilationError::static_message`]), `path` (from any
+    /// [`CompilationError::InContext`] layers, else the error's own path),
+    /// any amounts (in sats) or sizes carried by the error, and `message`
+    /// (the innermost error's `Display`).
+    pub fn log_fields(&self) -> Vec<(&'static str, String)> {
+        self.log_fields_in(Denomination::Bitcoin)
+    }
+
+    /// [`CompilationError::log_fields`] with `message` showing amounts in
+    /// `unit`; the `_sats` fields are always in sats.
+    pub fn log_fields_in(&self, unit: Denomination) -> Vec<(&'static str, String)> {
+        let (path, error) = self.flatten_ref();
+        let mut fields = vec![("code", error.static_message().to_string())];
+        if !path.is_empty() {
+            fields.push(("path", String::from(path)));
+        } else if let Some(p) = error.own_path() {
+            fields.push(("path", String::from(p.clone())));
+        }
+        let sats = |a: &bitcoin::Amount| a.as_sat().to_string();
+        match error {
+            CompilationError::OutOfFunds {
+                available,
+                required,
+            } => {
+                fields.push(("available_sats", sats(available)));
+                fields.push(("required_sats", sats(required)));
+            }
+            CompilationError::MinFeerateError { fees, required } => {
+                fields.push(("fees_sats", sats(fees)));
+                fields.push(("required_sats", sats(required)));
+            }
+            CompilationError::OutputTooLarge { bytes, max } => {
+                fields.push(("bytes", bytes.to_string()));
+                fields.push(("limit", max.to_string()));
+            }
+            CompilationError::ScriptTooLarge { bytes, limit, .. } => {
+                fields.push(("bytes", bytes.to_string()));
+                fields.push(("limit", limit.to_string()));
+            }
+            _ => {}
+        }
+        fields.push(("message", error.display_in(unit).to_string()));
+        fields
+    }
+
+    /// The underlying amount parsing error, if this error was caused by one,
+    /// so callers can branch on the kind of failure.
+    pub fn amount_error(&self) -> Option<&bitcoin::util::amount::ParseAmountError> {
+        match self {
+            CompilationError::ParseAmountError(e) => Some(e),
+            _ => None,
+        }
+    }
 }
 
 impl From<bitcoin::util::amount::ParseAmountError> for CompilationError {
@@ -162,22 +539,1107 @@ impl From<miniscript::Error> for CompilationError {
         CompilationError::MiniscriptE(v)
     }
 }
+impl From<bitcoin::util::address::Error> for CompilationError {
+    fn from(e: bitcoin::util::address::Error) -> Self {
+        CompilationError::AddressError(e)
+    }
+}
+impl From<std::num::ParseIntError> for CompilationError {
+    fn from(e: std::num::ParseIntError) -> Self {
+        CompilationError::IntParse(e)
+    }
+}
+
+/// The hex parsing errors folded into [`CompilationError::HexParse`]
+#[derive(Debug)]
+pub enum HexParseError {
+    /// from the `hex` crate, e.g. `hex::decode`
+    Hex(hex::FromHexError),
+    /// from parsing a hash (e.g. `sha256::Hash::from_str`)
+    Hash(bitcoin::hashes::hex::Error),
+    /// from building a hash out of decoded bytes of the wrong length
+    Length(bitcoin::hashes::Error),
+}
+impl fmt::Display for HexParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HexParseError::Hex(e) => e.fmt(f),
+            HexParseError::Hash(e) => e.fmt(f),
+            HexParseError::Length(e) => e.fmt(f),
+        }
+    }
+}
+impl Error for HexParseError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            HexParseError::Hex(e) => Some(e),
+            HexParseError::Hash(e) => Some(e),
+            HexParseError::Length(e) => Some(e),
+        }
+    }
+}
+impl From<hex::FromHexError> for CompilationError {
+    fn from(e: hex::FromHexError) -> Self {
+        CompilationError::HexParse(HexParseError::Hex(e))
+    }
+}
+impl From<bitcoin::hashes::hex::Error> for CompilationError {
+    fn from(e: bitcoin::hashes::hex::Error) -> Self {
+        CompilationError::HexParse(HexParseError::Hash(e))
+    }
+}
+impl From<bitcoin::hashes::Error> for CompilationError {
+    fn from(e: bitcoin::hashes::Error) -> Self {
+        CompilationError::HexParse(HexParseError::Length(e))
+    }
+}
+impl From<serde_json::Error> for CompilationError {
+    fn from(e: serde_json::Error) -> Self {
+        CompilationError::Json(e)
+    }
+}
 impl From<ObjectError> for CompilationError {
     fn from(e: ObjectError) -> Self {
         CompilationError::CompiledObjectError(e)
     }
 }
 
+/// Displays a [`CompilationError`] with its amounts in a chosen unit, see
+/// [`CompilationError::display_in`]
+pub struct DisplayIn<'a> {
+    error: &'a CompilationError,
+    unit: Denomination,
+}
+
+impl fmt::Display for DisplayIn<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.error.fmt_in(f, self.unit)
+    }
+}
+
 impl fmt::Display for CompilationError {
+    /// amounts (e.g. those of [`CompilationError::OutOfFunds`]) are shown in
+    /// BTC, see [`CompilationError::display_in`] for other units
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_in(f, Denomination::Bitcoin)
+    }
+}
+
+impl CompilationError {
+    /// Display this error with its amounts (e.g. those of
+    /// [`CompilationError::OutOfFunds`]) in `unit` rather than BTC, e.g.
+    /// `e.display_in(Denomination::Satoshi).to_string()`.
+    pub fn display_in(&self, unit: Denomination) -> DisplayIn<'_> {
+        DisplayIn { error: self, unit }
+    }
+
+    fn fmt_in(&self, f: &mut fmt::Formatter<'_>, unit: Denomination) -> fmt::Result {
+        match self {
+            CompilationError::OutOfFunds {
+                available,
+                required,
+            } => write!(
+                f,
+                "out of funds: required {} but only {} available",
+                display_amount(required, unit),
+                display_amount(available, unit)
+            ),
+            CompilationError::MinFeerateError { fees, required } => write!(
+                f,
+                "minimum feerate not satisfied: pays {} in fees but requires {}",
+                display_amount(fees, unit),
+                display_amount(required, unit)
+            ),
+            CompilationError::InvalidArgument {
+                field,
+                reason,
+                path,
+            } => write!(
+                f,
+                "invalid argument `{}` at {}: {}",
+                field,
+                String::from(path.clone()),
+                reason
+            ),
+            CompilationError::GuardFailed { path, error } => {
+                write!(
+                    f,
+                    "guard at {} failed: {}",
+                    String::from(path.clone()),
+                    error.display_in(unit)
+                )
+            }
+            CompilationError::ScriptTooLarge { bytes, limit, path } => write!(
+                f,
+                "script for {} is {} bytes, over the {} byte limit",
+                String::from(path.clone()),
+                bytes,
+                limit
+            ),
+            CompilationError::NonStandardTimelock { path, detail } => write!(
+                f,
+                "non-standard timelock at {}: {}",
+                String::from(path.clone()),
+                detail
+            ),
+            CompilationError::TransactionTooHeavy {
+                weight,
+                limit,
+                path,
+            } => write!(
+                f,
+                "transaction for {} weighs at least {} WU, over the {} WU limit",
+                String::from(path.clone()),
+                weight,
+                limit
+            ),
+            CompilationError::HexParse(e) => write!(f, "could not parse hex: {}", e),
+            CompilationError::BranchIndexOutOfRange {
+                requested,
+                available,
+                path,
+            } => write!(
+                f,
+                "branch #{} selected under {}, which only has {} branches",
+                requested,
+                String::from(path.clone()),
+                available
+            ),
+            CompilationError::SchemaValidationFailed { errors } => {
+                write!(f, "arguments do not match schema: {}", errors.join("; "))
+            }
+            CompilationError::UnspendableBranch { path } => write!(
+                f,
+                "the guards of {} can never be satisfied",
+                String::from(path.clone())
+            ),
+            CompilationError::UnknownTemplatePath { hash } => {
+                write!(f, "the path of template {} is not known", hash)
+            }
+            CompilationError::UnknownKey { alias, path } => write!(
+                f,
+                "unknown key alias `{}` at {}",
+                alias,
+                String::from(path.clone())
+            ),
+            CompilationError::Unsupported { feature, context } => {
+                write!(f, "not supported in {:?} scripts: {}", context, feature)
+            }
+            CompilationError::DuplicateTemplate { hash, paths } => write!(
+                f,
+                "template {} is produced by more than one branch: {}",
+                hash,
+                paths
+                    .iter()
+                    .map(|p| String::from(p.clone()))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            CompilationError::GuardConflict { path, detail } => write!(
+                f,
+                "guards at {} conflict: {}",
+                String::from(path.clone()),
+                detail
+            ),
+            CompilationError::CyclicDependency { cycle } => write!(
+                f,
+                "cyclic dependency: {}",
+                cycle
+                    .iter()
+                    .map(|p| String::from(p.clone()))
+                    .collect::<Vec<_>>()
+                    .join(" -> ")
+            ),
+            CompilationError::OutputTooLarge { bytes, max } => write!(
+                f,
+                "compiled output is {} bytes, over the {} byte limit",
+                bytes, max
+            ),
+            CompilationError::FrozenContext => {
+                write!(
+                    f,
+                    "context is frozen, so no more paths can be derived from it"
+                )
+            }
+            CompilationError::EmulatorRequired => write!(
+                f,
+                "the contract requires CTV, but the context has no emulator for it"
+            ),
+            CompilationError::IntParse(e) => write!(f, "could not parse integer: {}", e),
+            CompilationError::Json(e) => write!(f, "json error: {}", e),
+            CompilationError::AddressError(e) => write!(f, "could not parse address: {}", e),
+            CompilationError::Multiple(errors) => {
+                write!(f, "{} errors:", errors.len())?;
+                for (i, e) in errors.iter().enumerate() {
+                    write!(f, "\n  {}. {}", i + 1, e.display_in(unit))?;
+                }
+                Ok(())
+            }
+            CompilationError::InContext { path, error } => {
+                write!(
+                    f,
+                    "at {}: {}",
+                    String::from(path.clone()),
+                    error.display_in(unit)
+                )
+            }
+            _ => write!(f, "{:?}", self),
+        }
+    }
+}
+
+impl Error for CompilationError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CompilationError::ParseAmountError(e) => Some(e),
+            CompilationError::Json(e) => Some(e),
+            CompilationError::AddressError(e) => Some(e),
+            CompilationError::IntParse(e) => Some(e),
+            CompilationError::HexParse(e) => e.source(),
+            CompilationError::GuardFailed { error, .. } => Some(error.as_ref()),
+            CompilationError::InContext { error, .. } => Some(error.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+/// # gRPC Status Code
+/// The canonical gRPC status codes (with the same discriminants as
+/// `tonic::Code`), without depending on a gRPC library.
+#[cfg(feature = "grpc")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum GrpcCode {
+    /// The operation completed successfully
+    Ok = 0,
+    /// The operation was cancelled
+    Cancelled = 1,
+    /// Unknown error
+    Unknown = 2,
+    /// Client specified an invalid argument
+    InvalidArgument = 3,
+    /// Deadline expired before operation could complete
+    DeadlineExceeded = 4,
+    /// Some requested entity was not found
+    NotFound = 5,
+    /// Some entity that we attempted to create already exists
+    AlreadyExists = 6,
+    /// The caller does not have permission to execute the specified operation
+    PermissionDenied = 7,
+    /// Some resource has been exhausted
+    ResourceExhausted = 8,
+    /// The system is not in a state required for the operation's execution
+    FailedPrecondition = 9,
+    /// The operation was aborted
+    Aborted = 10,
+    /// Operation was attempted past the valid range
+    OutOfRange = 11,
+    /// Operation is not implemented or not supported
+    Unimplemented = 12,
+    /// Internal error
+    Internal = 13,
+    /// The service is currently unavailable
+    Unavailable = 14,
+    /// Unrecoverable data loss or corruption
+    DataLoss = 15,
+    /// The request does not have valid authentication credentials
+    Unauthenticated = 16,
+}
+
+#[cfg(feature = "grpc")]
+impl CompilationError {
+    /// The gRPC status a service should report this error with. Errors
+    /// wrapping another (e.g. [`CompilationError::InContext`]) report the
+    /// wrapped error's code, and [`CompilationError::Multiple`] the first's.
+    pub fn grpc_code(&self) -> GrpcCode {
+        match self {
+            CompilationError::InContext { error, .. }
+            | CompilationError::GuardFailed { error, .. } => error.grpc_code(),
+            CompilationError::Multiple(errors) => errors
+                .first()
+                .map(CompilationError::grpc_code)
+                .unwrap_or(GrpcCode::Internal),
+            CompilationError::OutOfFunds { .. }
+            | CompilationError::MinFeerateError { .. }
+            | CompilationError::EmulatorRequired
+            | CompilationError::WebAPIDisabled => GrpcCode::FailedPrecondition,
+            CompilationError::InvalidArgument { .. }
+            | CompilationError::UnknownKey { .. }
+            | CompilationError::ContinuationCoercion(_)
+            | CompilationError::ParseAmountError(_)
+            | CompilationError::IntParse(_)
+            | CompilationError::HexParse(_)
+            | CompilationError::SchemaValidationFailed { .. }
+            | CompilationError::AddressError(_)
+            | CompilationError::Json(_)
+            | CompilationError::DeserializationError(_)
+            | CompilationError::InvalidPathName
+            | CompilationError::PathFragmentError(_) => GrpcCode::InvalidArgument,
+            CompilationError::UnknownModule | CompilationError::ModuleCouldNotFindFunction(_) => {
+                GrpcCode::NotFound
+            }
+            CompilationError::Unsupported { .. } => GrpcCode::Unimplemented,
+            CompilationError::OutputTooLarge { .. } => GrpcCode::ResourceExhausted,
+            CompilationError::TerminateCompilation | CompilationError::TerminateWith(_) => {
+                GrpcCode::Aborted
+            }
+            CompilationError::Custom(_) => GrpcCode::Unknown,
+            _ => GrpcCode::Internal,
+        }
+    }
+}
+
+/// Collects independent failures (e.g. every under-funded output rather
+/// than just the first) into a single [`CompilationError::Multiple`].
+#[derive(Debug, Default)]
+pub struct MultipleErrors(Vec<CompilationError>);
+
+impl MultipleErrors {
+    /// an empty collection
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// record an error
+    pub fn push(&mut self, e: impl Into<CompilationError>) -> &mut Self {
+        self.0.push(e.into());
+        self
+    }
+    /// record the error (if any) of `r`, returning its value otherwise
+    pub fn check<T, E: Into<CompilationError>>(&mut self, r: Result<T, E>) -> Option<T> {
+        r.map_err(|e| {
+            self.push(e);
+        })
+        .ok()
+    }
+    /// have any errors been recorded?
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+    /// Ok if nothing was recorded, the error itself if only one was, and
+    /// [`CompilationError::Multiple`] otherwise
+    pub fn finish(mut self) -> Result<(), CompilationError> {
+        match self.0.len() {
+            0 => Ok(()),
+            1 => Err(self.0.remove(0)),
+            _ => Err(CompilationError::Multiple(self.0)),
+        }
+    }
+}
+
+/// Put errors collected from many places (e.g. by
+/// [`crate::contract::object::Object::check_all_branches_fundable`]) into a
+/// canonical order, so that reports of the same failures are identical from
+/// run to run. Errors are ordered by path, then by the innermost error's
+/// [`CompilationError::static_message`], then by their `Display`.
+pub fn sort_errors(errors: &mut [(EffectPath, CompilationError)]) {
+    errors.sort_by_cached_key(|(path, e)| {
+        (
+            path.clone(),
+            e.flatten_ref().1.static_message(),
+            e.to_string(),
+        )
+    })
+}
+
+#[cfg(feature = "anyhow")]
+impl CompilationError {
+    /// Convert into an [`anyhow::Error`] with one `at <path>` context layer
+    /// per [`CompilationError::InContext`] layer of this error, outermost
+    /// first, above the underlying error and its sources. `{}` shows the
+    /// outermost path and `{:#}` the whole chain.
+    pub fn context_chain(self) -> anyhow::Error {
+        let mut paths = vec![];
+        let mut error = self;
+        while let CompilationError::InContext { path, error: inner } = error {
+            paths.push(path);
+            error = *inner;
+        }
+        paths
+            .into_iter()
+            .rev()
+            .fold(anyhow::Error::new(error), |e, path| {
+                e.context(format!("at {}", String::from(path)))
+            })
+    }
+}
+
+/// The kind of error a [`CompilationErrorBuilder`] produces
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ErrorKind {
+    /// [`CompilationError::Custom`], wrapped in the builder's path if set
+    #[default]
+    Custom,
+    /// [`CompilationError::TerminateWith`], wrapped in the builder's path if
+    /// set
+    Terminate,
+    /// [`CompilationError::InvalidArgument`] for the builder's field
+    InvalidArgument,
+    /// [`CompilationError::GuardConflict`]
+    GuardConflict,
+}
+
+/// A message only error, for [`ErrorKind::Custom`]
+#[derive(Debug)]
+struct Message(String);
+impl fmt::Display for Message {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{:?}", self)
+        f.write_str(&self.0)
     }
 }
+impl Error for Message {}
+
+/// Fluent construction of a [`CompilationError`] carrying a path and
+/// message, see [`CompilationError::builder`].
+///
+/// ```
+/// use sapio::contract::error::ErrorKind;
+/// use sapio::contract::CompilationError;
+/// use sapio_base::effects::EffectPath;
+/// use std::convert::TryFrom;
+///
+/// let e = CompilationError::builder()
+///     .path(EffectPath::try_from("@root/@action/pay").unwrap())
+///     .field("amount")
+///     .message("must be positive")
+///     .kind(ErrorKind::InvalidArgument)
+///     .build();
+/// assert_eq!(
+///     e.to_string(),
+///     "invalid argument `amount` at @root/@action/pay: must be positive"
+/// );
+/// ```
+#[derive(Debug, Default)]
+pub struct CompilationErrorBuilder {
+    kind: ErrorKind,
+    path: Option<EffectPath>,
+    field: Option<String>,
+    message: String,
+}
 
-impl Error for CompilationError {}
+impl CompilationErrorBuilder {
+    /// where the error occurred
+    pub fn path(mut self, path: EffectPath) -> Self {
+        self.path = Some(path);
+        self
+    }
+    /// what went wrong
+    pub fn message(mut self, message: impl Into<String>) -> Self {
+        self.message = message.into();
+        self
+    }
+    /// which error to build, [`ErrorKind::Custom`] by default
+    pub fn kind(mut self, kind: ErrorKind) -> Self {
+        self.kind = kind;
+        self
+    }
+    /// the rejected parameter, for [`ErrorKind::InvalidArgument`]
+    pub fn field(mut self, field: impl Into<String>) -> Self {
+        self.field = Some(field.into());
+        self
+    }
+    /// Build the error. Kinds without a path of their own are wrapped in
+    /// [`CompilationError::InContext`] if a path was given.
+    pub fn build(self) -> CompilationError {
+        let wrap = |e: CompilationError, path: Option<EffectPath>| match path {
+            Some(p) => e.in_context(p),
+            None => e,
+        };
+        match self.kind {
+            ErrorKind::Custom => wrap(CompilationError::custom(Message(self.message)), self.path),
+            ErrorKind::Terminate => wrap(CompilationError::TerminateWith(self.message), self.path),
+            ErrorKind::InvalidArgument => CompilationError::InvalidArgument {
+                field: self.field.unwrap_or_default(),
+                reason: self.message,
+                path: self.path.unwrap_or_default(),
+            },
+            ErrorKind::GuardConflict => CompilationError::GuardConflict {
+                path: self.path.unwrap_or_default(),
+                detail: self.message,
+            },
+        }
+    }
+}
 
 impl From<EmulatorError> for CompilationError {
     fn from(e: EmulatorError) -> Self {
-        CompilationError::Custom(Box::new(e))
+        match e {
+            EmulatorError::NoEmulator => CompilationError::EmulatorRequired,
+            e => CompilationError::Custom(Box::new(e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use sapio_base::effects::PathFragment;
+    use sapio_base::plugin_args::ContextualArguments;
+    use std::convert::TryFrom;
+
+    fn json_error() -> serde_json::Error {
+        serde_json::from_str::<()>("not json").unwrap_err()
+    }
+
+    #[test]
+    fn test_sort_errors() {
+        let p = |s: &str| EffectPath::try_from(s).unwrap();
+        let funds = |n| CompilationError::OutOfFunds {
+            available: bitcoin::Amount::from_sat(n),
+            required: bitcoin::Amount::from_sat(10),
+        };
+        let errors = || {
+            vec![
+                (p("@root/b/c"), CompilationError::MissingTemplates),
+                (p("@root/a"), funds(2)),
+                (p("@root/b"), CompilationError::EmptyPolicy),
+                (p("@root/a"), CompilationError::EmptyPolicy),
+                (
+                    p("@root/b"),
+                    CompilationError::EmptyPolicy.in_context(p("@root/b/c")),
+                ),
+                (p("@root/a"), funds(1)),
+            ]
+        };
+        let show = |v: &[(EffectPath, CompilationError)]| {
+            v.iter()
+                .map(|(p, e)| format!("{} {}", String::from(p.clone()), e))
+                .collect::<Vec<_>>()
+        };
+        let mut expected = errors();
+        sort_errors(&mut expected);
+        assert_eq!(
+            expected
+                .iter()
+                .map(|(p, e)| (String::from(p.clone()), e.static_message()))
+                .collect::<Vec<_>>()[..4],
+            [
+                ("@root/a".into(), funds(1).static_message()),
+                ("@root/a".into(), funds(1).static_message()),
+                (
+                    "@root/a".into(),
+                    CompilationError::EmptyPolicy.static_message()
+                ),
+                (
+                    "@root/b".into(),
+                    CompilationError::EmptyPolicy.static_message()
+                ),
+            ]
+        );
+        assert!(show(&expected)[0] < show(&expected)[1]);
+        for shift in 0..expected.len() {
+            let mut shuffled = errors();
+            shuffled.rotate_left(shift);
+            if shift % 2 == 1 {
+                shuffled.reverse();
+            }
+            sort_errors(&mut shuffled);
+            assert_eq!(show(&shuffled), show(&expected));
+        }
+    }
+
+    /// one instance of every `CompilationError` variant
+    fn all_variants() -> Vec<CompilationError> {
+        vec![
+            CompilationError::AdditionalGuardsNotAllowedHere,
+            CompilationError::TerminateCompilation,
+            CompilationError::TerminateWith("msg".into()),
+            CompilationError::OverwriteMetadata("color".into()),
+            CompilationError::MinFeerateError {
+                fees: bitcoin::Amount::from_sat(1),
+                required: bitcoin::Amount::from_sat(2),
+            },
+            CompilationError::ContexPathAlreadyDerived,
+            CompilationError::FrozenContext,
+            CompilationError::InvalidPathName,
+            CompilationError::PathFragmentError(ValidFragmentError::BranchParseError),
+            CompilationError::MissingTemplates,
+            CompilationError::EmptyPolicy,
+            CompilationError::OutOfFunds {
+                available: bitcoin::Amount::from_sat(1),
+                required: bitcoin::Amount::from_sat(2),
+            },
+            CompilationError::IncompatibleSequence,
+            CompilationError::IncompatibleLockTime,
+            CompilationError::NoSuchSequence,
+            CompilationError::ParseAmountError(bitcoin::util::amount::ParseAmountError::Negative),
+            CompilationError::Miniscript(
+                miniscript::policy::compiler::CompilerError::TopLevelNonSafe,
+            ),
+            CompilationError::MiniscriptE(miniscript::Error::InvalidPush(vec![])),
+            CompilationError::TimeLockError(sapio_base::timelocks::LockTimeError::HeightTooHigh(0)),
+            CompilationError::CompiledObjectError(ObjectError::OpReturnTooLong),
+            CompilationError::ConditionalCompilationFailed(LinkedList::new()),
+            CompilationError::EffectDBError(EffectDBError::SerializationError(json_error())),
+            CompilationError::SIMPError(SIMPError::SerializationError(json_error())),
+            CompilationError::UnknownModule,
+            CompilationError::InvalidModule,
+            CompilationError::InternalModuleError("msg".into()),
+            CompilationError::ModuleFailedToGetMemory("msg".into()),
+            CompilationError::ModuleCouldNotAllocateError(0, "msg".into()),
+            CompilationError::ModuleCouldNotFindFunction("f".into()),
+            CompilationError::ModuleCouldNotDeallocate(0, "msg".into()),
+            CompilationError::ModuleCouldNotCreateContract(
+                EffectPath::from(PathFragment::Root),
+                CreateArgs {
+                    arguments: serde_json::Value::Null,
+                    context: ContextualArguments {
+                        network: bitcoin::Network::Regtest,
+                        amount: bitcoin::Amount::from_sat(0),
+                        effects: Default::default(),
+                    },
+                },
+                "msg".into(),
+            ),
+            CompilationError::ModuleCouldNotGetAPI("msg".into()),
+            CompilationError::ModuleCouldNotGetLogo("msg".into()),
+            CompilationError::ModuleCouldNotGetName("msg".into()),
+            CompilationError::ModuleRuntimeError("msg".into()),
+            CompilationError::ModuleFailedAPICheck("msg".into()),
+            CompilationError::ModuleCompilationErrorUnsendable("msg".into()),
+            CompilationError::SerializationError(json_error()),
+            CompilationError::DeserializationError(json_error()),
+            CompilationError::WebAPIDisabled,
+            CompilationError::Custom("msg".into()),
+            CompilationError::ContinuationCoercion("msg".into()),
+            CompilationError::UnknownKey {
+                alias: "alice".into(),
+                path: EffectPath::from(PathFragment::Root),
+            },
+            CompilationError::Json(json_error()),
+            CompilationError::Unsupported {
+                feature: "a script too large for P2SH".into(),
+                context: ScriptContext::Segwitv0,
+            },
+            CompilationError::DuplicateTemplate {
+                hash: bitcoin::hashes::Hash::hash(&[]),
+                paths: vec![],
+            },
+            CompilationError::AddressError(bitcoin::util::address::Error::EmptyBech32Payload),
+            CompilationError::GuardConflict {
+                path: EffectPath::from(PathFragment::Root),
+                detail: "detail".into(),
+            },
+            CompilationError::UnreachableBranch,
+            CompilationError::UnspendableBranch {
+                path: EffectPath::from(PathFragment::Root),
+            },
+            CompilationError::UnknownTemplatePath {
+                hash: bitcoin::hashes::Hash::hash(&[]),
+            },
+            CompilationError::InvalidArgument {
+                field: "amount".into(),
+                reason: "reason".into(),
+                path: EffectPath::from(PathFragment::Root),
+            },
+            CompilationError::GuardFailed {
+                path: EffectPath::from(PathFragment::Root),
+                error: Box::new(CompilationError::TerminateCompilation),
+            },
+            CompilationError::CyclicDependency { cycle: vec![] },
+            CompilationError::OutputTooLarge { bytes: 2, max: 1 },
+            CompilationError::IntParse("x".parse::<u8>().unwrap_err()),
+            CompilationError::HexParse(HexParseError::Hex(hex::FromHexError::OddLength)),
+            CompilationError::ScriptTooLarge {
+                bytes: 2,
+                limit: 1,
+                path: EffectPath::from(PathFragment::Root),
+            },
+            CompilationError::NonStandardTimelock {
+                path: EffectPath::from(PathFragment::Root),
+                detail: "".into(),
+            },
+            CompilationError::TransactionTooHeavy {
+                weight: 2,
+                limit: 1,
+                path: EffectPath::from(PathFragment::Root),
+            },
+            CompilationError::BranchIndexOutOfRange {
+                requested: 5,
+                available: 3,
+                path: EffectPath::from(PathFragment::Root),
+            },
+            CompilationError::SchemaValidationFailed { errors: vec![] },
+            CompilationError::Multiple(vec![]),
+            CompilationError::EmulatorRequired,
+            CompilationError::TerminateCompilation.in_context(EffectPath::empty()),
+        ]
+    }
+
+    #[test]
+    fn test_amount_error_kinds() {
+        use bitcoin::util::amount::{Denomination, ParseAmountError};
+        use bitcoin::Amount;
+        let cases = [
+            ("-1", ParseAmountError::Negative),
+            ("0.000000001", ParseAmountError::TooPrecise),
+            ("1x", ParseAmountError::InvalidCharacter('x')),
+            ("", ParseAmountError::InvalidFormat),
+            ("100000000000000000000", ParseAmountError::TooBig),
+        ];
+        for (s, kind) in cases {
+            let e: CompilationError = Amount::from_str_in(s, Denomination::Bitcoin)
+                .unwrap_err()
+                .into();
+            assert_eq!(e.amount_error(), Some(&kind));
+            assert_eq!(
+                e.source()
+                    .and_then(|s| s.downcast_ref::<ParseAmountError>()),
+                Some(&kind)
+            );
+        }
+        assert!(CompilationError::OutOfFunds {
+            available: Amount::from_sat(0),
+            required: Amount::from_sat(1),
+        }
+        .amount_error()
+        .is_none());
+    }
+
+    #[test]
+    fn test_hex_parse() {
+        use bitcoin::hashes::{sha256, Hash};
+        use std::str::FromStr;
+        fn preimage(s: &str) -> Result<sha256::Hash, CompilationError> {
+            Ok(sha256::Hash::from_slice(&hex::decode(s)?)?)
+        }
+        let e = preimage("not hex").unwrap_err();
+        assert!(matches!(
+            e,
+            CompilationError::HexParse(HexParseError::Hex(_))
+        ));
+        assert!(e
+            .source()
+            .and_then(|s| s.downcast_ref::<hex::FromHexError>())
+            .is_some());
+        assert!(matches!(
+            preimage("abcd").unwrap_err(),
+            CompilationError::HexParse(HexParseError::Length(_))
+        ));
+        let from_str =
+            || -> Result<sha256::Hash, CompilationError> { Ok(sha256::Hash::from_str("abc")?) };
+        assert!(matches!(
+            from_str().unwrap_err(),
+            CompilationError::HexParse(HexParseError::Hash(_))
+        ));
+        assert!(preimage(&"ab".repeat(32)).is_ok());
+    }
+
+    #[cfg(feature = "anyhow")]
+    #[test]
+    fn test_context_chain() {
+        use std::convert::TryFrom;
+        let e = CompilationError::EmptyPolicy
+            .in_context(EffectPath::try_from("@action/pay").unwrap())
+            .in_context(EffectPath::try_from("@root").unwrap())
+            .context_chain();
+        assert_eq!(e.to_string(), "at @root");
+        assert_eq!(format!("{:#}", e), "at @root: at @action/pay: EmptyPolicy");
+        assert!(matches!(
+            e.downcast_ref::<CompilationError>(),
+            Some(CompilationError::EmptyPolicy)
+        ));
+        // the underlying error's own sources follow it
+        let e = CompilationError::from("x".parse::<u8>().unwrap_err()).context_chain();
+        assert!(e.chain().any(|s| s.is::<std::num::ParseIntError>()));
+        assert_eq!(
+            e.to_string(),
+            "could not parse integer: invalid digit found in string"
+        );
+    }
+
+    #[test]
+    fn test_log_fields() {
+        use std::convert::TryFrom;
+        let e = CompilationError::OutOfFunds {
+            available: bitcoin::Amount::from_sat(1_000),
+            required: bitcoin::Amount::from_sat(2_500),
+        }
+        .in_context(EffectPath::try_from("@action/pay").unwrap())
+        .in_context(EffectPath::try_from("@root").unwrap());
+        assert_eq!(
+            e.log_fields(),
+            vec![
+                ("code", "out of funds".to_string()),
+                ("path", "@root/@action/pay".to_string()),
+                ("available_sats", "1000".to_string()),
+                ("required_sats", "2500".to_string()),
+                (
+                    "message",
+                    "out of funds: required 0.00002500 BTC but only 0.00001000 BTC available"
+                        .to_string()
+                ),
+            ]
+        );
+        assert_eq!(
+            CompilationError::EmptyPolicy.log_fields(),
+            vec![
+                ("code", "policy is empty".to_string()),
+                ("message", CompilationError::EmptyPolicy.to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_amount_display_unit() {
+        let e = CompilationError::OutOfFunds {
+            available: bitcoin::Amount::from_sat(1_000),
+            required: bitcoin::Amount::from_sat(150_000_000),
+        };
+        assert_eq!(
+            e.to_string(),
+            "out of funds: required 1.50000000 BTC but only 0.00001000 BTC available"
+        );
+        assert_eq!(
+            e.display_in(Denomination::Satoshi).to_string(),
+            "out of funds: required 150000000 satoshi but only 1000 satoshi available"
+        );
+        let e = CompilationError::InContext {
+            path: EffectPath::try_from("@root").unwrap(),
+            error: Box::new(e),
+        };
+        assert_eq!(
+            e.display_in(Denomination::Satoshi).to_string(),
+            "at @root: out of funds: required 150000000 satoshi but only 1000 satoshi available"
+        );
+    }
+
+    #[test]
+    fn test_send_sync_across_await() {
+        use std::future::Future;
+        use std::sync::Arc;
+        use std::task::{Context, Poll, Wake, Waker};
+        struct Noop;
+        impl Wake for Noop {
+            fn wake(self: Arc<Self>) {}
+        }
+        /// yields once, so anything live across it must be stored in the future
+        struct YieldOnce(bool);
+        impl Future for YieldOnce {
+            type Output = ();
+            fn poll(mut self: std::pin::Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+                if self.0 {
+                    Poll::Ready(())
+                } else {
+                    self.0 = true;
+                    cx.waker().wake_by_ref();
+                    Poll::Pending
+                }
+            }
+        }
+        fn assert_send<F: Future + Send>(f: F) -> F {
+            f
+        }
+        let fut = assert_send(async {
+            let e = CompilationError::custom(json_error());
+            YieldOnce(false).await;
+            let boxed: Box<dyn Error + Send + Sync> = e.into();
+            boxed
+        });
+        let waker = Waker::from(Arc::new(Noop));
+        let mut cx = Context::from_waker(&waker);
+        let mut fut = Box::pin(fut);
+        let boxed = loop {
+            if let Poll::Ready(b) = fut.as_mut().poll(&mut cx) {
+                break b;
+            }
+        };
+        let e = boxed.downcast_ref::<CompilationError>().unwrap();
+        assert!(matches!(e, CompilationError::Custom(_)));
+        assert_eq!(boxed.to_string(), e.to_string());
+    }
+
+    #[test]
+    fn test_flatten_in_context() {
+        use std::convert::TryFrom;
+        let at = |s: &str| EffectPath::try_from(s).unwrap();
+        let e = CompilationError::TerminateWith("boom".into())
+            .in_context(at("@next/#2"))
+            .in_context(at("pay"))
+            .in_context(at("@root/@action"));
+        assert_eq!(
+            e.to_string(),
+            "at @root/@action: at pay: at @next/#2: TerminateWith(\"boom\")"
+        );
+        let (path, inner) = e.flatten();
+        assert_eq!(String::from(path), "@root/@action/pay/@next/#2");
+        assert!(matches!(inner, CompilationError::TerminateWith(m) if m == "boom"));
+
+        let (path, inner) = CompilationError::UnreachableBranch.flatten();
+        assert_eq!(path, EffectPath::empty());
+        assert!(matches!(inner, CompilationError::UnreachableBranch));
+
+        // errors which say where they occurred are not located again
+        let located = [
+            CompilationError::TransactionTooHeavy {
+                weight: 500_000,
+                limit: 400_000,
+                path: at("@root/#0"),
+            },
+            CompilationError::NonStandardTimelock {
+                path: at("@root/#0"),
+                detail: "lock time overflow".into(),
+            },
+            CompilationError::DuplicateTemplate {
+                hash: bitcoin::hashes::Hash::hash(b"tx"),
+                paths: vec![at("@root/#0"), at("@root/#1")],
+            },
+        ];
+        for e in located {
+            let e = e.at(&at("@root/@action"));
+            assert!(!matches!(e, CompilationError::InContext { .. }), "{:?}", e);
+        }
+    }
+
+    #[cfg(feature = "grpc")]
+    #[test]
+    fn test_grpc_code() {
+        use bitcoin::Amount;
+        let oof = || CompilationError::OutOfFunds {
+            available: Amount::from_sat(1),
+            required: Amount::from_sat(2),
+        };
+        assert_eq!(oof().grpc_code(), GrpcCode::FailedPrecondition);
+        assert_eq!(
+            oof()
+                .in_context(EffectPath::from(PathFragment::Root))
+                .grpc_code(),
+            GrpcCode::FailedPrecondition
+        );
+        assert_eq!(
+            CompilationError::IntParse("x".parse::<u8>().unwrap_err()).grpc_code(),
+            GrpcCode::InvalidArgument
+        );
+        assert_eq!(
+            CompilationError::MissingTemplates.grpc_code(),
+            GrpcCode::Internal
+        );
+        assert_eq!(GrpcCode::FailedPrecondition as i32, 9);
+    }
+
+    #[test]
+    fn test_multiple_errors() {
+        use bitcoin::Amount;
+        let mut errors = MultipleErrors::new();
+        assert!(errors.is_empty());
+        for required in [10, 20, 30] {
+            errors.push(CompilationError::OutOfFunds {
+                available: Amount::from_sat(5),
+                required: Amount::from_sat(required),
+            });
+        }
+        assert_eq!(errors.check("7".parse::<u8>()), Some(7));
+        assert_eq!(errors.check("x".parse::<u8>()), None);
+        let e = errors.finish().unwrap_err();
+        match &e {
+            CompilationError::Multiple(v) => assert_eq!(v.len(), 4),
+            e => panic!("expected Multiple, got {:?}", e),
+        }
+        assert_eq!(
+            e.display_in(Denomination::Satoshi).to_string(),
+            "4 errors:\n  \
+             1. out of funds: required 10 satoshi but only 5 satoshi available\n  \
+             2. out of funds: required 20 satoshi but only 5 satoshi available\n  \
+             3. out of funds: required 30 satoshi but only 5 satoshi available\n  \
+             4. could not parse integer: invalid digit found in string"
+        );
+
+        assert!(MultipleErrors::new().finish().is_ok());
+        let mut one = MultipleErrors::new();
+        one.push(CompilationError::UnreachableBranch);
+        assert!(matches!(
+            one.finish(),
+            Err(CompilationError::UnreachableBranch)
+        ));
+    }
+
+    #[test]
+    fn test_int_parse_error() {
+        fn quantity(s: &str) -> Result<u64, CompilationError> {
+            Ok(s.parse()?)
+        }
+        assert_eq!(quantity("12").unwrap(), 12);
+        let e = quantity("twelve").unwrap_err();
+        assert!(matches!(e, CompilationError::IntParse(_)));
+        assert!(e
+            .source()
+            .and_then(|s| s.downcast_ref::<std::num::ParseIntError>())
+            .is_some());
+    }
+
+    #[test]
+    fn test_json_error() {
+        #[derive(serde::Deserialize)]
+        struct Args {
+            #[allow(dead_code)]
+            amount: u64,
+        }
+        fn parse(s: &str) -> Result<Args, CompilationError> {
+            Ok(serde_json::from_str(s)?)
+        }
+        let e = parse("{\"amount\": \"ten\"}").err().unwrap();
+        assert!(matches!(e, CompilationError::Json(_)));
+        assert!(e
+            .source()
+            .and_then(|s| s.downcast_ref::<serde_json::Error>())
+            .is_some());
+    }
+
+    #[test]
+    fn test_address_error() {
+        use std::str::FromStr;
+        fn payout(arg: &str) -> Result<bitcoin::Address, CompilationError> {
+            Ok(bitcoin::Address::from_str(arg)?)
+        }
+        let e = payout("bcrt1notanaddress").unwrap_err();
+        assert!(matches!(e, CompilationError::AddressError(_)));
+        assert!(e
+            .source()
+            .and_then(|s| s.downcast_ref::<bitcoin::util::address::Error>())
+            .is_some());
+    }
+
+    #[test]
+    fn test_display_messages() {
+        let at = |s: &str| EffectPath::try_from(s).unwrap();
+        let e = CompilationError::CyclicDependency {
+            cycle: vec![at("@root"), at("@root/@action/loop")],
+        };
+        assert_eq!(
+            e.to_string(),
+            "cyclic dependency: @root -> @root/@action/loop"
+        );
+        let e = CompilationError::UnknownKey {
+            alias: "alice".into(),
+            path: at("@root/@guard/signed"),
+        };
+        assert_eq!(
+            e.to_string(),
+            "unknown key alias `alice` at @root/@guard/signed"
+        );
+        for e in all_variants() {
+            let has_message = matches!(
+                e,
+                CompilationError::UnknownKey { .. }
+                    | CompilationError::Unsupported { .. }
+                    | CompilationError::DuplicateTemplate { .. }
+                    | CompilationError::GuardConflict { .. }
+                    | CompilationError::CyclicDependency { .. }
+                    | CompilationError::OutputTooLarge { .. }
+                    | CompilationError::FrozenContext
+                    | CompilationError::EmulatorRequired
+                    | CompilationError::IntParse(_)
+                    | CompilationError::Json(_)
+                    | CompilationError::AddressError(_)
+            );
+            if has_message {
+                assert_ne!(e.to_string(), format!("{:?}", e));
+            }
+        }
+    }
+
+    #[test]
+    fn test_static_message_non_empty() {
+        for e in all_variants() {
+            assert!(!e.static_message().is_empty(), "{:?}", e);
+        }
     }
 }
@@ -0,0 +1,90 @@
+// Copyright Judica, Inc 2022
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+//  License, v. 2.0. If a copy of the MPL was not distributed with this
+//  file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Pluggable resolution of key aliases for guards
+use bitcoin::XOnlyPublicKey;
+use std::collections::BTreeMap;
+
+/// A `KeyResolver` maps the aliases a policy is written over to concrete
+/// keys, see [`crate::contract::Context::resolve_policy`]. A map is the
+/// simplest resolver, but e.g. an HD wallet or remote KMS can be provided
+/// via [`crate::contract::Context::with_key_resolver`].
+pub trait KeyResolver: Sync + Send {
+    /// the key for `alias`, or `None` if it is not known
+    fn resolve(&self, alias: &str) -> Option<XOnlyPublicKey>;
+}
+
+impl KeyResolver for BTreeMap<String, XOnlyPublicKey> {
+    fn resolve(&self, alias: &str) -> Option<XOnlyPublicKey> {
+        self.get(alias).copied()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate as sapio;
+    use crate::contract::test_helpers::{ctx, key};
+    use crate::contract::{Compilable, CompilationError, Contract};
+    use crate::*;
+    use bitcoin::util::amount::Amount;
+    use miniscript::policy::Concrete;
+    use sapio_base::Clause;
+    use std::str::FromStr;
+    use std::sync::Arc;
+
+    /// derives the key for aliases of the form `idx/N`
+    struct Indexed;
+    impl KeyResolver for Indexed {
+        fn resolve(&self, alias: &str) -> Option<XOnlyPublicKey> {
+            let i: u8 = alias.strip_prefix("idx/")?.parse().ok()?;
+            Some(key(i.checked_add(1)?))
+        }
+    }
+
+    struct Vault {
+        policy: Concrete<String>,
+    }
+    impl Vault {
+        #[guard]
+        fn owners(self, ctx: sapio::Context) -> Result<Clause, CompilationError> {
+            ctx.resolve_policy(&self.policy)
+        }
+        #[then(guarded_by = "[Self::owners]")]
+        fn sweep(self, ctx: sapio::Context) {
+            ctx.template()
+                .add_output(Amount::from_sat(1000), &key(9), None)?
+                .into()
+        }
+    }
+    impl Contract for Vault {
+        declare! {then, Self::sweep}
+        declare! {non updatable}
+    }
+
+    #[test]
+    fn test_key_resolver() {
+        let vault = |p: &str| Vault {
+            policy: Concrete::from_str(p).unwrap(),
+        };
+        let c = || ctx(10_000).with_key_resolver(Arc::new(Indexed));
+        assert_eq!(
+            c().resolve_policy(&Concrete::from_str("pk(idx/0)").unwrap())
+                .unwrap(),
+            Clause::Key(key(1))
+        );
+        assert!(vault("and(pk(idx/0),pk(idx/1))").compile(c()).is_ok());
+        match vault("pk(alice)").compile(c()) {
+            Err(CompilationError::GuardFailed { error, .. }) => assert!(matches!(
+                *error,
+                CompilationError::UnknownKey { ref alias, .. } if alias == "alice"
+            )),
+            r => panic!("expected UnknownKey, got {:?}", r.err()),
+        }
+        // without a resolver no alias is known
+        assert!(vault("pk(idx/0)").compile(ctx(10_000)).is_err());
+    }
+}
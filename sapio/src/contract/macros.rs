@@ -200,7 +200,7 @@ macro_rules! decl_guard {
         $name:ident} => {
             $crate::contract::macros::paste!{
                 $(#[$meta])*
-                fn [<guard_ $name>](&self, _ctx:$crate::contract::Context) -> $crate::sapio_base::Clause {
+                fn [<guard_ $name>](&self, _ctx:$crate::contract::Context) -> Result<$crate::sapio_base::Clause, $crate::contract::CompilationError> {
                     unimplemented!();
                 }
                 $(#[$meta])*
@@ -230,3 +230,15 @@ macro_rules! decl_compile_if {
             }
      };
 }
+
+/// Log a formatted message through [`crate::contract::Context::log`], so it
+/// is prefixed with the context's current path.
+/// ```ignore
+/// compile_log!(ctx, "paying {} to {}", amt, who);
+/// ```
+#[macro_export]
+macro_rules! compile_log {
+    ($ctx:expr, $($arg:tt)+) => {
+        $ctx.log(format_args!($($arg)+))
+    };
+}
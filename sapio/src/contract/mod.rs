@@ -17,6 +17,10 @@ pub mod compiler;
 pub mod error;
 pub use error::CompilationError;
 pub mod context;
+pub mod keys;
+#[cfg(test)]
+pub(crate) mod test_helpers;
+pub mod warnings;
 use bitcoin::util::amount::Amount;
 pub use compiler::Compilable;
 pub use context::Context;
@@ -0,0 +1,97 @@
+// Copyright Judica, Inc 2022
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+//  License, v. 2.0. If a copy of the MPL was not distributed with this
+//  file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! shared fixtures for unit tests that need to compile contracts
+use super::{Context, Contract};
+use crate as sapio;
+use crate::*;
+use bitcoin::util::amount::Amount;
+use bitcoin::XOnlyPublicKey;
+use sapio_base::effects::{EditableMapEffectDB, EffectPath, MapEffectDB, PathFragment};
+use sapio_base::serialization_helpers::SArc;
+use sapio_ctv_emulator_trait::CTVAvailable;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::sync::Arc;
+
+/// a deterministic, valid key derived from a single byte
+pub(crate) fn key(b: u8) -> XOnlyPublicKey {
+    let secp = bitcoin::secp256k1::Secp256k1::new();
+    bitcoin::secp256k1::SecretKey::from_slice(&[b; 32])
+        .expect("valid secret key")
+        .x_only_public_key(&secp)
+        .0
+}
+
+/// effects setting each `(path, name, value)`
+pub(crate) fn effects_db(entries: &[(&str, &str, serde_json::Value)]) -> MapEffectDB {
+    let mut effects: BTreeMap<_, BTreeMap<_, _>> = BTreeMap::new();
+    for (path, name, value) in entries {
+        let path = SArc(Arc::new(EffectPath::try_from(*path).expect("valid path")));
+        effects
+            .entry(path)
+            .or_default()
+            .insert(SArc(Arc::new(name.to_string())), value.clone());
+    }
+    EditableMapEffectDB {
+        effects,
+        empty: Default::default(),
+        provenance: Default::default(),
+    }
+    .into()
+}
+
+/// a regtest root context with real CTV and the given effects
+pub(crate) fn ctx_with_effects(sats: u64, effects: MapEffectDB) -> Context {
+    Context::new(
+        bitcoin::Network::Regtest,
+        Amount::from_sat(sats),
+        Arc::new(CTVAvailable),
+        EffectPath::from(PathFragment::Root),
+        Arc::new(effects),
+    )
+}
+
+/// a regtest root context with real CTV and no effects
+pub(crate) fn ctx(sats: u64) -> Context {
+    ctx_with_effects(sats, Default::default())
+}
+
+/// pays 1000 sats to `to`
+pub(crate) struct Leaf {
+    pub(crate) to: XOnlyPublicKey,
+}
+impl Leaf {
+    #[then]
+    fn pay(self, ctx: sapio::Context) {
+        ctx.template()
+            .add_output(Amount::from_sat(1000), &self.to, None)?
+            .into()
+    }
+}
+impl Contract for Leaf {
+    declare! {then, Self::pay}
+    declare! {non updatable}
+}
+
+/// creates a [`Leaf`] for each of `a` and `b`, with 2000 sats each
+pub(crate) struct Split {
+    pub(crate) a: XOnlyPublicKey,
+    pub(crate) b: XOnlyPublicKey,
+}
+impl Split {
+    #[then]
+    fn divide(self, ctx: sapio::Context) {
+        ctx.template()
+            .add_output(Amount::from_sat(2000), &Leaf { to: self.a }, None)?
+            .add_output(Amount::from_sat(2000), &Leaf { to: self.b }, None)?
+            .into()
+    }
+}
+impl Contract for Split {
+    declare! {then, Self::divide}
+    declare! {non updatable}
+}
@@ -0,0 +1,76 @@
+// Copyright Judica, Inc 2022
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+//  License, v. 2.0. If a copy of the MPL was not distributed with this
+//  file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! non-fatal problems found during compilation
+use super::CompilationError;
+use sapio_base::effects::EffectPath;
+use std::sync::{Arc, Mutex};
+
+/// How seriously to treat a problem the compiler can detect
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LintLevel {
+    /// ignore it
+    Allow,
+    /// record it in the compilation's [`Warnings`] and continue
+    Warn,
+    /// fail compilation with it
+    Deny,
+}
+
+/// A problem found during compilation that did not stop it
+#[derive(Debug)]
+pub struct CompilationWarning {
+    /// where the problem was found
+    pub path: EffectPath,
+    /// what the problem was, as it would have been reported if fatal
+    pub error: CompilationError,
+}
+
+/// A shared list of [`CompilationWarning`]s, accumulated by a
+/// [`crate::contract::Context`] and every context derived from it. Take a
+/// handle with [`crate::contract::Context::warnings`] before compiling to
+/// read them afterwards.
+#[derive(Clone, Default, Debug)]
+pub struct Warnings(Arc<Mutex<Vec<CompilationWarning>>>);
+
+impl Warnings {
+    /// add a warning
+    pub fn push(&self, path: EffectPath, error: CompilationError) {
+        self.0
+            .lock()
+            .expect("warnings lock poisoned")
+            .push(CompilationWarning { path, error });
+    }
+    /// remove and return all warnings recorded so far
+    pub fn take(&self) -> Vec<CompilationWarning> {
+        std::mem::take(&mut *self.0.lock().expect("warnings lock poisoned"))
+    }
+    /// how many warnings have been recorded
+    pub fn len(&self) -> usize {
+        self.0.lock().expect("warnings lock poisoned").len()
+    }
+    /// have no warnings been recorded?
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// Report `error` at `path` according to `level`: dropped if `Allow`,
+    /// recorded if `Warn`, and returned as an error if `Deny`.
+    pub fn report(
+        &self,
+        level: LintLevel,
+        path: &EffectPath,
+        error: CompilationError,
+    ) -> Result<(), CompilationError> {
+        match level {
+            LintLevel::Allow => Ok(()),
+            LintLevel::Warn => {
+                self.push(path.clone(), error);
+                Ok(())
+            }
+            LintLevel::Deny => Err(error),
+        }
+    }
+}
@@ -13,6 +13,7 @@ use bitcoin::util::amount::Amount;
 use bitcoin::VarInt;
 use bitcoin::Witness;
 use sapio_base::effects::PathFragment;
+use sapio_base::serialization_helpers::SArc;
 use sapio_base::simp::SIMPAttachableAt;
 use sapio_base::simp::TemplateInputLT;
 use sapio_base::simp::TemplateLT;
@@ -74,6 +75,18 @@ impl Builder {
         Ok(c)
     }
 
+    /// reduce the amount available in the builder's context by the fee the
+    /// context's [`super::fees::FeeEstimator`] quotes for this template's
+    /// estimated size, and add it to the fees. A context without an
+    /// estimator pays no fee.
+    pub fn add_estimated_fees(self) -> Result<Self, CompilationError> {
+        let fee = match self.ctx.fee_estimator() {
+            Some(estimator) => estimator.fee_for(self.estimate_tx_size()),
+            None => return Ok(self),
+        };
+        self.add_fees(fee)
+    }
+
     /// Creates a new Output, forcing the compilation of the compilable object and defaulting
     /// metadata if not provided to blank.
     pub fn add_output(
@@ -325,6 +338,8 @@ impl From<Builder> for Template {
             min_feerate_sats_vbyte: t.min_feerate,
             tx,
             metadata_map_s2s: t.metadata,
+            path: Some(SArc(t.ctx.path().clone())),
+            timelock_guards: vec![],
         }
     }
 }
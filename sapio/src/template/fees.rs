@@ -0,0 +1,86 @@
+// Copyright Judica, Inc 2022
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+//  License, v. 2.0. If a copy of the MPL was not distributed with this
+//  file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Pluggable feerate estimation for templates
+use bitcoin::util::amount::Amount;
+
+/// A `FeeEstimator` is consulted by [`crate::template::Builder::add_estimated_fees`]
+/// to price a template from its estimated size. The default implementation
+/// is a static rate, but e.g. a mempool-backed estimator can be provided via
+/// [`crate::contract::Context::with_fee_estimator`].
+pub trait FeeEstimator: Sync + Send {
+    /// the feerate to pay, in sats per vbyte
+    fn feerate(&self) -> Amount;
+    /// the fee to pay for a transaction of `vbytes`, saturating at
+    /// [`Amount::max_value`] (which no context can afford) on overflow
+    fn fee_for(&self, vbytes: u64) -> Amount {
+        self.feerate()
+            .checked_mul(vbytes)
+            .unwrap_or_else(Amount::max_value)
+    }
+}
+
+/// A `FeeEstimator` which always returns the same feerate (sats per vbyte)
+#[derive(Clone, Copy, Debug)]
+pub struct StaticFeeRate(pub Amount);
+
+impl FeeEstimator for StaticFeeRate {
+    fn feerate(&self) -> Amount {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::contract::test_helpers::{ctx, key};
+    use crate::contract::CompilationError;
+    use crate::template::Template;
+    use std::sync::Arc;
+
+    struct Stub;
+    impl FeeEstimator for Stub {
+        fn feerate(&self) -> Amount {
+            Amount::from_sat(7)
+        }
+    }
+
+    #[test]
+    fn test_estimated_fee() -> Result<(), CompilationError> {
+        let builder = ctx(100_000)
+            .with_fee_estimator(Arc::new(Stub))
+            .template()
+            .add_output(Amount::from_sat(1000), &key(1), None)?;
+        let size = builder.estimate_tx_size();
+        let t: Template = builder.add_estimated_fees()?.into();
+        assert_eq!(t.max - t.total_amount(), Amount::from_sat(7 * size));
+        Ok(())
+    }
+
+    #[test]
+    fn test_estimated_fee_out_of_funds() -> Result<(), CompilationError> {
+        let r = ctx(1_100)
+            .with_fee_estimator(Arc::new(StaticFeeRate(Amount::from_sat(1_000))))
+            .template()
+            .add_output(Amount::from_sat(1000), &key(1), None)?
+            .add_estimated_fees();
+        assert!(matches!(r, Err(CompilationError::OutOfFunds { .. })));
+        Ok(())
+    }
+
+    #[test]
+    fn test_fee_overflow_saturates() -> Result<(), CompilationError> {
+        let rate = StaticFeeRate(Amount::max_value());
+        assert_eq!(rate.fee_for(2), Amount::max_value());
+        let r = ctx(100_000)
+            .with_fee_estimator(Arc::new(rate))
+            .template()
+            .add_output(Amount::from_sat(1000), &key(1), None)?
+            .add_estimated_fees();
+        assert!(matches!(r, Err(CompilationError::OutOfFunds { .. })));
+        Ok(())
+    }
+}
@@ -8,6 +8,19 @@
 use super::*;
 use sapio_base::simp::SIMPError;
 use serde::{Deserialize, Serialize};
+/// What a wallet needs to know to fund one input of a [`Template`], see
+/// [`Template::inputs_summary`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InputSummary {
+    /// the coin this input spends, or `None` if it is a placeholder to be
+    /// filled in when the template is bound to a coin
+    pub prevout: Option<bitcoin::OutPoint>,
+    /// the least value the input must carry, if it can be determined
+    pub amount: Option<Amount>,
+    /// the input's nSequence
+    pub sequence: u32,
+}
+
 /// Metadata for outputs, arbitrary KV set.
 #[derive(Serialize, Deserialize, Clone, JsonSchema, Debug, PartialEq, Eq)]
 pub struct InputMetadata {
@@ -72,3 +85,30 @@ impl<const N: usize> From<[(&str, serde_json::Value); N]> for InputMetadata {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::contract::test_helpers::{ctx, key};
+    use crate::contract::CompilationError;
+    use sapio_base::timelocks::RelHeight;
+
+    #[test]
+    fn test_inputs_summary() -> Result<(), CompilationError> {
+        let t: Template = ctx(10_000)
+            .template()
+            .add_output(Amount::from_sat(9_000), &key(1), None)?
+            .add_fees(Amount::from_sat(1_000))?
+            .set_sequence(0, RelHeight::from(10).into())?
+            .into();
+        assert_eq!(
+            t.inputs_summary(),
+            vec![InputSummary {
+                prevout: None,
+                amount: Some(Amount::from_sat(10_000)),
+                sequence: 10,
+            }]
+        );
+        Ok(())
+    }
+}
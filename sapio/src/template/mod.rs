@@ -8,6 +8,8 @@
 use crate::contract::error::CompilationError;
 use bitcoin::hashes::sha256;
 use bitcoin::util::amount::Amount;
+use sapio_base::effects::EffectPath;
+use sapio_base::serialization_helpers::SArc;
 use sapio_base::simp::SIMPAttachableAt;
 use sapio_base::simp::SIMPError;
 use sapio_base::simp::TemplateInputLT;
@@ -21,6 +23,7 @@ pub mod output;
 pub use output::{Output, OutputMeta};
 pub mod builder;
 pub use builder::Builder;
+pub mod fees;
 
 use self::input::InputMetadata;
 /// Metadata Struct which has some standard defined fields
@@ -152,8 +155,57 @@ pub struct Template {
     /// sapio specific information about all the inputs in the `tx`.
     #[serde(rename = "inputs_info")]
     pub inputs: Vec<InputMetadata>,
+    /// the context path at which this template was built, if known
+    #[serde(rename = "template_path", default)]
+    #[cfg_attr(
+        feature = "serde-introspection",
+        serde(skip_serializing_if = "Option::is_none")
+    )]
+    #[cfg_attr(not(feature = "serde-introspection"), serde(skip_serializing))]
+    pub path: Option<SArc<EffectPath>>,
+    /// the guards with timelocks of the branch that created this template,
+    /// with the path each was built at, see [`Template::timelock_explanation`]
+    #[serde(rename = "timelock_guards", default)]
+    #[cfg_attr(
+        feature = "serde-introspection",
+        serde(skip_serializing_if = "Vec::is_empty")
+    )]
+    #[cfg_attr(not(feature = "serde-introspection"), serde(skip_serializing))]
+    pub timelock_guards: Vec<(SArc<EffectPath>, Clause)>,
+}
+
+/// does `c` require a timelock of the kind of `n` (an nSequence if
+/// `relative`, else an nLockTime) that `n` satisfies?
+fn requires_timelock(c: &Clause, relative: bool, n: u32) -> bool {
+    match c {
+        Clause::Older(m) if relative => {
+            let value = |x: u32| x & 0xffff;
+            value(*m) != 0 && (m & (1 << 22)) == (n & (1 << 22)) && value(*m) <= value(n)
+        }
+        Clause::After(m) if !relative => {
+            *m != 0 && (*m < LOCKTIME_THRESHOLD) == (n < LOCKTIME_THRESHOLD) && *m <= n
+        }
+        Clause::And(v) | Clause::Threshold(_, v) => {
+            v.iter().any(|c| requires_timelock(c, relative, n))
+        }
+        Clause::Or(v) => v.iter().any(|(_, c)| requires_timelock(c, relative, n)),
+        _ => false,
+    }
 }
 
+/// does `c` contain any `older` or `after`?
+pub(crate) fn has_timelock(c: &Clause) -> bool {
+    match c {
+        Clause::Older(_) | Clause::After(_) => true,
+        Clause::And(v) | Clause::Threshold(_, v) => v.iter().any(has_timelock),
+        Clause::Or(v) => v.iter().any(|(_, c)| has_timelock(c)),
+        _ => false,
+    }
+}
+
+/// nLockTime values below this are block heights, the rest unix times
+const LOCKTIME_THRESHOLD: u32 = 500_000_000;
+
 impl Template {
     /// Get the cached template hash of this Template
     pub fn hash(&self) -> sha256::Hash {
@@ -169,4 +221,130 @@ impl Template {
             .map(|o| o.amount)
             .fold(Amount::from_sat(0), |b, a| b + a)
     }
+
+    /// A line for each timelock this template's transaction carries (the
+    /// relative lock of each input, and the absolute lock), naming the guards
+    /// that require it, e.g. `input 0: CSV 144 blocks, from guard at <path>`.
+    /// Guards are those of the branch that created the template (see
+    /// [`Template::timelock_guards`]) and the template's own
+    /// [`Template::guards`]; a lock none of them requires is reported too.
+    pub fn timelock_explanation(&self) -> Vec<String> {
+        let show = |p: Option<&SArc<EffectPath>>| {
+            p.map_or("<unknown path>".into(), |p| {
+                String::from(p.0.as_ref().clone())
+            })
+        };
+        let guards: Vec<(String, &Clause)> = self
+            .timelock_guards
+            .iter()
+            .map(|(p, c)| (format!("guard at {}", show(Some(p))), c))
+            .chain(
+                self.guards
+                    .iter()
+                    .map(|c| (format!("template guard at {}", show(self.path.as_ref())), c)),
+            )
+            .collect();
+        let explain = |what: String, relative: bool, n: u32| {
+            let from: Vec<&str> = guards
+                .iter()
+                .filter(|(_, c)| requires_timelock(c, relative, n))
+                .map(|(g, _)| g.as_str())
+                .collect();
+            if from.is_empty() {
+                format!("{}, not required by any guard", what)
+            } else {
+                format!("{}, from {}", what, from.join(", "))
+            }
+        };
+        let mut lines = vec![];
+        for (i, input) in self.tx.input.iter().enumerate() {
+            let n = input.sequence;
+            let value = n & 0xffff;
+            if n & (1 << 31) != 0 || value == 0 {
+                continue;
+            }
+            let lock = if n & (1 << 22) != 0 {
+                format!("CSV {} seconds", value * 512)
+            } else {
+                format!("CSV {} blocks", value)
+            };
+            lines.push(explain(format!("input {}: {}", i, lock), true, n));
+        }
+        let n = self.tx.lock_time;
+        if n != 0 {
+            let lock = if n < LOCKTIME_THRESHOLD {
+                format!("CLTV height {}", n)
+            } else {
+                format!("CLTV time {}", n)
+            };
+            lines.push(explain(format!("nLockTime: {}", lock), false, n));
+        }
+        lines
+    }
+
+    /// The inputs this template spends, in order. Templates are built
+    /// before the coins funding them are known, so every prevout is a
+    /// placeholder until the transaction is bound. The expected value of an
+    /// input is only known when there is exactly one, in which case it must
+    /// carry the template's full amount (its outputs plus any fees).
+    pub fn inputs_summary(&self) -> Vec<input::InputSummary> {
+        let only_input = self.tx.input.len() == 1;
+        self.tx
+            .input
+            .iter()
+            .map(|i| input::InputSummary {
+                prevout: Some(i.previous_output).filter(|o| !o.is_null()),
+                amount: Some(self.max).filter(|_| only_input),
+                sequence: i.sequence,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate as sapio;
+    use crate::contract::test_helpers::{ctx, key};
+    use crate::contract::{Compilable, Contract};
+    use crate::*;
+    use bitcoin::util::amount::Amount;
+    use sapio_base::timelocks::{AbsHeight, RelHeight};
+    use std::convert::TryFrom;
+
+    struct Cooldown;
+    impl Cooldown {
+        #[guard]
+        fn signed(self, _ctx: sapio::Context) {
+            sapio_base::Clause::Key(key(2))
+        }
+        #[guard]
+        fn waited(self, _ctx: sapio::Context) {
+            RelHeight::from(144).into()
+        }
+        #[then(guarded_by = "[Self::signed, Self::waited]")]
+        fn sweep(self, ctx: sapio::Context) {
+            ctx.template()
+                .set_sequence(0, RelHeight::from(144).into())?
+                .set_lock_time(AbsHeight::try_from(800_000).unwrap().into())?
+                .add_output(Amount::from_sat(1000), &key(1), None)?
+                .into()
+        }
+    }
+    impl Contract for Cooldown {
+        declare! {then, Self::sweep}
+        declare! {non updatable}
+    }
+
+    #[test]
+    fn test_timelock_explanation() {
+        let obj = Cooldown.compile(ctx(10_000)).unwrap();
+        let t = obj.ctv_to_tx.values().next().unwrap();
+        assert_eq!(
+            t.timelock_explanation(),
+            vec![
+                "input 0: CSV 144 blocks, from guard at @root/@action/sweep/@guard/#1".to_string(),
+                "nLockTime: CLTV height 800000, not required by any guard".to_string(),
+            ]
+        );
+    }
 }
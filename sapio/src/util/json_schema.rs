@@ -0,0 +1,208 @@
+// Copyright Judica, Inc 2022
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+//  License, v. 2.0. If a copy of the MPL was not distributed with this
+//  file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! checking JSON values against the schemas `schemars` generates
+use serde_json::Value;
+
+/// Check `v` against `schema`, returning a message for every violation
+/// found, e.g. `field amount must be at least 0`. An empty result means `v`
+/// is valid.
+///
+/// This covers the keywords `schemars` emits for plain Rust types: `type`,
+/// `enum`, `const`, `minimum`/`maximum`, `properties`, `required`,
+/// `additionalProperties`, `items`, `allOf`/`anyOf`/`oneOf`, and `$ref`s
+/// into the root's `definitions`. Other keywords are not checked.
+pub fn validate(schema: &Value, v: &Value) -> Vec<String> {
+    let mut errors = vec![];
+    check(schema, schema, v, "arguments", &mut errors);
+    errors
+}
+
+fn type_name(t: &str) -> &str {
+    match t {
+        "null" => "null",
+        "boolean" => "a boolean",
+        "object" => "an object",
+        "array" => "an array",
+        "number" => "a number",
+        "integer" => "an integer",
+        "string" => "a string",
+        t => t,
+    }
+}
+
+fn has_type(t: &str, v: &Value) -> bool {
+    match t {
+        "null" => v.is_null(),
+        "boolean" => v.is_boolean(),
+        "object" => v.is_object(),
+        "array" => v.is_array(),
+        "number" => v.is_number(),
+        "integer" => v.is_i64() || v.is_u64(),
+        "string" => v.is_string(),
+        _ => true,
+    }
+}
+
+fn check(root: &Value, schema: &Value, v: &Value, field: &str, errors: &mut Vec<String>) {
+    let schema = match schema {
+        // `true` accepts anything, `false` nothing
+        Value::Bool(true) => return,
+        Value::Bool(false) => return errors.push(format!("field {} is not allowed", field)),
+        Value::Object(schema) => schema,
+        _ => return,
+    };
+    if let Some(r) = schema.get("$ref").and_then(Value::as_str) {
+        match r
+            .strip_prefix("#/definitions/")
+            .and_then(|d| root.get("definitions")?.get(d))
+        {
+            Some(def) => check(root, def, v, field, errors),
+            None => errors.push(format!("field {} refers to unknown schema {}", field, r)),
+        }
+    }
+    let types: Vec<&str> = match schema.get("type") {
+        Some(Value::String(t)) => vec![t],
+        Some(Value::Array(ts)) => ts.iter().filter_map(Value::as_str).collect(),
+        _ => vec![],
+    };
+    if !types.is_empty() && !types.iter().any(|t| has_type(t, v)) {
+        let names: Vec<&str> = types.iter().map(|t| type_name(t)).collect();
+        // the other keywords are meaningless for a value of the wrong type
+        return errors.push(format!("field {} must be {}", field, names.join(" or ")));
+    }
+    if let Some(options) = schema.get("enum").and_then(Value::as_array) {
+        if !options.contains(v) {
+            let names: Vec<String> = options.iter().map(Value::to_string).collect();
+            errors.push(format!(
+                "field {} must be one of {}",
+                field,
+                names.join(", ")
+            ));
+        }
+    }
+    if let Some(c) = schema.get("const") {
+        if c != v {
+            errors.push(format!("field {} must be {}", field, c));
+        }
+    }
+    if let Some(n) = v.as_f64() {
+        if let Some(min) = schema.get("minimum").and_then(Value::as_f64) {
+            if n < min {
+                errors.push(format!("field {} must be at least {}", field, min));
+            }
+        }
+        if let Some(max) = schema.get("maximum").and_then(Value::as_f64) {
+            if n > max {
+                errors.push(format!("field {} must be at most {}", field, max));
+            }
+        }
+    }
+    if let Some(obj) = v.as_object() {
+        let props = schema.get("properties").and_then(Value::as_object);
+        let sub = |k: &str| {
+            if field == "arguments" {
+                k.to_string()
+            } else {
+                format!("{}.{}", field, k)
+            }
+        };
+        for req in schema
+            .get("required")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_str)
+        {
+            if !obj.contains_key(req) {
+                errors.push(format!("field {} is required", sub(req)));
+            }
+        }
+        for (k, fv) in obj {
+            match props.and_then(|p| p.get(k)) {
+                Some(s) => check(root, s, fv, &sub(k), errors),
+                None => {
+                    if let Some(extra) = schema.get("additionalProperties") {
+                        check(root, extra, fv, &sub(k), errors)
+                    }
+                }
+            }
+        }
+    }
+    if let (Some(items), Some(arr)) = (schema.get("items"), v.as_array()) {
+        for (i, iv) in arr.iter().enumerate() {
+            check(root, items, iv, &format!("{}[{}]", field, i), errors);
+        }
+    }
+    for s in schema
+        .get("allOf")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+    {
+        check(root, s, v, field, errors);
+    }
+    for key in ["anyOf", "oneOf"] {
+        if let Some(options) = schema.get(key).and_then(Value::as_array) {
+            let fits = |s: &Value| {
+                let mut e = vec![];
+                check(root, s, v, field, &mut e);
+                e.is_empty()
+            };
+            if !options.iter().any(fits) {
+                errors.push(format!("field {} does not match any allowed form", field));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use schemars::JsonSchema;
+    use serde::Deserialize;
+    use serde_json::json;
+
+    #[derive(JsonSchema, Deserialize)]
+    #[allow(dead_code)]
+    enum Speed {
+        Slow,
+        Fast,
+    }
+    #[derive(JsonSchema, Deserialize)]
+    #[allow(dead_code)]
+    struct Args {
+        amount: u64,
+        note: Option<String>,
+        speed: Speed,
+        splits: Vec<u8>,
+    }
+
+    #[test]
+    fn test_validate() {
+        let schema = serde_json::to_value(schemars::schema_for!(Args)).unwrap();
+        let ok = json!({"amount": 5, "speed": "Fast", "splits": [1, 2]});
+        assert!(validate(&schema, &ok).is_empty());
+        let bad = json!({"amount": -5, "note": 3, "speed": "Warp", "splits": [1, -2]});
+        assert_eq!(
+            validate(&schema, &bad),
+            vec![
+                "field amount must be at least 0",
+                "field note must be a string or null",
+                "field speed must be one of \"Slow\", \"Fast\"",
+                "field splits[1] must be at least 0",
+            ]
+        );
+        assert_eq!(
+            validate(&schema, &json!({"speed": "Slow", "splits": []})),
+            vec!["field amount is required"]
+        );
+        assert_eq!(
+            validate(&schema, &json!(7)),
+            vec!["field arguments must be an object"]
+        );
+    }
+}
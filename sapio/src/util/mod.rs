@@ -7,3 +7,4 @@
 //! Basic functionality / structs for Sapio
 pub mod amountrange;
 pub mod extended_address;
+pub mod json_schema;
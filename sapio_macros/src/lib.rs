@@ -51,6 +51,15 @@ pub fn compile_if(args: TokenStream, input: TokenStream) -> TokenStream {
 ///     /*Clause*/
 /// }
 /// ```
+/// A guard that can fail to produce a clause declares a `Result` return
+/// type; its error is reported at the guard's path (any other declared
+/// return type, e.g. `-> Clause`, is a plain clause as above):
+/// ```ignore
+/// #[guard]
+/// fn name(self, ctx) -> Result<Clause, CompilationError> {
+///     /*Result<Clause, CompilationError>*/
+/// }
+/// ```
 #[proc_macro_attribute]
 pub fn guard(args: TokenStream, input: TokenStream) -> TokenStream {
     let args = parse_macro_input!(args as AttributeArgs);
@@ -62,6 +71,15 @@ pub fn guard(args: TokenStream, input: TokenStream) -> TokenStream {
     let name = input.sig.ident;
     let guard_name = format_ident!("guard_{}", name);
     let block = input.block;
+    let body = match input.sig.output {
+        syn::ReturnType::Default => quote! {
+            Ok((|| -> sapio::sapio_base::Clause #block)())
+        },
+        syn::ReturnType::Type(_, ty) if returns_result(&ty) => quote! { #block },
+        syn::ReturnType::Type(_, ty) => quote! {
+            Ok((|| -> #ty #block)())
+        },
+    };
     let mut ty = format_ident!("Fresh");
     let simp_gen_f = simp_at(&args).unwrap_or(TokenStream::from_str("None").unwrap().into());
     for arg in args {
@@ -73,14 +91,22 @@ pub fn guard(args: TokenStream, input: TokenStream) -> TokenStream {
         }
     }
     proc_macro::TokenStream::from(quote! {
-        fn #guard_name(&self, #context_arg) -> sapio::sapio_base::Clause
-        #block
+        fn #guard_name(&self, #context_arg) -> Result<sapio::sapio_base::Clause, sapio::contract::CompilationError>
+        { #body }
         fn  #name() -> Option<sapio::contract::actions::Guard<Self>> {
             Some(sapio::contract::actions::Guard::#ty(Self::#guard_name, #simp_gen_f))
         }
     })
 }
 
+/// is `ty` spelled as a `Result<..>` (by any path, e.g. `std::result::Result`)?
+fn returns_result(ty: &syn::Type) -> bool {
+    match ty {
+        syn::Type::Path(p) => p.path.segments.last().is_some_and(|s| s.ident == "Result"),
+        _ => false,
+    }
+}
+
 fn get_arrays(args: &Vec<NestedMeta>) -> (proc_macro2::TokenStream, proc_macro2::TokenStream) {
     let mut compile_if_array = None;
     let mut guarded_by_array = None;
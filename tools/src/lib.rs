@@ -30,7 +30,7 @@ impl TxIndex for BitcoinNodeIndex {
                 .block_on(self.client.get_raw_transaction(b, None))
                 .map(Arc::new)
                 .map_err(|e| {
-                    let b: Box<dyn std::error::Error> = Box::new(e);
+                    let b: Box<dyn std::error::Error + Send + Sync> = Box::new(e);
                     TxIndexError::RpcError(b)
                 })
         })
@@ -43,7 +43,7 @@ impl TxIndex for BitcoinNodeIndex {
                     .block_on(self.client.send_raw_transaction(&*tx))
             })
             .map_err(|e| {
-                let b: Box<dyn std::error::Error> = Box::new(e);
+                let b: Box<dyn std::error::Error + Send + Sync> = Box::new(e);
                 TxIndexError::RpcError(b)
             })
         } else {